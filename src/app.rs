@@ -1,7 +1,18 @@
 use crate::{
-    audio::{audio_model::AudioModel, song},
+    audio::{
+        arrangement::pattern_name,
+        audio_model::{AudioModel, AudioSettings},
+        audio_thread::Command,
+        midi::{self, MidiSource},
+        offline_renderer::{self, ExportSettings},
+        sampler::Sample,
+        song, songs,
+        tuning::Scale,
+        wavetable_oscillator::Wavetable,
+    },
     basics::{scene::Scene, scene_loader},
     color_utils::{self, ColorPalette},
+    gui::gui_sequencer::ImportReport,
     renderer, save_image,
     shader_utils::Effect,
 };
@@ -78,7 +89,7 @@ impl Settings {
 }
 
 impl<'a> App<'a> {
-    async fn new(window: &'a Window) -> App<'a> {
+    async fn new(window: &'a Window, audio_settings: &AudioSettings) -> App<'a> {
         let json = include_str!("../scenes/scene_03.json");
         let scene_data = scene_loader::construct_scene_from_json(json);
 
@@ -93,7 +104,7 @@ impl<'a> App<'a> {
             &scene_data,
         );
 
-        let audio_model = AudioModel::new().expect("Error creating audio model");
+        let audio_model = AudioModel::new(audio_settings).expect("Error creating audio model");
 
         Self {
             size,
@@ -144,41 +155,163 @@ impl<'a> App<'a> {
             &self.renderer.queue,
             delta_time,
             signal_peak,
-            self.audio_model.on_beat(),
+            self.audio_model.analysis(),
             Arc::new(rolling_wave),
             &self.settings.color_palette,
         );
         let _ = self.renderer.render(
             self.window,
             &self.scene,
-            &mut self.audio_model.sequencers_mut(),
+            &mut self.audio_model,
             fps,
             &mut self.settings,
             &mut self.ui_events,
         );
 
         // Process ui events
-        let sequencers = self.audio_model.sequencers_mut();
+        let sample_rate = self.audio_model.sample_rate();
+        let audio_model = &mut self.audio_model;
         for ui_event in self.ui_events.iter() {
+            let audio_engine = audio_model.audio_engine_mut();
             match ui_event {
                 UiEvent::SaveSong => {
-                    let _r = song::save_song(sequencers);
+                    let _r = song::save_song(audio_engine);
                 }
                 UiEvent::LoadSong => {
-                    song::load_song(sequencers);
+                    if let Err(e) = song::load_song(audio_engine) {
+                        eprintln!("Error loading song: {e}");
+                    }
                 }
                 UiEvent::ClearSong => {
-                    song::clear_song(sequencers);
+                    song::clear_song(audio_engine);
+                }
+                UiEvent::ExportSong => {
+                    let settings = ExportSettings {
+                        sample_rate,
+                        ..ExportSettings::default()
+                    };
+                    if let Err(e) =
+                        offline_renderer::export_engine("song.wav", audio_engine, &settings)
+                    {
+                        eprintln!("Error exporting song: {e}");
+                    }
+                }
+                UiEvent::ExportMidi => {
+                    if let Err(e) = midi::export_file("song.mid", &song::to_song(audio_engine)) {
+                        eprintln!("Error exporting midi: {e}");
+                    }
+                }
+                UiEvent::AddTrack => audio_engine.add_track(),
+                UiEvent::RemoveTrack(index) => audio_model.remove_track(*index),
+                UiEvent::Play => audio_model.send(Command::Play),
+                UiEvent::Pause => audio_model.send(Command::Pause),
+                UiEvent::Stop => audio_model.send(Command::Stop),
+                UiEvent::QueuePattern { track, pattern } => {
+                    audio_model.send(Command::QueuePattern {
+                        track: *track,
+                        pattern: *pattern,
+                    })
+                }
+                UiEvent::ImportMidi {
+                    path,
+                    source,
+                    track,
+                    pattern,
+                } => match midi::import_file(path, *source) {
+                    Ok(import) => {
+                        self.renderer.gui.settings.pattern_tools.midi_import.report =
+                            Some(ImportReport {
+                                summary: format!(
+                                    "imported {} notes into pattern {} of sequencer {}",
+                                    import.imported,
+                                    pattern_name(*pattern),
+                                    track + 1
+                                ),
+                                bpm: import.bpm,
+                                issues: import.issues.iter().map(|i| i.to_string()).collect(),
+                            });
+                        if let Some(steps) = audio_engine
+                            .sequencers
+                            .get_mut(*track)
+                            .and_then(|sequencer| sequencer.pattern_mut(*pattern))
+                        {
+                            *steps = import.steps;
+                        }
+                    }
+                    Err(e) => {
+                        self.renderer.gui.settings.pattern_tools.midi_import.report =
+                            Some(ImportReport {
+                                summary: format!("error importing {path}: {e}"),
+                                bpm: None,
+                                issues: Vec::new(),
+                            });
+                    }
+                },
+                UiEvent::SetBpm(bpm) => audio_engine.transport.set_bpm(*bpm),
+                UiEvent::RemovePattern { track, pattern } => {
+                    audio_engine.remove_pattern(*track, *pattern);
+                    audio_model.send(Command::RemovePattern {
+                        track: *track,
+                        pattern: *pattern,
+                    });
+                }
+                UiEvent::LoadPreset { preset, track } => {
+                    if let Some(notes) = songs::preset(*preset) {
+                        audio_engine.arrange_patterns(*track, songs::to_patterns(&notes));
+                    }
+                }
+                UiEvent::LoadSample { path, track } => match Sample::load(path) {
+                    Ok(sample) => {
+                        if let Some(sequencer) = audio_engine.sequencers.get_mut(*track) {
+                            sequencer.sampler.sample = Some(sample);
+                        }
+                    }
+                    Err(e) => eprintln!("Error loading sample {path}: {e}"),
+                },
+                UiEvent::LoadWavetable { path, track } => match Wavetable::load(path) {
+                    Ok(table) => {
+                        if let Some(sequencer) = audio_engine.sequencers.get_mut(*track) {
+                            sequencer.modulated_oscillator.wavetable.table = Arc::new(table);
+                        }
+                    }
+                    Err(e) => eprintln!("Error loading wavetable {path}: {e}"),
+                },
+                UiEvent::LoadScale { path } => match Scale::load(path) {
+                    Ok(scale) => audio_engine.tuning.scale = Some(Arc::new(scale)),
+                    Err(e) => eprintln!("Error loading scale {path}: {e}"),
+                },
+                UiEvent::ToggleInput => audio_model.toggle_input(),
+                UiEvent::AnalyseInput(analyse_input) => {
+                    audio_model.set_analyse_input(*analyse_input)
+                }
+                UiEvent::ToggleRecording => {
+                    if !audio_model.input_status().is_recording {
+                        audio_model.start_recording();
+                    } else if let Err(e) = audio_model.stop_recording("input.wav") {
+                        eprintln!("Error writing recording: {e}");
+                    }
+                }
+                UiEvent::SetLatency(latency_ms) => audio_model.set_latency_target(*latency_ms),
+                UiEvent::UpdateEffects => {
+                    self.renderer.post_processor.update_effects(
+                        &self.renderer.device,
+                        &self
+                            .renderer
+                            .render_texture_material
+                            .post_process_texture_view,
+                        &self.renderer.render_texture_material.render_texture_view,
+                        &self.settings.effect_to_active,
+                    );
+                    // The sound goes through the same effects in the same order
+                    let effects = self
+                        .settings
+                        .effect_to_active
+                        .iter()
+                        .filter(|(_, active)| **active)
+                        .map(|(effect, _)| *effect)
+                        .collect();
+                    audio_model.set_post_process(effects);
                 }
-                UiEvent::UpdateEffects => self.renderer.post_processor.update_effects(
-                    &self.renderer.device,
-                    &self
-                        .renderer
-                        .render_texture_material
-                        .post_process_texture_view,
-                    &self.renderer.render_texture_material.render_texture_view,
-                    &self.settings.effect_to_active,
-                ),
             }
         }
         self.ui_events.clear();
@@ -201,7 +334,7 @@ impl<'a> App<'a> {
     }
 }
 
-pub async fn start() {
+pub async fn start(audio_settings: &AudioSettings) {
     let size = Size::Physical(PhysicalSize {
         width: 1080,
         height: 1080,
@@ -209,7 +342,7 @@ pub async fn start() {
     let input = WinitInputHelper::new();
     let event_loop = EventLoop::new().expect("Error creating event loop");
     let window = create_window(size, &event_loop);
-    let app = App::new(&window).await;
+    let app = App::new(&window, audio_settings).await;
 
     let _r = run_event_loop(event_loop, app, input);
 }
@@ -315,5 +448,49 @@ pub enum UiEvent {
     SaveSong,
     LoadSong,
     ClearSong,
+    ExportSong,
+    ExportMidi,
+    AddTrack,
+    RemoveTrack(usize),
+    Play,
+    Pause,
+    Stop,
+    SetBpm(f32),
+    ImportMidi {
+        path: String,
+        source: MidiSource,
+        track: usize,
+        pattern: usize,
+    },
+    QueuePattern {
+        track: usize,
+        pattern: usize,
+    },
+    RemovePattern {
+        track: usize,
+        pattern: usize,
+    },
+    LoadPreset {
+        preset: usize,
+        track: usize,
+    },
+    LoadSample {
+        path: String,
+        track: usize,
+    },
+    LoadWavetable {
+        path: String,
+        track: usize,
+    },
+    /// Scala file for the song's tuning
+    LoadScale {
+        path: String,
+    },
+    ToggleInput,
+    /// The visuals follow the live input instead of the master output
+    AnalyseInput(bool),
+    ToggleRecording,
+    /// Milliseconds of audio to keep buffered ahead of the device
+    SetLatency(u32),
     UpdateEffects,
 }
@@ -1,10 +1,33 @@
+pub mod analyser;
+pub mod arpeggiator;
+pub mod arrangement;
+pub mod audio_backend;
 pub mod audio_clock;
+pub mod audio_engine;
 pub mod audio_model;
+pub mod audio_stats;
+pub mod audio_thread;
+pub mod effects;
 pub mod envelope;
+pub mod filter;
+pub mod fm_oscillator;
 pub mod lfo;
+pub mod live_input;
+pub mod midi;
+pub mod mixer;
 pub mod modulated_oscillator;
+pub mod modulation;
+pub mod offline_renderer;
 pub mod oscillator_type;
+pub mod pattern_generator;
+pub mod post_process;
+pub mod sampler;
 pub mod sequencer;
 pub mod song;
 pub mod songs;
+pub mod step;
+pub mod transport;
+pub mod tuning;
 pub mod vco;
+pub mod voice;
+pub mod wavetable_oscillator;
@@ -0,0 +1,252 @@
+//! Spectrum analysis of the master output for visuals.
+//!
+//! Samples are pushed as they are rendered and analysed once per video frame over the last
+//! `FFT_SIZE` of them.
+
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+use std::{collections::VecDeque, f32::consts::TAU, sync::Arc};
+
+pub const FFT_SIZE: usize = 2048;
+pub const BAND_COUNT: usize = 16;
+/// Frequency range the bands are spread over, log-spaced
+const MIN_FREQUENCY: f32 = 30.0;
+const MAX_FREQUENCY: f32 = 16000.0;
+/// Band level that reads as 0.0, full scale reads as 1.0
+const MIN_DB: f32 = -60.0;
+/// Share of the way back to the current level bands fall each frame, they rise instantly
+const BAND_RELEASE: f32 = 0.2;
+/// Frames of spectral flux the onset threshold is averaged over, about 0.7 s at 60 fps
+const FLUX_HISTORY: usize = 40;
+/// How far above the recent average flux has to go to count as an onset
+const ONSET_THRESHOLD: f32 = 1.5;
+/// Flux below this is silence or noise and never an onset
+const MIN_FLUX: f32 = 0.05;
+/// Beats are onsets below this frequency, kicks and bass
+const BEAT_FREQUENCY: f32 = 150.0;
+/// Share of the onset envelope left after each frame
+const ONSET_DECAY: f32 = 0.85;
+
+/// What visuals know about the audio, updated once per frame
+#[derive(Debug, Clone, Default)]
+pub struct Analysis {
+    /// 0.0..=1.0 by log-spaced band, lowest first
+    pub bands: [f32; BAND_COUNT],
+    /// Peak level of every track after its channel strip
+    pub track_levels: Vec<f32>,
+    /// Set on the frame a new sound starts
+    pub onset: bool,
+    /// Set on the frame a low frequency onset starts
+    pub beat: bool,
+    /// 1.0 at an onset, fades out over the next frames
+    pub onset_envelope: f32,
+    /// Same for beats
+    pub beat_envelope: f32,
+    /// The transport's beat, detected beats follow the sound instead
+    pub on_beat: bool,
+}
+
+pub struct Analyser {
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    samples: VecDeque<f32>,
+    spectrum: Vec<Complex<f32>>,
+    /// Magnitudes of the last analysis, for the flux
+    magnitudes: Vec<f32>,
+    /// First bin of every band and the end of the last one
+    band_edges: [usize; BAND_COUNT + 1],
+    beat_bins: usize,
+    flux_history: VecDeque<f32>,
+    beat_flux_history: VecDeque<f32>,
+    was_onset: bool,
+    was_beat: bool,
+    analysis: Analysis,
+}
+
+impl Analyser {
+    pub fn new(sample_rate: u32) -> Self {
+        let bin_width = sample_rate as f32 / FFT_SIZE as f32;
+        let max_frequency = MAX_FREQUENCY.min(sample_rate as f32 * 0.5);
+        let mut band_edges = [0; BAND_COUNT + 1];
+        for (i, edge) in band_edges.iter_mut().enumerate() {
+            let frequency =
+                MIN_FREQUENCY * (max_frequency / MIN_FREQUENCY).powf(i as f32 / BAND_COUNT as f32);
+            *edge = ((frequency / bin_width).round() as usize).clamp(1, FFT_SIZE / 2);
+        }
+        // Low bands are narrower than a bin, each one gets at least one
+        for i in 1..band_edges.len() {
+            band_edges[i] = band_edges[i].max(band_edges[i - 1] + 1);
+        }
+
+        Self {
+            fft: FftPlanner::new().plan_fft_forward(FFT_SIZE),
+            // Hann window, keeps a tone from leaking into every band
+            window: (0..FFT_SIZE)
+                .map(|i| 0.5 - 0.5 * (TAU * i as f32 / FFT_SIZE as f32).cos())
+                .collect(),
+            samples: VecDeque::from(vec![0.0; FFT_SIZE]),
+            spectrum: vec![Complex::new(0.0, 0.0); FFT_SIZE],
+            magnitudes: vec![0.0; FFT_SIZE / 2],
+            band_edges,
+            beat_bins: ((BEAT_FREQUENCY / bin_width) as usize).max(1),
+            flux_history: VecDeque::with_capacity(FLUX_HISTORY),
+            beat_flux_history: VecDeque::with_capacity(FLUX_HISTORY),
+            was_onset: false,
+            was_beat: false,
+            analysis: Analysis::default(),
+        }
+    }
+
+    pub fn push(&mut self, sample: f32) {
+        self.samples.pop_front();
+        self.samples.push_back(sample);
+    }
+
+    pub fn analysis(&self) -> &Analysis {
+        &self.analysis
+    }
+
+    pub fn set_track_levels(&mut self, levels: impl Iterator<Item = f32>) {
+        self.analysis.track_levels.clear();
+        self.analysis.track_levels.extend(levels);
+    }
+
+    pub fn set_on_beat(&mut self, on_beat: bool) {
+        self.analysis.on_beat = on_beat;
+    }
+
+    /// Analyses the last `FFT_SIZE` samples
+    pub fn analyse(&mut self) -> &Analysis {
+        for ((bin, sample), window) in self
+            .spectrum
+            .iter_mut()
+            .zip(&self.samples)
+            .zip(&self.window)
+        {
+            *bin = Complex::new(sample * window, 0.0);
+        }
+        self.fft.process(&mut self.spectrum);
+
+        // A full scale sine peaks at a quarter of the size with the Hann window
+        let scale = 4.0 / FFT_SIZE as f32;
+        let mut flux = 0.0;
+        let mut beat_flux = 0.0;
+        for (i, (magnitude, bin)) in self.magnitudes.iter_mut().zip(&self.spectrum).enumerate() {
+            let new = bin.norm() * scale;
+            let rise = (new - *magnitude).max(0.0);
+            flux += rise;
+            if i < self.beat_bins {
+                beat_flux += rise;
+            }
+            *magnitude = new;
+        }
+
+        for (band, edges) in self
+            .analysis
+            .bands
+            .iter_mut()
+            .zip(self.band_edges.windows(2))
+        {
+            let peak = self.magnitudes[edges[0]..edges[1]]
+                .iter()
+                .fold(0.0_f32, |peak, &m| peak.max(m));
+            let level = ((20.0 * peak.max(1e-6).log10() - MIN_DB) / -MIN_DB).clamp(0.0, 1.0);
+            *band = if level > *band {
+                level
+            } else {
+                *band + (level - *band) * BAND_RELEASE
+            };
+        }
+
+        let is_onset = is_peak(flux, &mut self.flux_history);
+        let is_beat = is_peak(beat_flux, &mut self.beat_flux_history);
+        // Only the frame an onset starts on counts
+        self.analysis.onset = is_onset && !self.was_onset;
+        self.analysis.beat = is_beat && !self.was_beat;
+        self.was_onset = is_onset;
+        self.was_beat = is_beat;
+        self.analysis.onset_envelope = if self.analysis.onset {
+            1.0
+        } else {
+            self.analysis.onset_envelope * ONSET_DECAY
+        };
+        self.analysis.beat_envelope = if self.analysis.beat {
+            1.0
+        } else {
+            self.analysis.beat_envelope * ONSET_DECAY
+        };
+
+        &self.analysis
+    }
+}
+
+/// True when `flux` is well above its recent average, which it then joins
+fn is_peak(flux: f32, history: &mut VecDeque<f32>) -> bool {
+    let average = if history.is_empty() {
+        0.0
+    } else {
+        history.iter().sum::<f32>() / history.len() as f32
+    };
+    if history.len() == FLUX_HISTORY {
+        history.pop_front();
+    }
+    history.push_back(flux);
+    flux > MIN_FLUX && flux > average * ONSET_THRESHOLD
+}
+
+#[cfg(test)]
+mod analyser_tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 44100;
+
+    fn push_sine(analyser: &mut Analyser, frequency: f32, amplitude: f32, count: usize) {
+        for i in 0..count {
+            let t = i as f32 / SAMPLE_RATE as f32;
+            analyser.push(amplitude * (TAU * frequency * t).sin());
+        }
+    }
+
+    #[test]
+    fn test_sine_lands_in_its_band() {
+        let mut analyser = Analyser::new(SAMPLE_RATE);
+        push_sine(&mut analyser, 1000.0, 0.5, FFT_SIZE);
+        let bands = analyser.analyse().bands;
+        let band = analyser
+            .band_edges
+            .windows(2)
+            .position(|edges| {
+                let bin = (1000.0 * FFT_SIZE as f32 / SAMPLE_RATE as f32).round() as usize;
+                (edges[0]..edges[1]).contains(&bin)
+            })
+            .unwrap();
+        let loudest = (0..BAND_COUNT)
+            .max_by(|a, b| bands[*a].total_cmp(&bands[*b]))
+            .unwrap();
+        assert_eq!(loudest, band);
+        // Half scale is about -6 dB
+        assert!((bands[band] - (1.0 - 6.0 / -MIN_DB)).abs() < 0.02);
+        assert!(bands[0] < 0.1);
+    }
+
+    #[test]
+    fn test_onsets_and_beats() {
+        let mut analyser = Analyser::new(SAMPLE_RATE);
+        for _ in 0..10 {
+            push_sine(&mut analyser, 0.0, 0.0, 735);
+            assert!(!analyser.analyse().onset);
+        }
+        // A kick-like low tone starts
+        push_sine(&mut analyser, 60.0, 0.8, 735);
+        let analysis = analyser.analyse();
+        assert!(analysis.onset && analysis.beat);
+        assert_eq!(analysis.onset_envelope, 1.0);
+        assert_eq!(analysis.beat_envelope, 1.0);
+        // Holding the tone is not a new onset
+        for _ in 0..10 {
+            push_sine(&mut analyser, 60.0, 0.8, 735);
+            assert!(!analyser.analyse().onset);
+        }
+        assert!(analyser.analysis().onset_envelope < 0.5);
+        assert!(analyser.analysis().beat_envelope < 0.5);
+    }
+}
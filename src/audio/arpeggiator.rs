@@ -0,0 +1,288 @@
+use super::{
+    step::Step,
+    transport::{StepPosition, STEPS_PER_BAR},
+};
+use rand::{rngs::StdRng, Rng};
+
+/// Note lengths the arpeggiator plays at, as notes per bar and their label
+pub const RATES: [(u32, &str); 6] = [
+    (4, "1/4"),
+    (8, "1/8"),
+    (12, "1/8T"),
+    (16, "1/16"),
+    (24, "1/16T"),
+    (32, "1/32"),
+];
+
+/// Chord a step plays above its note
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChordType {
+    Major,
+    Minor,
+    Diminished,
+    Augmented,
+    Sus2,
+    Sus4,
+    Major7,
+    Minor7,
+    Dominant7,
+}
+
+impl ChordType {
+    pub const ALL: [ChordType; 9] = [
+        ChordType::Major,
+        ChordType::Minor,
+        ChordType::Diminished,
+        ChordType::Augmented,
+        ChordType::Sus2,
+        ChordType::Sus4,
+        ChordType::Major7,
+        ChordType::Minor7,
+        ChordType::Dominant7,
+    ];
+
+    /// Semitones above the root, the root included
+    pub fn intervals(self) -> &'static [u8] {
+        match self {
+            ChordType::Major => &[0, 4, 7],
+            ChordType::Minor => &[0, 3, 7],
+            ChordType::Diminished => &[0, 3, 6],
+            ChordType::Augmented => &[0, 4, 8],
+            ChordType::Sus2 => &[0, 2, 7],
+            ChordType::Sus4 => &[0, 5, 7],
+            ChordType::Major7 => &[0, 4, 7, 11],
+            ChordType::Minor7 => &[0, 3, 7, 10],
+            ChordType::Dominant7 => &[0, 4, 7, 10],
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ChordType::Major => "maj",
+            ChordType::Minor => "min",
+            ChordType::Diminished => "dim",
+            ChordType::Augmented => "aug",
+            ChordType::Sus2 => "sus2",
+            ChordType::Sus4 => "sus4",
+            ChordType::Major7 => "maj7",
+            ChordType::Minor7 => "min7",
+            ChordType::Dominant7 => "7",
+        }
+    }
+
+    pub fn to_u8(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_u8(value: u8) -> Option<Self> {
+        Self::ALL.get(value as usize).copied()
+    }
+}
+
+/// Order the arpeggiator plays the notes of a chord in, `Off` plays them together
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArpMode {
+    Off,
+    Up,
+    Down,
+    UpDown,
+    Random,
+}
+
+impl ArpMode {
+    pub fn to_u8(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(ArpMode::Off),
+            1 => Some(ArpMode::Up),
+            2 => Some(ArpMode::Down),
+            3 => Some(ArpMode::UpDown),
+            4 => Some(ArpMode::Random),
+            _ => None,
+        }
+    }
+}
+
+/// What the sequencer should do with its voices this sample
+#[derive(Debug, Clone, Copy)]
+pub enum ArpEvent {
+    None,
+    /// Play the step's note raised by `semitones`
+    NoteOn {
+        step: Step,
+        semitones: u8,
+    },
+    NoteOff,
+}
+
+/// Plays the notes of a chord step one after another. A chord is held until the next step that
+/// plays, so rests after it keep it going.
+pub struct Arpeggiator {
+    pub mode: ArpMode,
+    /// Notes per bar, one of `RATES`
+    pub rate: u32,
+    /// Chord step being played and the step it started on
+    held: Option<(Step, &'static [u8], u64)>,
+    /// Index of the last note played since the chord started
+    tick: Option<u64>,
+    is_note_on: bool,
+}
+
+impl Arpeggiator {
+    pub fn new() -> Self {
+        Self {
+            mode: ArpMode::Off,
+            rate: 16,
+            held: None,
+            tick: None,
+            is_note_on: false,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.mode != ArpMode::Off
+    }
+
+    /// Starts playing `step`'s chord from `start`
+    pub fn hold(&mut self, step: Step, intervals: &'static [u8], start: u64) {
+        self.held = Some((step, intervals, start));
+        self.tick = None;
+    }
+
+    pub fn stop(&mut self) {
+        self.held = None;
+        self.is_note_on = false;
+    }
+
+    /// Notes start on every tick of the rate and are released after the chord step's gate
+    pub fn update(&mut self, position: StepPosition, rng: &mut StdRng) -> ArpEvent {
+        let Some((step, intervals, start)) = self.held else {
+            return ArpEvent::None;
+        };
+        if !self.is_active() {
+            let was_note_on = self.is_note_on;
+            self.stop();
+            return if was_note_on {
+                ArpEvent::NoteOff
+            } else {
+                ArpEvent::None
+            };
+        }
+
+        let elapsed = position.step.saturating_sub(start) as f32 + position.phase;
+        let ticks = elapsed * self.rate.max(1) as f32 / STEPS_PER_BAR as f32;
+        let tick = ticks as u64;
+        if self.tick != Some(tick) {
+            self.tick = Some(tick);
+            self.is_note_on = true;
+            let index = self.note_index(tick, intervals.len(), rng);
+            return ArpEvent::NoteOn {
+                step,
+                semitones: intervals[index],
+            };
+        }
+        if self.is_note_on && ticks.fract() >= step.gate {
+            self.is_note_on = false;
+            return ArpEvent::NoteOff;
+        }
+        ArpEvent::None
+    }
+
+    fn note_index(&self, tick: u64, count: usize, rng: &mut StdRng) -> usize {
+        let count = count as u64;
+        let index = match self.mode {
+            ArpMode::Off | ArpMode::Up => tick % count,
+            ArpMode::Down => count - 1 - tick % count,
+            ArpMode::UpDown => {
+                // The top and bottom notes aren't repeated when the direction turns
+                let period = (2 * count).saturating_sub(2).max(1);
+                let i = tick % period;
+                if i < count {
+                    i
+                } else {
+                    period - i
+                }
+            }
+            ArpMode::Random => rng.random_range(0..count),
+        };
+        index as usize
+    }
+}
+
+#[cfg(test)]
+mod arpeggiator_tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    /// Semitones played at every step of a held chord, for `steps` steps
+    fn play(arpeggiator: &mut Arpeggiator, chord: ChordType, steps: u64) -> Vec<u8> {
+        let mut rng = StdRng::seed_from_u64(0);
+        arpeggiator.hold(Step::default(), chord.intervals(), 0);
+        let mut notes = Vec::new();
+        for step in 0..steps {
+            for i in 0..100 {
+                let position = StepPosition {
+                    step,
+                    phase: i as f32 / 100.0,
+                };
+                if let ArpEvent::NoteOn { semitones, .. } = arpeggiator.update(position, &mut rng) {
+                    notes.push(semitones);
+                }
+            }
+        }
+        notes
+    }
+
+    #[test]
+    fn test_modes() {
+        let mut arpeggiator = Arpeggiator::new();
+        arpeggiator.mode = ArpMode::Up;
+        assert_eq!(
+            play(&mut arpeggiator, ChordType::Minor7, 5),
+            [0, 3, 7, 10, 0]
+        );
+        arpeggiator.mode = ArpMode::Down;
+        assert_eq!(play(&mut arpeggiator, ChordType::Major, 4), [7, 4, 0, 7]);
+        arpeggiator.mode = ArpMode::UpDown;
+        assert_eq!(
+            play(&mut arpeggiator, ChordType::Major, 6),
+            [0, 4, 7, 4, 0, 4]
+        );
+        arpeggiator.mode = ArpMode::Random;
+        let notes = play(&mut arpeggiator, ChordType::Major, 16);
+        assert!(notes
+            .iter()
+            .all(|n| ChordType::Major.intervals().contains(n)));
+    }
+
+    #[test]
+    fn test_rate_and_gate() {
+        let mut arpeggiator = Arpeggiator::new();
+        arpeggiator.mode = ArpMode::Up;
+        arpeggiator.rate = 32;
+        assert_eq!(play(&mut arpeggiator, ChordType::Major, 2).len(), 4);
+        arpeggiator.rate = 12;
+        // Eighth note triplets, three every two beats
+        assert_eq!(play(&mut arpeggiator, ChordType::Major, 8).len(), 6);
+
+        let mut rng = StdRng::seed_from_u64(0);
+        arpeggiator.rate = 16;
+        arpeggiator.hold(Step::default(), ChordType::Major.intervals(), 0);
+        let at = |phase| StepPosition { step: 0, phase };
+        assert!(matches!(
+            arpeggiator.update(at(0.0), &mut rng),
+            ArpEvent::NoteOn { .. }
+        ));
+        assert!(matches!(
+            arpeggiator.update(at(0.4), &mut rng),
+            ArpEvent::None
+        ));
+        assert!(matches!(
+            arpeggiator.update(at(0.5), &mut rng),
+            ArpEvent::NoteOff
+        ));
+    }
+}
@@ -0,0 +1,130 @@
+use super::transport::STEPS_PER_BAR;
+
+pub const MAX_PATTERNS: usize = 16;
+pub const MAX_REPEATS: u32 = 64;
+
+/// One row of the arrangement, a scene with the pattern every track plays
+#[derive(Debug, Clone, PartialEq)]
+pub struct Section {
+    /// Pattern index by track, tracks without an entry play their first pattern
+    pub patterns: Vec<usize>,
+    /// Times the longest pattern of the section is played
+    pub repeats: u32,
+}
+
+impl Section {
+    pub fn new(track_count: usize) -> Self {
+        Self {
+            patterns: vec![0; track_count],
+            repeats: 1,
+        }
+    }
+
+    pub fn pattern(&self, track: usize) -> usize {
+        self.patterns.get(track).copied().unwrap_or(0)
+    }
+
+    /// Length in whole bars when the longest pattern is `longest_steps` long, sections always
+    /// start and end on a bar so pattern switches stay on the grid
+    pub fn bars(&self, longest_steps: usize) -> u64 {
+        let pattern_bars = longest_steps.div_ceil(STEPS_PER_BAR as usize).max(1) as u64;
+        pattern_bars * self.repeats.max(1) as u64
+    }
+}
+
+/// Timeline of sections the transport follows while active, it loops after the last section
+#[derive(Debug, Clone, Default)]
+pub struct Arrangement {
+    pub sections: Vec<Section>,
+    pub active: bool,
+}
+
+impl Arrangement {
+    /// Index of the section playing in `bar`, `section_bars` gives the length of each section
+    pub fn section_at(&self, bar: u64, section_bars: impl Fn(&Section) -> u64) -> Option<usize> {
+        let total: u64 = self.sections.iter().map(&section_bars).sum();
+        if total == 0 {
+            return None;
+        }
+
+        let mut bar = bar % total;
+        for (i, length) in self.sections.iter().map(&section_bars).enumerate() {
+            if bar < length {
+                return Some(i);
+            }
+            bar -= length;
+        }
+        None
+    }
+
+    /// Keeps one pattern entry per track when tracks are added or removed
+    pub fn set_track_count(&mut self, track_count: usize) {
+        for section in &mut self.sections {
+            section.patterns.resize(track_count, 0);
+        }
+    }
+
+    pub fn remove_track(&mut self, track: usize) {
+        for section in &mut self.sections {
+            if track < section.patterns.len() {
+                section.patterns.remove(track);
+            }
+        }
+    }
+
+    /// Sections using a removed pattern fall back to the first one, later patterns move down
+    pub fn remove_pattern(&mut self, track: usize, pattern: usize) {
+        for section in &mut self.sections {
+            if let Some(index) = section.patterns.get_mut(track) {
+                if *index == pattern {
+                    *index = 0;
+                } else if *index > pattern {
+                    *index -= 1;
+                }
+            }
+        }
+    }
+}
+
+/// Patterns are named A, B, C...
+pub fn pattern_name(index: usize) -> char {
+    (b'A' + (index % 26) as u8) as char
+}
+
+#[cfg(test)]
+mod arrangement_tests {
+    use super::*;
+
+    #[test]
+    fn test_section_at_loops() {
+        let mut arrangement = Arrangement::default();
+        // A four times, then B twice
+        arrangement.sections.push(Section {
+            patterns: vec![0],
+            repeats: 4,
+        });
+        arrangement.sections.push(Section {
+            patterns: vec![1],
+            repeats: 2,
+        });
+        let section_bars = |section: &Section| section.bars(16);
+
+        assert_eq!(arrangement.section_at(0, section_bars), Some(0));
+        assert_eq!(arrangement.section_at(3, section_bars), Some(0));
+        assert_eq!(arrangement.section_at(4, section_bars), Some(1));
+        assert_eq!(arrangement.section_at(5, section_bars), Some(1));
+        assert_eq!(arrangement.section_at(6, section_bars), Some(0));
+        assert_eq!(Arrangement::default().section_at(0, section_bars), None);
+    }
+
+    #[test]
+    fn test_sections_fill_whole_bars() {
+        let section = Section {
+            patterns: vec![0, 0],
+            repeats: 2,
+        };
+        assert_eq!(section.bars(12), 2);
+        assert_eq!(section.bars(64), 8);
+        assert_eq!(section.pattern(5), 0);
+    }
+}
@@ -0,0 +1,384 @@
+//! Backends consume the frames produced by `AudioModel`, advance the `AudioClock` and record
+//! every callback in `AudioStats`. Frames are interleaved stereo and are mapped to the device's
+//! channel count here.
+//!
+//! `CpalBackend` plays them on the default output device, `NullBackend` runs without a sound card
+//! and either discards or captures them. `CpalInput` records the default input device.
+
+extern crate cpal;
+extern crate ringbuf;
+
+use super::{audio_clock::AudioClock, audio_stats::AudioStats};
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    BufferSize, Stream,
+};
+use ringbuf::{HeapConsumer, HeapProducer, HeapRb};
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+/// Frames the audio thread keeps in the ring buffer ahead of the device
+pub const DEFAULT_LATENCY_MS: u32 = 20;
+pub const MIN_LATENCY_MS: u32 = 2;
+/// Fits in the ring buffer at up to 48 kHz
+pub const MAX_LATENCY_MS: u32 = 150;
+/// Samples per frame in the ring buffer
+pub const FRAME_SIZE: usize = 2;
+/// In frames
+pub const RING_BUFFER_SIZE: usize = 1024 * 8;
+const NULL_TICK: Duration = Duration::from_millis(5);
+/// In mono samples at the input device's rate
+const INPUT_RING_BUFFER_SIZE: usize = 8192;
+
+pub trait AudioBackend {
+    fn name(&self) -> &str;
+
+    fn sample_rate(&self) -> u32;
+
+    fn channel_count(&self) -> u16;
+
+    /// Starts consuming frames and returns the producer side of the ring buffer
+    fn start(
+        &mut self,
+        audio_clock: Arc<AudioClock>,
+        audio_stats: Arc<AudioStats>,
+    ) -> Result<HeapProducer<f32>, BackendError>;
+}
+
+#[derive(Debug)]
+pub enum BackendError {
+    NoOutputDevice,
+    NoInputDevice,
+    DefaultConfig(cpal::DefaultStreamConfigError),
+    BuildStream(cpal::BuildStreamError),
+    PlayStream(cpal::PlayStreamError),
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackendError::NoOutputDevice => write!(f, "no output device available"),
+            BackendError::NoInputDevice => write!(f, "no input device available"),
+            BackendError::DefaultConfig(e) => write!(f, "{e}"),
+            BackendError::BuildStream(e) => write!(f, "{e}"),
+            BackendError::PlayStream(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+pub struct CpalBackend {
+    device: cpal::Device,
+    config: cpal::StreamConfig,
+    output_stream: Option<Stream>,
+}
+
+impl CpalBackend {
+    /// Assumes that the default output device supports the f32 sample format. The device picks
+    /// its buffer size unless `buffer_frames` is given.
+    pub fn new(buffer_frames: Option<u32>) -> Result<Self, BackendError> {
+        let host = cpal::default_host();
+        let device = host
+            .default_output_device()
+            .ok_or(BackendError::NoOutputDevice)?;
+        println!(
+            "Using default output device: \"{}\"",
+            device.name().unwrap_or_default()
+        );
+
+        let mut config: cpal::StreamConfig = device
+            .default_output_config()
+            .map_err(BackendError::DefaultConfig)?
+            .into();
+        if let Some(frames) = buffer_frames {
+            config.buffer_size = BufferSize::Fixed(frames);
+        }
+
+        Ok(Self {
+            device,
+            config,
+            output_stream: None,
+        })
+    }
+}
+
+impl AudioBackend for CpalBackend {
+    fn name(&self) -> &str {
+        "cpal"
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.config.sample_rate.0
+    }
+
+    fn channel_count(&self) -> u16 {
+        self.config.channels
+    }
+
+    fn start(
+        &mut self,
+        audio_clock: Arc<AudioClock>,
+        audio_stats: Arc<AudioStats>,
+    ) -> Result<HeapProducer<f32>, BackendError> {
+        let (producer, mut consumer) = create_ring();
+
+        let channels = self.config.channels as usize;
+        let output_data_fn = move |data: &mut [f32], info: &cpal::OutputCallbackInfo| {
+            let start = Instant::now();
+            let buffered_frames = consumer.len() / FRAME_SIZE;
+            let mut missing_frames = 0;
+            for frame in data.chunks_mut(channels) {
+                if let Some(input) = pop_frame(&mut consumer) {
+                    write_frame(frame, input);
+                } else {
+                    // Whatever the device left in the buffer would play otherwise
+                    write_frame(frame, [0.0; FRAME_SIZE]);
+                    missing_frames += 1;
+                }
+                audio_clock.update();
+            }
+            let timestamp = info.timestamp();
+            if let Some(latency) = timestamp.playback.duration_since(&timestamp.callback) {
+                audio_stats.set_device_latency(latency);
+            }
+            audio_stats.record_callback(
+                data.len() / channels,
+                buffered_frames,
+                missing_frames,
+                start.elapsed(),
+            );
+        };
+
+        println!(
+            "Attempting to build the output stream with f32 samples and `{:?}`.",
+            self.config
+        );
+        let output_stream = self
+            .device
+            .build_output_stream(&self.config, output_data_fn, err_fn, None)
+            .map_err(BackendError::BuildStream)?;
+        println!("Successfully built the output stream.");
+
+        output_stream.play().map_err(BackendError::PlayStream)?;
+        self.output_stream = Some(output_stream);
+
+        Ok(producer)
+    }
+}
+
+/// Microphone or line-in, mixed down to mono. The stream runs as long as this is kept.
+pub struct CpalInput {
+    sample_rate: u32,
+    _stream: Stream,
+}
+
+impl CpalInput {
+    /// Opens the default input device and returns the consumer side of its ring buffer. Assumes
+    /// that the device supports the f32 sample format.
+    pub fn open() -> Result<(Self, HeapConsumer<f32>), BackendError> {
+        let host = cpal::default_host();
+        let device = host
+            .default_input_device()
+            .ok_or(BackendError::NoInputDevice)?;
+        println!(
+            "Using default input device: \"{}\"",
+            device.name().unwrap_or_default()
+        );
+        let config: cpal::StreamConfig = device
+            .default_input_config()
+            .map_err(BackendError::DefaultConfig)?
+            .into();
+
+        let (mut producer, consumer) = HeapRb::new(INPUT_RING_BUFFER_SIZE).split();
+        let channels = config.channels as usize;
+        let input_data_fn = move |data: &[f32], _: &cpal::InputCallbackInfo| {
+            for frame in data.chunks(channels) {
+                // Samples are dropped while the audio thread is behind
+                let _ = producer.push(frame.iter().sum::<f32>() / frame.len() as f32);
+            }
+        };
+        let stream = device
+            .build_input_stream(&config, input_data_fn, err_fn, None)
+            .map_err(BackendError::BuildStream)?;
+        stream.play().map_err(BackendError::PlayStream)?;
+
+        Ok((
+            Self {
+                sample_rate: config.sample_rate.0,
+                _stream: stream,
+            },
+            consumer,
+        ))
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+}
+
+/// Advances the clock from a timer instead of a sound card, samples are captured interleaved
+/// when a buffer is given and discarded otherwise
+pub struct NullBackend {
+    sample_rate: u32,
+    channel_count: u16,
+    capture: Option<Arc<Mutex<Vec<f32>>>>,
+    is_running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl NullBackend {
+    pub fn new(
+        sample_rate: u32,
+        channel_count: u16,
+        capture: Option<Arc<Mutex<Vec<f32>>>>,
+    ) -> Self {
+        Self {
+            sample_rate,
+            channel_count,
+            capture,
+            is_running: Arc::new(AtomicBool::new(false)),
+            thread: None,
+        }
+    }
+}
+
+impl AudioBackend for NullBackend {
+    fn name(&self) -> &str {
+        "null"
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channel_count(&self) -> u16 {
+        self.channel_count
+    }
+
+    fn start(
+        &mut self,
+        audio_clock: Arc<AudioClock>,
+        audio_stats: Arc<AudioStats>,
+    ) -> Result<HeapProducer<f32>, BackendError> {
+        let (producer, mut consumer) = create_ring();
+
+        let sample_rate = self.sample_rate as f64;
+        let capture = self.capture.clone();
+        let is_running = Arc::clone(&self.is_running);
+        is_running.store(true, Ordering::Release);
+        self.thread = Some(std::thread::spawn(move || {
+            let start = Instant::now();
+            let mut consumed_frames: u64 = 0;
+            while is_running.load(Ordering::Acquire) {
+                let tick_start = Instant::now();
+                let target_frames = (start.elapsed().as_secs_f64() * sample_rate) as u64;
+                let frames = target_frames.saturating_sub(consumed_frames) as usize;
+                let buffered_frames = consumer.len() / FRAME_SIZE;
+                let mut missing_frames = 0;
+                let mut captured = capture.as_ref().map(|c| c.lock().unwrap());
+                while consumed_frames < target_frames {
+                    let frame = pop_frame(&mut consumer).unwrap_or_else(|| {
+                        missing_frames += 1;
+                        [0.0; FRAME_SIZE]
+                    });
+                    if let Some(captured) = captured.as_mut() {
+                        captured.extend_from_slice(&frame);
+                    }
+                    audio_clock.update();
+                    consumed_frames += 1;
+                }
+                drop(captured);
+                if frames > 0 {
+                    audio_stats.record_callback(
+                        frames,
+                        buffered_frames,
+                        missing_frames,
+                        tick_start.elapsed(),
+                    );
+                }
+                std::thread::sleep(NULL_TICK);
+            }
+        }));
+
+        Ok(producer)
+    }
+}
+
+impl Drop for NullBackend {
+    fn drop(&mut self) {
+        self.is_running.store(false, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn create_ring() -> (HeapProducer<f32>, HeapConsumer<f32>) {
+    let ring = HeapRb::new(RING_BUFFER_SIZE * FRAME_SIZE);
+    let (mut producer, consumer) = ring.split();
+    producer.push_slice(&[0.0; FRAME_SIZE]);
+    (producer, consumer)
+}
+
+/// Frames are always pushed whole, so a partial frame is never left behind
+fn pop_frame(consumer: &mut HeapConsumer<f32>) -> Option<[f32; FRAME_SIZE]> {
+    if consumer.len() < FRAME_SIZE {
+        return None;
+    }
+    let mut frame = [0.0; FRAME_SIZE];
+    consumer.pop_slice(&mut frame);
+    Some(frame)
+}
+
+/// Mono devices get the average, extra channels beyond left and right are silent
+fn write_frame(output: &mut [f32], frame: [f32; FRAME_SIZE]) {
+    match output.len() {
+        1 => output[0] = (frame[0] + frame[1]) * 0.5,
+        _ => {
+            for (channel, sample) in output.iter_mut().enumerate() {
+                *sample = frame.get(channel).copied().unwrap_or(0.0);
+            }
+        }
+    }
+}
+
+fn err_fn(err: cpal::StreamError) {
+    eprintln!("an error occurred on stream: {}", err);
+}
+
+#[cfg(test)]
+mod audio_backend_tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 1000;
+
+    #[test]
+    fn test_null_backend_consumes_frames() {
+        let capture = Arc::new(Mutex::new(Vec::new()));
+        let mut backend = NullBackend::new(SAMPLE_RATE, 2, Some(Arc::clone(&capture)));
+        let audio_clock = Arc::new(AudioClock::new(SAMPLE_RATE));
+        let audio_stats = Arc::new(AudioStats::new(SAMPLE_RATE));
+        let mut producer = backend
+            .start(Arc::clone(&audio_clock), Arc::clone(&audio_stats))
+            .unwrap();
+        producer.push_slice(&[0.5, -0.5].repeat(20));
+
+        // The ring buffer starts with one silent frame
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while audio_clock.get_elapsed_samples() < 21 && Instant::now() < deadline {
+            std::thread::sleep(NULL_TICK);
+        }
+        drop(backend);
+        assert!(audio_clock.get_elapsed_samples() >= 21);
+        assert!(audio_stats.take_report().callback_frames > 0);
+        let captured = capture.lock().unwrap();
+        assert_eq!(captured[..2], [0.0, 0.0]);
+        assert_eq!(captured[2..42], [0.5, -0.5].repeat(20));
+    }
+}
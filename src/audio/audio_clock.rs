@@ -1,21 +1,77 @@
-use std::sync::atomic::{AtomicU32, Ordering};
+//! Samples played by the output device since the stream started.
+//!
+//! The clock only knows samples and seconds. Musical positions depend on the tempo, which can
+//! change while playing, so they come from `Transport::steps()` and
+//! `Transport::musical_position_at()`, which count from the sample of the last tempo change.
 
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Counts the samples consumed by the audio backend, 64 bits so it never wraps in practice
 pub struct AudioClock {
-    elapsed_samples: AtomicU32,
+    sample_rate: u32,
+    elapsed_samples: AtomicU64,
 }
 
 impl AudioClock {
-    pub fn new() -> Self {
+    pub fn new(sample_rate: u32) -> Self {
+        Self::starting_at(sample_rate, 0)
+    }
+
+    pub fn starting_at(sample_rate: u32, elapsed_samples: u64) -> Self {
         Self {
-            elapsed_samples: AtomicU32::new(0),
+            sample_rate,
+            elapsed_samples: AtomicU64::new(elapsed_samples),
         }
     }
 
     pub fn update(&self) {
-        self.elapsed_samples.fetch_add(1, Ordering::Release);
+        self.advance(1);
+    }
+
+    pub fn advance(&self, samples: u64) {
+        self.elapsed_samples.fetch_add(samples, Ordering::Release);
     }
 
-    pub fn get_elapsed_samples(&self) -> u32 {
+    pub fn get_elapsed_samples(&self) -> u64 {
         self.elapsed_samples.load(Ordering::Acquire)
     }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn seconds(&self) -> f64 {
+        self.get_elapsed_samples() as f64 / self.sample_rate as f64
+    }
+}
+
+#[cfg(test)]
+mod audio_clock_tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    #[test]
+    fn test_passes_u32_max() {
+        let audio_clock = AudioClock::starting_at(SAMPLE_RATE, u32::MAX as u64 - 2);
+        let mut previous = audio_clock.get_elapsed_samples();
+        for _ in 0..5 {
+            audio_clock.update();
+            let elapsed = audio_clock.get_elapsed_samples();
+            assert_eq!(elapsed, previous + 1);
+            previous = elapsed;
+        }
+        assert_eq!(previous, u32::MAX as u64 + 3);
+    }
+
+    #[test]
+    fn test_seconds_after_days() {
+        // Three days at 48 kHz is well past where a 32-bit counter wraps
+        let samples = 3 * 24 * 60 * 60 * SAMPLE_RATE as u64;
+        let audio_clock = AudioClock::starting_at(SAMPLE_RATE, samples);
+        assert_eq!(audio_clock.seconds(), (3 * 24 * 60 * 60) as f64);
+
+        audio_clock.advance(SAMPLE_RATE as u64 / 2);
+        assert_eq!(audio_clock.seconds(), (3 * 24 * 60 * 60) as f64 + 0.5);
+    }
 }
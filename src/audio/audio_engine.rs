@@ -0,0 +1,305 @@
+use super::{
+    arrangement::{Arrangement, Section},
+    effects::EffectChain,
+    live_input::LiveInput,
+    mixer::Limiter,
+    post_process::PostProcess,
+    sequencer::Sequencer,
+    songs,
+    step::Step,
+    transport::{Transport, DEFAULT_BPM, STEPS_PER_BAR},
+    tuning::Tuning,
+};
+
+pub const SEQUENCER_COUNT: usize = 3;
+pub const DEFAULT_MASTER_GAIN: f32 = 1.0;
+
+/// Everything that makes sound, shared by the live stream and offline renders
+pub struct AudioEngine {
+    pub transport: Transport,
+    pub sequencers: Vec<Sequencer>,
+    pub input: LiveInput,
+    pub master_effects: EffectChain,
+    pub master_gain: f32,
+    pub tuning: Tuning,
+    /// Follows the image post-process stack, not saved with the song
+    pub post_process: PostProcess,
+    pub arrangement: Arrangement,
+    /// Bar and section the arrangement was last checked at
+    arranged_bar: Option<u64>,
+    current_section: Option<usize>,
+    limiter: Limiter,
+    sample_rate: u32,
+}
+
+impl AudioEngine {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            transport: Transport::new(DEFAULT_BPM, sample_rate),
+            sequencers: create_sequencers(sample_rate),
+            input: LiveInput::new(sample_rate),
+            master_effects: EffectChain::new(sample_rate, 2),
+            master_gain: DEFAULT_MASTER_GAIN,
+            tuning: Tuning::default(),
+            post_process: PostProcess::new(sample_rate),
+            arrangement: Arrangement::default(),
+            arranged_bar: None,
+            current_section: None,
+            limiter: Limiter::new(sample_rate),
+            sample_rate,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn add_track(&mut self) {
+        self.add_sequencer(create_sequencer(self.sample_rate));
+    }
+
+    /// Appends a track that was built elsewhere
+    pub fn add_sequencer(&mut self, sequencer: Sequencer) {
+        self.sequencers.push(sequencer);
+        self.arrangement.set_track_count(self.sequencers.len());
+    }
+
+    pub fn remove_track(&mut self, index: usize) {
+        if index < self.sequencers.len() {
+            self.sequencers.remove(index);
+            self.arrangement.remove_track(index);
+        }
+    }
+
+    pub fn remove_pattern(&mut self, track: usize, pattern: usize) {
+        if let Some(sequencer) = self.sequencers.get_mut(track) {
+            if sequencer.patterns().len() > 1 {
+                sequencer.remove_pattern(pattern);
+                self.arrangement.remove_pattern(track, pattern);
+            }
+        }
+    }
+
+    /// Replaces the patterns of `track` and lays them out one after another in the arrangement,
+    /// the other tracks keep what the sections already had them play
+    pub fn arrange_patterns(&mut self, track: usize, patterns: Vec<Vec<Step>>) {
+        let Some(sequencer) = self.sequencers.get_mut(track) else {
+            return;
+        };
+        sequencer.set_patterns(patterns);
+        let pattern_count = sequencer.patterns().len();
+        let sections = &mut self.arrangement.sections;
+        while sections.len() < pattern_count {
+            sections.push(Section::new(self.sequencers.len()));
+        }
+        for (pattern, section) in sections.iter_mut().enumerate() {
+            section.patterns.resize(self.sequencers.len(), 0);
+            section.patterns[track] = pattern.min(pattern_count - 1);
+        }
+        self.arrangement.active = true;
+    }
+
+    /// Section of the arrangement the transport is in, if the arrangement is followed
+    pub fn current_section(&self) -> Option<usize> {
+        self.arrangement
+            .active
+            .then_some(self.current_section)
+            .flatten()
+    }
+
+    pub fn play(&mut self) {
+        self.transport.play();
+    }
+
+    /// Held notes are released, playback resumes from the same position
+    pub fn pause(&mut self) {
+        self.transport.pause();
+        for sequencer in &mut self.sequencers {
+            sequencer.release();
+        }
+    }
+
+    /// Held notes are released and the playhead goes back to the first step
+    pub fn stop(&mut self) {
+        self.transport.stop();
+        self.arranged_bar = None;
+        self.current_section = None;
+        for sequencer in &mut self.sequencers {
+            sequencer.reset();
+        }
+    }
+
+    /// Renders one stereo frame: every sequencer goes through its effects and channel strip and
+    /// the live input through its strip, the sum goes through the master effects, the post
+    /// process and the limiter, then the transport advances
+    pub fn update(&mut self) -> [f32; 2] {
+        if self.arrangement.active && self.transport.is_playing() {
+            self.follow_arrangement();
+        }
+        let bpm = self.transport.bpm();
+        let frame = mix(
+            &mut self.sequencers,
+            &mut self.input,
+            &self.transport,
+            &self.tuning,
+        );
+        let frame = self.master_effects.process_frame(frame, bpm);
+        let [left, right] = self.post_process.process_frame(frame, bpm);
+        let frame = self
+            .limiter
+            .process([left * self.master_gain, right * self.master_gain]);
+        self.transport.update();
+        frame
+    }
+
+    /// Queues the patterns of the section the current bar belongs to, the sequencers switch to
+    /// them on the first step of the bar. Every pattern restarts when a new section begins, edits
+    /// to the current section take effect on the next bar.
+    fn follow_arrangement(&mut self) {
+        let bar = self.transport.step_position().step / STEPS_PER_BAR as u64;
+        if self.arranged_bar == Some(bar) {
+            return;
+        }
+        self.arranged_bar = Some(bar);
+
+        let sequencers = &self.sequencers;
+        let section = self
+            .arrangement
+            .section_at(bar, |section| section_bars(section, sequencers));
+        let is_new_section = section != self.current_section;
+        self.current_section = section;
+        let Some(section) = section.map(|i| &self.arrangement.sections[i]) else {
+            return;
+        };
+        for (i, sequencer) in self.sequencers.iter_mut().enumerate() {
+            let pattern = section.pattern(i);
+            if is_new_section || pattern != sequencer.pattern_index() {
+                sequencer.queue_pattern(pattern);
+            }
+        }
+    }
+}
+
+pub fn create_sequencers(sample_rate: u32) -> Vec<Sequencer> {
+    (0..SEQUENCER_COUNT)
+        .map(|_| create_sequencer(sample_rate))
+        .collect()
+}
+
+pub fn create_sequencer(sample_rate: u32) -> Sequencer {
+    Sequencer::new(sample_rate, songs::TEMPLATE_16.to_vec())
+}
+
+/// Bars a section lasts with the patterns the sequencers have now
+pub fn section_bars(section: &Section, sequencers: &[Sequencer]) -> u64 {
+    let longest = sequencers
+        .iter()
+        .enumerate()
+        .map(|(i, sequencer)| sequencer.length(section.pattern(i)))
+        .max()
+        .unwrap_or(0);
+    section.bars(longest)
+}
+
+/// Samples that just started cut off the other samplers in their choke group, samplers started
+/// together all keep playing
+fn choke_samplers(sequencers: &mut [Sequencer]) {
+    let group_bit = |group: u8| 1_u32 << (group % 32);
+    let triggered_groups = sequencers
+        .iter()
+        .filter_map(Sequencer::choke_trigger)
+        .fold(0, |groups, group| groups | group_bit(group));
+    if triggered_groups == 0 {
+        return;
+    }
+    for s in sequencers.iter_mut() {
+        let Some(group) = s.sampler.choke_group else {
+            continue;
+        };
+        if triggered_groups & group_bit(group) != 0 && s.choke_trigger().is_none() {
+            s.sampler.choke();
+        }
+    }
+}
+
+fn mix(
+    sequencers: &mut Vec<Sequencer>,
+    input: &mut LiveInput,
+    transport: &Transport,
+    tuning: &Tuning,
+) -> [f32; 2] {
+    // Sequencers only render their release tails while the transport isn't playing
+    let position = transport.is_playing().then(|| transport.step_position());
+    let bpm = transport.bpm();
+    let is_any_solo = input.strip.solo || sequencers.iter().any(|s| s.strip.solo);
+    let mut frame = input.process(is_any_solo);
+    for s in sequencers.iter_mut() {
+        // Silent tracks keep running so they stay in time and their effects don't hold old audio
+        let track_frame = s.update(position, bpm, tuning);
+        let track_frame = s.effects.process_frame(track_frame, bpm);
+        if s.strip.is_audible(is_any_solo) {
+            let [left, right] = s.strip.process(track_frame);
+            s.meter.process([left, right]);
+            frame[0] += left;
+            frame[1] += right;
+        } else {
+            s.meter.process([0.0, 0.0]);
+        }
+    }
+    choke_samplers(sequencers);
+    frame
+}
+
+#[cfg(test)]
+mod audio_engine_tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 1000;
+    /// 120 bpm is 8 steps per second
+    const SAMPLES_PER_BAR: usize = SAMPLE_RATE as usize * 2;
+
+    fn run(audio_engine: &mut AudioEngine, samples: usize) {
+        for _ in 0..samples {
+            audio_engine.update();
+        }
+    }
+
+    #[test]
+    fn test_arrangement_switches_on_bars() {
+        let mut audio_engine = AudioEngine::new(SAMPLE_RATE);
+        audio_engine.sequencers[0].duplicate_pattern(0);
+        audio_engine.arrangement.sections = vec![
+            Section {
+                patterns: vec![0, 0, 0],
+                repeats: 2,
+            },
+            Section {
+                patterns: vec![1, 0, 0],
+                repeats: 1,
+            },
+        ];
+        audio_engine.arrangement.active = true;
+
+        run(&mut audio_engine, SAMPLES_PER_BAR * 2 - 10);
+        assert_eq!(audio_engine.current_section(), Some(0));
+        assert_eq!(audio_engine.sequencers[0].pattern_index(), 0);
+        run(&mut audio_engine, 20);
+        assert_eq!(audio_engine.current_section(), Some(1));
+        assert_eq!(audio_engine.sequencers[0].pattern_index(), 1);
+        // The arrangement loops
+        run(&mut audio_engine, SAMPLES_PER_BAR);
+        assert_eq!(audio_engine.sequencers[0].pattern_index(), 0);
+    }
+
+    #[test]
+    fn test_queued_pattern_waits_for_bar() {
+        let mut audio_engine = AudioEngine::new(SAMPLE_RATE);
+        let pattern = audio_engine.sequencers[0].duplicate_pattern(0).unwrap();
+        run(&mut audio_engine, SAMPLES_PER_BAR / 2);
+        audio_engine.sequencers[0].queue_pattern(pattern);
+        run(&mut audio_engine, SAMPLES_PER_BAR / 2 - 10);
+        assert_eq!(audio_engine.sequencers[0].pattern_index(), 0);
+        run(&mut audio_engine, 20);
+        assert_eq!(audio_engine.sequencers[0].pattern_index(), pattern);
+    }
+}
@@ -1,146 +1,162 @@
-//! Assumes that the input and output devices can use the same stream configuration and that they
-//! support the f32 sample format.
-//!
-//! Uses a delay of `LATENCY_MS` milliseconds in case the default input and output streams are not
-//! precisely synchronised.
-
-extern crate cpal;
-extern crate ringbuf;
-
-use super::audio_clock::AudioClock;
-use crate::audio::{sequencer::Sequencer, songs};
-use cpal::{
-    traits::{DeviceTrait, HostTrait, StreamTrait},
-    Stream,
+use super::{
+    analyser::{Analyser, Analysis},
+    audio_backend::{
+        AudioBackend, CpalBackend, CpalInput, NullBackend, DEFAULT_LATENCY_MS, MAX_LATENCY_MS,
+        MIN_LATENCY_MS, RING_BUFFER_SIZE,
+    },
+    audio_clock::AudioClock,
+    audio_engine::{create_sequencer, AudioEngine},
+    audio_stats::{AudioReport, AudioStats},
+    audio_thread::{AudioThread, Command, Snapshot},
+    live_input,
+    song::{self, Song},
+    transport::{MusicalPosition, TransportState},
 };
-use kopek::metronome::Metronome;
-use ringbuf::{HeapProducer, HeapRb};
-use std::{collections::VecDeque, sync::Arc};
+use crate::shader_utils::Effect;
+use ringbuf::{HeapConsumer, HeapRb};
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+const NULL_SAMPLE_RATE: u32 = 44100;
+const NULL_CHANNEL_COUNT: u16 = 2;
+/// Seconds of recording the audio thread can get ahead of the interface
+const RECORDING_BUFFER_TIME: u32 = 1;
+/// Peaks in the report are over this long
+const REPORT_INTERVAL: Duration = Duration::from_millis(500);
+
+pub struct AudioSettings {
+    pub null_audio: bool,
+    /// Target for the frames buffered ahead of the device
+    pub latency_ms: u32,
+    /// Device buffer size in frames, the device picks one when it is not set
+    pub buffer_frames: Option<u32>,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        Self {
+            null_audio: false,
+            latency_ms: DEFAULT_LATENCY_MS,
+            buffer_frames: None,
+        }
+    }
+}
+
+/// What the live input is doing, for the interface
+#[derive(Debug, Clone, Copy)]
+pub struct InputStatus {
+    pub is_open: bool,
+    pub is_analysed: bool,
+    pub is_recording: bool,
+}
 
-const LATENCY_MS: f32 = 10.0;
+/// How the output stream is doing, for the interface
+#[derive(Debug, Clone, Copy)]
+pub struct OutputStatus {
+    /// Peaks are over the last `REPORT_INTERVAL`
+    pub report: AudioReport,
+    /// In milliseconds
+    pub latency_target: u32,
+}
 
+/// The interface's side of the audio thread. It keeps a copy of the engine for the interface to
+/// edit and sends the audio thread what changed on the next update after an edit.
 pub struct AudioModel {
-    output_stream: Stream,
+    backend: Box<dyn AudioBackend>,
     audio_clock: Arc<AudioClock>,
-    metronome: Metronome,
-    sequencers: Vec<Sequencer>,
-    input_producer: HeapProducer<Input>,
-    producer: HeapProducer<f32>,
+    audio_thread: AudioThread,
+    /// Edited by the interface, never rendered
+    audio_engine: AudioEngine,
+    /// Song of the engine as the audio thread was last told about it
+    sent_song: Song,
+    /// The copy may differ from `sent_song`, it is only compared after edits
+    is_dirty: bool,
+    /// Commands waiting for room in the queue
+    pending: VecDeque<Command>,
+    snapshot: Snapshot,
+    sample_rate: u32,
     signal: f32, // for visuals, @todo this should be signal peak
     pub rolling_wave: VecDeque<f32>,
-    // view_consumer: HeapConsumer<f32>,
+    analyser: Analyser,
+    /// Open while the live input is on, the stream stays on this thread
+    input: Option<CpalInput>,
+    analyse_input: bool,
+    /// Input recorded so far and the queue the audio thread sends more through
+    recording: Option<(HeapConsumer<f32>, Vec<f32>)>,
+    audio_stats: Arc<AudioStats>,
+    report: AudioReport,
+    reported_at: Instant,
+    latency_ms: u32,
 }
 
 impl AudioModel {
-    pub fn new() -> Result<AudioModel, ()> {
-        let host = cpal::default_host();
-
-        // Default devices.
-        let input_device = host
-            .default_input_device()
-            .expect("failed to get default input device");
-        let output_device = host
-            .default_output_device()
-            .expect("failed to get default output device");
-        println!(
-            "Using default input device: \"{}\"",
-            input_device.name().unwrap()
-        );
-        println!(
-            "Using default output device: \"{}\"",
-            output_device.name().unwrap()
-        );
-
-        // We'll try and use the same configuration between streams to keep it simple.
-        let output_config: cpal::StreamConfig =
-            output_device.default_output_config().unwrap().into();
-
-        let ring = HeapRb::new(1024 * 2);
-        let (mut producer, mut consumer) = ring.split();
-        if !producer.is_full() {
-            producer.push(0.0).unwrap();
-        }
-        let input_ring = HeapRb::new(10);
-        let (input_producer, input_consumer) = input_ring.split();
-        // let view_ring = HeapRb::new(100000);
-        // let (view_producer, view_consumer) = view_ring.split();
-
-        let sample_rate = output_config.sample_rate.0;
-        let audio_clock = Arc::new(AudioClock::new());
-
-        let metronome = Metronome::new(60, sample_rate, output_config.channels as u32);
-
-        let clock_for_audio = Arc::clone(&audio_clock);
-        let output_data_fn = move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-            for frame in data.chunks_mut(output_config.channels as usize) {
-                if let Some(input) = consumer.pop() {
-                    for sample in frame {
-                        *sample = input;
-                    }
-                } else {
-                    // eprintln!("Ringbuffer underrun {:?}", std::time::SystemTime::now())
+    /// Falls back to the null backend when no output device can be opened
+    pub fn new(settings: &AudioSettings) -> Result<AudioModel, ()> {
+        let mut backend: Box<dyn AudioBackend> = if settings.null_audio {
+            Box::new(NullBackend::new(NULL_SAMPLE_RATE, NULL_CHANNEL_COUNT, None))
+        } else {
+            match CpalBackend::new(settings.buffer_frames) {
+                Ok(backend) => Box::new(backend),
+                Err(e) => {
+                    eprintln!("Can't open audio device ({e}), using null audio backend");
+                    Box::new(NullBackend::new(NULL_SAMPLE_RATE, NULL_CHANNEL_COUNT, None))
                 }
-                clock_for_audio.update();
             }
         };
+        let audio_clock = Arc::new(AudioClock::new(backend.sample_rate()));
+        let audio_stats = Arc::new(AudioStats::new(backend.sample_rate()));
+        let producer = match backend.start(Arc::clone(&audio_clock), Arc::clone(&audio_stats)) {
+            Ok(producer) => producer,
+            Err(e) => {
+                eprintln!("Can't start audio backend ({e}), using null audio backend");
+                backend = Box::new(NullBackend::new(
+                    backend.sample_rate(),
+                    backend.channel_count(),
+                    None,
+                ));
+                backend
+                    .start(Arc::clone(&audio_clock), Arc::clone(&audio_stats))
+                    .expect("Can't start null audio backend")
+            }
+        };
+        println!("Using {} audio backend", backend.name());
 
-        // Build streams.
-        println!(
-            "Attempting to build both streams with f32 samples and `{:?}`.",
-            output_config
-        );
-        let output_stream = output_device
-            .build_output_stream(&output_config, output_data_fn, err_fn, None)
-            .unwrap();
-        println!("Successfully built streams.");
-
-        // Play the streams, This is not correct
-        println!(
-            "Starting the output stream with `{}` milliseconds of latency.",
-            LATENCY_MS
-        );
-        output_stream.play().expect("Can't play output stream");
-
-        let sequencer = Sequencer::new(
-            480,
-            sample_rate,
-            output_config.channels.into(),
-            songs::TEMPLATE_16.to_vec(),
-        );
-
-        let sequencer_2 = Sequencer::new(
-            480,
-            sample_rate,
-            output_config.channels.into(),
-            songs::TEMPLATE_16.to_vec(),
-        );
+        let sample_rate = backend.sample_rate();
 
-        let sequencer_3 = Sequencer::new(
-            480,
-            sample_rate,
-            output_config.channels.into(),
-            songs::TEMPLATE_16.to_vec(),
+        // Both engines start out the same, from then on only the differences are sent
+        let audio_engine = AudioEngine::new(sample_rate);
+        let sent_song = song::to_song(&audio_engine);
+        let snapshot = Snapshot::new(&audio_engine, 0);
+        let latency_ms = settings.latency_ms.clamp(MIN_LATENCY_MS, MAX_LATENCY_MS);
+        let audio_thread = AudioThread::spawn(
+            AudioEngine::new(sample_rate),
+            producer,
+            latency_frames(latency_ms, sample_rate),
         );
-        let mut sequencers = Vec::new();
-        sequencers.push(sequencer);
-        sequencers.push(sequencer_2);
-        sequencers.push(sequencer_3);
-
-        // std::thread::spawn(move || loop {
-        //     let elapsed_samples = audio_clock.get_elapsed_samples();
-        //     sequencer.update(elapsed_samples);
-        // });
 
         Ok(AudioModel {
-            output_stream,
+            backend,
             audio_clock,
-            metronome,
-            sequencers,
-            input_producer,
-            producer,
+            audio_thread,
+            audio_engine,
+            sent_song,
+            is_dirty: false,
+            pending: VecDeque::new(),
+            snapshot,
+            sample_rate,
             signal: 0.0,
             rolling_wave: VecDeque::from([0.0; 512]),
-            // view_consumer,
+            analyser: Analyser::new(sample_rate),
+            input: None,
+            analyse_input: false,
+            recording: None,
+            audio_stats,
+            report: AudioReport::default(),
+            reported_at: Instant::now(),
+            latency_ms,
         })
     }
 
@@ -148,54 +164,212 @@ impl AudioModel {
         self.signal
     }
 
+    /// Spectrum, track levels and onsets of the last update
+    pub fn analysis(&self) -> &Analysis {
+        self.analyser.analysis()
+    }
+
+    /// True during the first part of every audible beat
     pub fn on_beat(&self) -> bool {
-        self.metronome.on_beat()
+        self.snapshot.transport_state == TransportState::Playing
+            && self.musical_position().beat_phase < 0.25
+    }
+
+    /// Position of what is being heard
+    pub fn musical_position(&self) -> MusicalPosition {
+        self.snapshot.position
+    }
+
+    /// Queued commands are sent in order, after the ones sent before them
+    pub fn send(&mut self, command: Command) {
+        self.pending.push_back(command);
+    }
+
+    pub fn input_status(&self) -> InputStatus {
+        InputStatus {
+            is_open: self.input.is_some(),
+            is_analysed: self.analyse_input,
+            is_recording: self.recording.is_some(),
+        }
     }
 
+    /// Opens the default input device and mixes it in, or closes it
+    pub fn toggle_input(&mut self) {
+        if self.input.take().is_some() {
+            self.send(Command::DisconnectInput);
+            return;
+        }
+        match CpalInput::open() {
+            Ok((input, consumer)) => {
+                self.send(Command::ConnectInput {
+                    consumer,
+                    sample_rate: input.sample_rate(),
+                });
+                self.input = Some(input);
+            }
+            Err(e) => eprintln!("Can't open input device ({e})"),
+        }
+    }
+
+    /// Makes the visuals follow the live input instead of the master output
+    pub fn set_analyse_input(&mut self, analyse_input: bool) {
+        self.analyse_input = analyse_input;
+        self.send(Command::AnalyseInput(analyse_input));
+    }
+
+    /// Records the live input before its channel strip, silence while it is closed
+    pub fn start_recording(&mut self) {
+        let (producer, consumer) =
+            HeapRb::new((self.sample_rate * RECORDING_BUFFER_TIME) as usize).split();
+        self.send(Command::StartRecording(producer));
+        self.recording = Some((consumer, Vec::new()));
+    }
+
+    /// Writes what was recorded to `path` as a mono WAV file
+    pub fn stop_recording(&mut self, path: &str) -> Result<(), hound::Error> {
+        self.send(Command::StopRecording);
+        let Some((mut consumer, mut samples)) = self.recording.take() else {
+            return Ok(());
+        };
+        samples.extend(consumer.pop_iter());
+        live_input::write_recording(path, &samples, self.sample_rate)
+    }
+
+    pub fn output_status(&self) -> OutputStatus {
+        OutputStatus {
+            report: self.report,
+            latency_target: self.latency_ms,
+        }
+    }
+
+    /// The audio thread keeps this much audio buffered ahead of the device
+    pub fn set_latency_target(&mut self, latency_ms: u32) {
+        self.latency_ms = latency_ms.clamp(MIN_LATENCY_MS, MAX_LATENCY_MS);
+        self.send(Command::SetLatency(latency_frames(
+            self.latency_ms,
+            self.sample_rate,
+        )));
+    }
+
+    /// Makes the master bus follow the image post-process stack, `effects` are the active ones
+    /// in order
+    pub fn set_post_process(&mut self, effects: Vec<Effect>) {
+        self.audio_engine.post_process.set_effects(effects.clone());
+        self.send(Command::SetPostProcess(effects));
+    }
+
+    /// Sends the interface's edits to the audio thread and takes in what it rendered since the
+    /// last update
     pub fn update(&mut self) {
-        let elapsed_samples = self.audio_clock.get_elapsed_samples();
-        // self.metronome.update(sample_count);
-        let mut signal_peak = 0.0;
-        while !self.producer.is_full() {
-            let mut value = 0.0;
-            for s in &mut self.sequencers {
-                value += s.update(elapsed_samples);
+        if self.is_dirty {
+            self.send_changes();
+            self.is_dirty = false;
+        }
+        while let Some(command) = self.pending.pop_front() {
+            if let Err(command) = self.audio_thread.send(command) {
+                self.pending.push_front(command);
+                break;
             }
-            value = value / self.sequencers.len() as f32;
-            self.producer.push(value).unwrap();
+        }
+        self.audio_thread.update_snapshot(&mut self.snapshot);
+        if self.reported_at.elapsed() >= REPORT_INTERVAL {
+            self.report = self.audio_stats.take_report();
+            self.reported_at = Instant::now();
+        }
+
+        let mut signal_peak = 0.0;
+        for value in self.audio_thread.view_samples() {
             if value > signal_peak {
                 signal_peak = value;
             }
 
             self.rolling_wave.pop_front();
             self.rolling_wave.push_back(value);
+            self.analyser.push(value);
         }
         self.signal = signal_peak;
-        // if self.metronome.on_beat() {
-        //     self.input_producer.push(Input::Start).unwrap();
-        // } else {
-        //     self.input_producer.push(Input::Stop).unwrap();
-        // }
+        if let Some((consumer, samples)) = &mut self.recording {
+            samples.extend(consumer.pop_iter());
+        }
+        self.analyser.analyse();
+        self.analyser
+            .set_track_levels(self.snapshot.tracks.iter().map(|track| track.level));
+        self.analyser.set_on_beat(self.on_beat());
     }
 
-    pub fn sequencers(&self) -> &Vec<Sequencer> {
-        &self.sequencers
+    /// Removes the track from both engines
+    pub fn remove_track(&mut self, index: usize) {
+        self.is_dirty = true;
+        self.audio_engine.remove_track(index);
+        if index < self.sent_song.tracks.len() {
+            self.sent_song.tracks.remove(index);
+        }
+        self.send(Command::RemoveTrack(index));
     }
 
-    pub fn sequencers_mut(&mut self) -> &mut Vec<Sequencer> {
-        &mut self.sequencers
+    /// Queues the tracks and song settings that differ from what was last sent
+    fn send_changes(&mut self) {
+        let mut song = song::to_song(&self.audio_engine);
+        let tracks = std::mem::take(&mut song.tracks);
+        let sent_tracks = std::mem::take(&mut self.sent_song.tracks);
+        for (track, (data, sequencer)) in
+            tracks.iter().zip(&self.audio_engine.sequencers).enumerate()
+        {
+            if track >= sent_tracks.len() {
+                let mut added = create_sequencer(self.sample_rate);
+                added.sampler.sample = sequencer.sampler.sample.clone();
+                added.modulated_oscillator.wavetable.table =
+                    Arc::clone(&sequencer.modulated_oscillator.wavetable.table);
+                song::apply_track(data, &mut added);
+                self.pending.push_back(Command::AddTrack(Box::new(added)));
+            } else if sent_tracks[track] != *data {
+                self.pending.push_back(Command::UpdateTrack {
+                    track,
+                    data: Box::new(data.clone()),
+                    patterns: song::to_step_patterns(data),
+                    routes: song::to_mod_routes(&data.modulation),
+                    sample: sequencer.sampler.sample.clone(),
+                    wavetable: Arc::clone(&sequencer.modulated_oscillator.wavetable.table),
+                });
+            }
+        }
+        if tracks.len() < sent_tracks.len() {
+            self.pending
+                .push_back(Command::TruncateTracks(tracks.len()));
+        }
+        // Sent after the tracks so the arrangement is fitted to the new track count
+        if song != self.sent_song || tracks.len() != sent_tracks.len() {
+            self.pending.push_back(Command::UpdateSong {
+                song: Box::new(song.clone()),
+                scale: self.audio_engine.tuning.scale.clone(),
+                arrangement: song::to_arrangement(&song.arrangement, tracks.len()),
+            });
+        }
+        song.tracks = tracks;
+        self.sent_song = song;
     }
-}
 
-fn err_fn(err: cpal::StreamError) {
-    eprintln!("an error occurred on stream: {}", err);
+    /// The interface's copy of the engine and what the audio thread last reported
+    pub fn engine_and_snapshot(&mut self) -> (&mut AudioEngine, &Snapshot) {
+        (&mut self.audio_engine, &self.snapshot)
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Marks the copy as edited
+    pub fn audio_engine_mut(&mut self) -> &mut AudioEngine {
+        self.is_dirty = true;
+        &mut self.audio_engine
+    }
+
+    /// The interface edited the copy through `engine_and_snapshot`
+    pub fn mark_dirty(&mut self) {
+        self.is_dirty = true;
+    }
 }
 
-#[derive(Debug)]
-pub enum Input {
-    Start,
-    Stop,
-    ChangeFreq(f32),
-    ChangeOscillator(u8),
-    ChangeNoise(u8),
+fn latency_frames(latency_ms: u32, sample_rate: u32) -> usize {
+    (latency_ms as u64 * sample_rate as u64 / 1000).clamp(1, RING_BUFFER_SIZE as u64) as usize
 }
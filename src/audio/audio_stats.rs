@@ -0,0 +1,176 @@
+//! Health of the output stream, written by the backend on every callback and read by the
+//! interface.
+//!
+//! Crackles are underruns, the device asking for frames the audio thread hasn't rendered yet.
+//! The buffer fill shows how close it came to one and the callback time how long the backend
+//! itself took.
+
+use std::{
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+/// What the interface shows about the output stream
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AudioReport {
+    /// Callbacks that ran out of frames since the stream started
+    pub underruns: u64,
+    /// Of those, the ones since the last report
+    pub recent_underruns: u64,
+    /// Frames played as silence since the stream started
+    pub missing_frames: u64,
+    /// Frames in the ring buffer when the last callback started
+    pub buffered_frames: usize,
+    /// Fewest frames in the ring buffer since the last report
+    pub min_buffered_frames: usize,
+    /// Frames the device asked for in the last callback
+    pub callback_frames: usize,
+    pub callback_time: Duration,
+    /// Slowest callback since the last report
+    pub max_callback_time: Duration,
+    /// From a callback to its frames being played, when the device reports it
+    pub device_latency: Duration,
+    /// From the audio thread rendering a frame to it being heard
+    pub latency: Duration,
+    /// Share of its time budget the slowest callback used
+    pub load: f32,
+}
+
+pub struct AudioStats {
+    sample_rate: u32,
+    underruns: AtomicU64,
+    recent_underruns: AtomicU64,
+    missing_frames: AtomicU64,
+    buffered_frames: AtomicUsize,
+    min_buffered_frames: AtomicUsize,
+    callback_frames: AtomicUsize,
+    callback_nanos: AtomicU64,
+    max_callback_nanos: AtomicU64,
+    device_latency_nanos: AtomicU64,
+}
+
+impl AudioStats {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            underruns: AtomicU64::new(0),
+            recent_underruns: AtomicU64::new(0),
+            missing_frames: AtomicU64::new(0),
+            buffered_frames: AtomicUsize::new(0),
+            min_buffered_frames: AtomicUsize::new(usize::MAX),
+            callback_frames: AtomicUsize::new(0),
+            callback_nanos: AtomicU64::new(0),
+            max_callback_nanos: AtomicU64::new(0),
+            device_latency_nanos: AtomicU64::new(0),
+        }
+    }
+
+    /// Called by the backend after every callback. `buffered_frames` were in the ring buffer when
+    /// it started and `missing_frames` of the `frames` it asked for weren't.
+    pub fn record_callback(
+        &self,
+        frames: usize,
+        buffered_frames: usize,
+        missing_frames: usize,
+        duration: Duration,
+    ) {
+        if missing_frames > 0 {
+            self.underruns.fetch_add(1, Ordering::Relaxed);
+            self.recent_underruns.fetch_add(1, Ordering::Relaxed);
+            self.missing_frames
+                .fetch_add(missing_frames as u64, Ordering::Relaxed);
+        }
+        self.buffered_frames
+            .store(buffered_frames, Ordering::Relaxed);
+        self.min_buffered_frames
+            .fetch_min(buffered_frames, Ordering::Relaxed);
+        self.callback_frames.store(frames, Ordering::Relaxed);
+        let nanos = duration.as_nanos() as u64;
+        self.callback_nanos.store(nanos, Ordering::Relaxed);
+        self.max_callback_nanos.fetch_max(nanos, Ordering::Relaxed);
+    }
+
+    pub fn set_device_latency(&self, latency: Duration) {
+        self.device_latency_nanos
+            .store(latency.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Starts the next report's minimum fill, slowest callback and recent underruns over
+    pub fn take_report(&self) -> AudioReport {
+        let buffered_frames = self.buffered_frames.load(Ordering::Relaxed);
+        let min_buffered_frames = self.min_buffered_frames.swap(usize::MAX, Ordering::Relaxed);
+        // No callback since the last report
+        let min_buffered_frames = if min_buffered_frames == usize::MAX {
+            buffered_frames
+        } else {
+            min_buffered_frames
+        };
+        let callback_frames = self.callback_frames.load(Ordering::Relaxed);
+        let max_callback_time =
+            Duration::from_nanos(self.max_callback_nanos.swap(0, Ordering::Relaxed));
+        let device_latency =
+            Duration::from_nanos(self.device_latency_nanos.load(Ordering::Relaxed));
+        let sample_rate = self.sample_rate.max(1) as f64;
+        let budget = callback_frames as f64 / sample_rate;
+
+        AudioReport {
+            underruns: self.underruns.load(Ordering::Relaxed),
+            recent_underruns: self.recent_underruns.swap(0, Ordering::Relaxed),
+            missing_frames: self.missing_frames.load(Ordering::Relaxed),
+            buffered_frames,
+            min_buffered_frames,
+            callback_frames,
+            callback_time: Duration::from_nanos(self.callback_nanos.load(Ordering::Relaxed)),
+            max_callback_time,
+            device_latency,
+            latency: Duration::from_secs_f64(buffered_frames as f64 / sample_rate) + device_latency,
+            load: if budget > 0.0 {
+                (max_callback_time.as_secs_f64() / budget) as f32
+            } else {
+                0.0
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod audio_stats_tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 1000;
+
+    #[test]
+    fn test_report() {
+        let audio_stats = AudioStats::new(SAMPLE_RATE);
+        audio_stats.record_callback(10, 30, 0, Duration::from_millis(1));
+        audio_stats.record_callback(10, 5, 5, Duration::from_millis(4));
+        audio_stats.record_callback(10, 20, 0, Duration::from_millis(2));
+        audio_stats.set_device_latency(Duration::from_millis(10));
+
+        let report = audio_stats.take_report();
+        assert_eq!(report.underruns, 1);
+        assert_eq!(report.recent_underruns, 1);
+        assert_eq!(report.missing_frames, 5);
+        assert_eq!(report.buffered_frames, 20);
+        assert_eq!(report.min_buffered_frames, 5);
+        assert_eq!(report.callback_time, Duration::from_millis(2));
+        assert_eq!(report.max_callback_time, Duration::from_millis(4));
+        // 20 frames at 1 kHz plus the device's own
+        assert_eq!(report.latency, Duration::from_millis(30));
+        // 4 ms out of the 10 ms that 10 frames last
+        assert!((report.load - 0.4).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_peaks_reset_with_every_report() {
+        let audio_stats = AudioStats::new(SAMPLE_RATE);
+        audio_stats.record_callback(10, 0, 10, Duration::from_millis(8));
+        audio_stats.take_report();
+
+        let report = audio_stats.take_report();
+        assert_eq!(report.underruns, 1);
+        assert_eq!(report.recent_underruns, 0);
+        assert_eq!(report.min_buffered_frames, report.buffered_frames);
+        assert_eq!(report.max_callback_time, Duration::ZERO);
+    }
+}
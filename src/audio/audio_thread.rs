@@ -0,0 +1,481 @@
+//! Renders the engine on its own thread so a slow interface frame can't starve the output.
+//!
+//! The interface edits a copy of the engine and sends what changed as `Command`s over a lock-free
+//! queue. The audio thread answers with a `Snapshot` of what is playing and with the mono mix
+//! for the visuals, neither side ever waits for the other.
+
+use super::{
+    arrangement::Arrangement,
+    audio_backend::FRAME_SIZE,
+    audio_engine::AudioEngine,
+    modulation::ModRoute,
+    sampler::Sample,
+    sequencer::Sequencer,
+    song::{self, Song, TrackData},
+    step::Step,
+    transport::{MusicalPosition, TransportState},
+    tuning::Scale,
+    wavetable_oscillator::Wavetable,
+};
+use crate::shader_utils::Effect;
+use ringbuf::{HeapConsumer, HeapProducer, HeapRb};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+const COMMAND_QUEUE_SIZE: usize = 256;
+const SNAPSHOT_QUEUE_SIZE: usize = 4;
+/// Mono samples for the visuals, a few video frames worth
+const VIEW_QUEUE_SIZE: usize = 16384;
+/// Sleep between fills of the output ring buffer
+const RENDER_TICK: Duration = Duration::from_millis(2);
+
+pub enum Command {
+    Play,
+    Pause,
+    Stop,
+    /// Switches `track` to `pattern` on the next bar
+    QueuePattern {
+        track: usize,
+        pattern: usize,
+    },
+    RemoveTrack(usize),
+    RemovePattern {
+        track: usize,
+        pattern: usize,
+    },
+    /// Appends a track, built by the interface so the audio thread doesn't allocate one
+    AddTrack(Box<Sequencer>),
+    /// Settings and patterns of a track. The patterns and routes of `data` come converted, and
+    /// the loaded sample and wavetable come along, so the audio thread never allocates or reads
+    /// files.
+    UpdateTrack {
+        track: usize,
+        data: Box<TrackData>,
+        patterns: Vec<Vec<Step>>,
+        routes: Vec<ModRoute>,
+        sample: Option<Sample>,
+        wavetable: Arc<Wavetable>,
+    },
+    /// Removes the tracks after the first `count`
+    TruncateTracks(usize),
+    /// Tempo, tuning, master bus and arrangement, the song's tracks are ignored. `scale` is the
+    /// tuning's loaded Scala file and `arrangement` the song's, built where allocating is fine.
+    UpdateSong {
+        song: Box<Song>,
+        scale: Option<Arc<Scale>>,
+        arrangement: Arrangement,
+    },
+    /// Mixes in the input device, which fills `consumer` at `sample_rate`
+    ConnectInput {
+        consumer: HeapConsumer<f32>,
+        sample_rate: u32,
+    },
+    DisconnectInput,
+    /// The visuals follow the live input instead of the master output
+    AnalyseInput(bool),
+    /// Sends the live input to `producer` at the engine's rate until recording stops
+    StartRecording(HeapProducer<f32>),
+    StopRecording,
+    /// Frames to keep in the output ring buffer, more is safer against underruns but is heard
+    /// later
+    SetLatency(usize),
+    /// Active image post-process effects in order, the master bus follows them
+    SetPostProcess(Vec<Effect>),
+}
+
+/// What a track is doing, for the interface
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TrackSnapshot {
+    pub pattern_index: usize,
+    pub queued_pattern: Option<usize>,
+    pub active_voices: usize,
+    /// Of the last note
+    pub frequency: f32,
+    pub level: f32,
+}
+
+/// State of the engine after the last block the audio thread rendered
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub transport_state: TransportState,
+    /// Position of what is being heard, the engine runs ahead by the buffered frames
+    pub position: MusicalPosition,
+    pub current_section: Option<usize>,
+    pub tracks: Vec<TrackSnapshot>,
+    /// Live input after its channel strip
+    pub input_level: f32,
+}
+
+impl TrackSnapshot {
+    fn new(sequencer: &Sequencer) -> Self {
+        Self {
+            pattern_index: sequencer.pattern_index(),
+            queued_pattern: sequencer.queued_pattern(),
+            active_voices: sequencer.voices.active_voices(),
+            frequency: sequencer.frequency(),
+            level: sequencer.meter.level(),
+        }
+    }
+}
+
+impl Snapshot {
+    pub fn new(audio_engine: &AudioEngine, buffered_frames: usize) -> Self {
+        Self {
+            transport_state: audio_engine.transport.state(),
+            position: audio_engine.transport.musical_position_at(buffered_frames),
+            current_section: audio_engine.current_section(),
+            tracks: audio_engine
+                .sequencers
+                .iter()
+                .map(TrackSnapshot::new)
+                .collect(),
+            input_level: audio_engine.input.meter.level(),
+        }
+    }
+
+    /// Same as `new` but reuses the track list, it only allocates when there are more tracks
+    /// than ever before
+    fn update(&mut self, audio_engine: &AudioEngine, buffered_frames: usize) {
+        self.transport_state = audio_engine.transport.state();
+        self.position = audio_engine.transport.musical_position_at(buffered_frames);
+        self.current_section = audio_engine.current_section();
+        self.tracks.clear();
+        self.tracks
+            .extend(audio_engine.sequencers.iter().map(TrackSnapshot::new));
+        self.input_level = audio_engine.input.meter.level();
+    }
+
+    /// Tracks the audio thread hasn't reported yet look idle
+    pub fn track(&self, index: usize) -> TrackSnapshot {
+        self.tracks.get(index).copied().unwrap_or_default()
+    }
+}
+
+/// Handle of the thread that owns the engine, the thread stops when it is dropped
+pub struct AudioThread {
+    commands: HeapProducer<Command>,
+    snapshots: HeapConsumer<Snapshot>,
+    /// Snapshots the interface is done with, the audio thread fills them again
+    spare_snapshots: HeapProducer<Snapshot>,
+    view: HeapConsumer<f32>,
+    is_running: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl AudioThread {
+    /// Renders `audio_engine` into `producer` whenever it holds fewer than `latency` frames
+    pub fn spawn(audio_engine: AudioEngine, producer: HeapProducer<f32>, latency: usize) -> Self {
+        let (commands, command_consumer) = HeapRb::new(COMMAND_QUEUE_SIZE).split();
+        let (snapshot_producer, snapshots) = HeapRb::new(SNAPSHOT_QUEUE_SIZE).split();
+        // One more than can be queued, for the one the interface holds
+        let (mut spare_snapshots, spare_consumer) = HeapRb::new(SNAPSHOT_QUEUE_SIZE + 1).split();
+        for _ in 0..SNAPSHOT_QUEUE_SIZE {
+            let _ = spare_snapshots.push(Snapshot::new(&audio_engine, 0));
+        }
+        let (view_producer, view) = HeapRb::new(VIEW_QUEUE_SIZE).split();
+        let is_running = Arc::new(AtomicBool::new(true));
+        let renderer = Renderer {
+            audio_engine,
+            producer,
+            commands: command_consumer,
+            snapshots: snapshot_producer,
+            spare_snapshots: spare_consumer,
+            view: view_producer,
+            analyse_input: false,
+            recording: None,
+            latency,
+        };
+        let handle = {
+            let is_running = Arc::clone(&is_running);
+            thread::Builder::new()
+                .name(String::from("audio"))
+                .spawn(move || renderer.run(&is_running))
+                .expect("Can't spawn audio thread")
+        };
+
+        Self {
+            commands,
+            snapshots,
+            spare_snapshots,
+            view,
+            is_running,
+            handle: Some(handle),
+        }
+    }
+
+    /// Gives the command back if the queue is full
+    pub fn send(&mut self, command: Command) -> Result<(), Command> {
+        self.commands.push(command)
+    }
+
+    /// Replaces `snapshot` with the newest one since the last call, if any. Replaced snapshots
+    /// go back to the audio thread to be filled again.
+    pub fn update_snapshot(&mut self, snapshot: &mut Snapshot) -> bool {
+        let mut is_updated = false;
+        while let Some(newer) = self.snapshots.pop() {
+            let older = std::mem::replace(snapshot, newer);
+            let _ = self.spare_snapshots.push(older);
+            is_updated = true;
+        }
+        is_updated
+    }
+
+    /// Mono mix, or live input while it is analysed, rendered since the last call. Samples are
+    /// dropped while nobody reads them
+    pub fn view_samples(&mut self) -> impl Iterator<Item = f32> + '_ {
+        self.view.pop_iter()
+    }
+}
+
+impl Drop for AudioThread {
+    fn drop(&mut self) {
+        self.is_running.store(false, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// The audio thread's side of the queues
+struct Renderer {
+    audio_engine: AudioEngine,
+    producer: HeapProducer<f32>,
+    commands: HeapConsumer<Command>,
+    snapshots: HeapProducer<Snapshot>,
+    spare_snapshots: HeapConsumer<Snapshot>,
+    view: HeapProducer<f32>,
+    analyse_input: bool,
+    recording: Option<HeapProducer<f32>>,
+    /// In frames
+    latency: usize,
+}
+
+impl Renderer {
+    fn run(mut self, is_running: &AtomicBool) {
+        while is_running.load(Ordering::Relaxed) {
+            self.render();
+            thread::sleep(RENDER_TICK);
+        }
+    }
+
+    fn render(&mut self) {
+        while let Some(command) = self.commands.pop() {
+            self.handle(command);
+        }
+        while self.producer.free_len() >= FRAME_SIZE
+            && self.producer.len() < self.latency * FRAME_SIZE
+        {
+            let frame = self.audio_engine.update();
+            self.producer.push_slice(&frame);
+            let input = self.audio_engine.input.sample();
+            let view = if self.analyse_input {
+                input
+            } else {
+                (frame[0] + frame[1]) * 0.5
+            };
+            let _ = self.view.push(view);
+            if let Some(recording) = &mut self.recording {
+                // The interface drains it every frame, a stalled one loses audio
+                let _ = recording.push(input);
+            }
+        }
+        // The interface takes the newest one, there is no point in queueing more
+        if self.snapshots.is_empty() {
+            if let Some(mut snapshot) = self.spare_snapshots.pop() {
+                let buffered_frames = self.producer.len() / FRAME_SIZE;
+                snapshot.update(&self.audio_engine, buffered_frames);
+                let _ = self.snapshots.push(snapshot);
+            }
+        }
+    }
+
+    fn handle(&mut self, command: Command) {
+        match command {
+            Command::AnalyseInput(analyse_input) => self.analyse_input = analyse_input,
+            Command::StartRecording(producer) => self.recording = Some(producer),
+            Command::StopRecording => self.recording = None,
+            Command::SetLatency(latency) => self.latency = latency,
+            command => apply(command, &mut self.audio_engine),
+        }
+    }
+}
+
+fn apply(command: Command, audio_engine: &mut AudioEngine) {
+    match command {
+        Command::Play => audio_engine.play(),
+        Command::Pause => audio_engine.pause(),
+        Command::Stop => audio_engine.stop(),
+        Command::QueuePattern { track, pattern } => {
+            if let Some(sequencer) = audio_engine.sequencers.get_mut(track) {
+                sequencer.queue_pattern(pattern);
+            }
+        }
+        Command::RemoveTrack(track) => audio_engine.remove_track(track),
+        Command::RemovePattern { track, pattern } => audio_engine.remove_pattern(track, pattern),
+        Command::AddTrack(sequencer) => audio_engine.add_sequencer(*sequencer),
+        Command::UpdateTrack {
+            track,
+            data,
+            patterns,
+            routes,
+            sample,
+            wavetable,
+        } => {
+            if let Some(sequencer) = audio_engine.sequencers.get_mut(track) {
+                sequencer.sampler.sample = sample;
+                sequencer.modulated_oscillator.wavetable.table = wavetable;
+                song::apply_track_settings(&data, sequencer);
+                sequencer.modulation.routes = routes;
+                sequencer.set_patterns(patterns);
+            }
+        }
+        Command::TruncateTracks(count) => {
+            while audio_engine.sequencers.len() > count {
+                audio_engine.remove_track(audio_engine.sequencers.len() - 1);
+            }
+        }
+        Command::UpdateSong {
+            song,
+            scale,
+            arrangement,
+        } => {
+            audio_engine.tuning.scale = scale;
+            song::apply_master_settings(&song, audio_engine);
+            audio_engine.arrangement = arrangement;
+        }
+        Command::ConnectInput {
+            consumer,
+            sample_rate,
+        } => audio_engine.input.connect(consumer, sample_rate),
+        Command::DisconnectInput => audio_engine.input.disconnect(),
+        Command::SetPostProcess(effects) => audio_engine.post_process.set_effects(effects),
+        // Handled by the renderer, they don't change the engine
+        Command::AnalyseInput(_)
+        | Command::StartRecording(_)
+        | Command::StopRecording
+        | Command::SetLatency(_) => {}
+    }
+}
+
+#[cfg(test)]
+mod audio_thread_tests {
+    use super::*;
+    use crate::audio::audio_engine::create_sequencer;
+    use std::time::Instant;
+
+    const SAMPLE_RATE: u32 = 1000;
+
+    #[test]
+    fn test_add_and_truncate_tracks() {
+        let mut audio_engine = AudioEngine::new(SAMPLE_RATE);
+        let mut sequencer = create_sequencer(SAMPLE_RATE);
+        sequencer.strip.gain = 0.25;
+        sequencer.duplicate_pattern(0);
+
+        apply(Command::AddTrack(Box::new(sequencer)), &mut audio_engine);
+        assert_eq!(audio_engine.sequencers.len(), 4);
+        assert_eq!(audio_engine.sequencers[3].strip.gain, 0.25);
+        assert_eq!(audio_engine.sequencers[3].patterns().len(), 2);
+
+        apply(Command::TruncateTracks(2), &mut audio_engine);
+        assert_eq!(audio_engine.sequencers.len(), 2);
+    }
+
+    #[test]
+    fn test_edits_keep_queued_pattern() {
+        let mut audio_engine = AudioEngine::new(SAMPLE_RATE);
+        audio_engine.sequencers[0].duplicate_pattern(0);
+        apply(
+            Command::QueuePattern {
+                track: 0,
+                pattern: 1,
+            },
+            &mut audio_engine,
+        );
+        let song = song::to_song(&audio_engine);
+        apply(
+            Command::UpdateTrack {
+                track: 0,
+                data: Box::new(song.tracks[0].clone()),
+                patterns: song::to_step_patterns(&song.tracks[0]),
+                routes: Vec::new(),
+                sample: None,
+                wavetable: Wavetable::basic(),
+            },
+            &mut audio_engine,
+        );
+        assert_eq!(audio_engine.sequencers[0].queued_pattern(), Some(1));
+    }
+
+    #[test]
+    fn test_records_and_analyses_input() {
+        let (producer, mut output) = HeapRb::<f32>::new(256).split();
+        let (view, mut view_consumer) = HeapRb::new(VIEW_QUEUE_SIZE).split();
+        let mut renderer = Renderer {
+            audio_engine: AudioEngine::new(SAMPLE_RATE),
+            producer,
+            commands: HeapRb::new(COMMAND_QUEUE_SIZE).split().1,
+            snapshots: HeapRb::new(SNAPSHOT_QUEUE_SIZE).split().0,
+            spare_snapshots: HeapRb::new(SNAPSHOT_QUEUE_SIZE).split().1,
+            view,
+            analyse_input: false,
+            recording: None,
+            latency: 32,
+        };
+        let (mut input, consumer) = HeapRb::new(256).split();
+        input.push_slice(&[0.5; 40]);
+        let (recording, mut recorded) = HeapRb::new(256).split();
+        for command in [
+            Command::ConnectInput {
+                consumer,
+                sample_rate: SAMPLE_RATE,
+            },
+            Command::AnalyseInput(true),
+            Command::StartRecording(recording),
+        ] {
+            renderer.handle(command);
+        }
+        renderer.render();
+        // Rendering stops at the latency target, not when the ring buffer is full
+        assert_eq!(output.len(), 32 * FRAME_SIZE);
+
+        // The input is muted but still recorded and analysed
+        assert!(output.pop_iter().all(|sample| sample == 0.0));
+        assert_eq!(recorded.len(), 32);
+        assert!(recorded.pop_iter().skip(1).all(|sample| sample == 0.5));
+        assert!(view_consumer.pop_iter().skip(1).all(|sample| sample == 0.5));
+    }
+
+    #[test]
+    fn test_thread_plays_and_reports() {
+        let (producer, mut consumer) = HeapRb::<f32>::new(256).split();
+        let mut audio_thread = AudioThread::spawn(AudioEngine::new(SAMPLE_RATE), producer, 128);
+        assert!(audio_thread.send(Command::Play).is_ok());
+
+        let start = Instant::now();
+        let mut snapshot = Snapshot::new(&AudioEngine::new(SAMPLE_RATE), 0);
+        let mut updates = 0;
+        while start.elapsed() < Duration::from_secs(2) {
+            consumer.pop_iter().for_each(drop);
+            if audio_thread.update_snapshot(&mut snapshot) {
+                updates += 1;
+            }
+            // Past the snapshots it started with, so they are being reused
+            if snapshot.transport_state == TransportState::Playing && updates > SNAPSHOT_QUEUE_SIZE
+            {
+                break;
+            }
+            thread::sleep(RENDER_TICK);
+        }
+        assert_eq!(snapshot.transport_state, TransportState::Playing);
+        assert!(updates > SNAPSHOT_QUEUE_SIZE);
+        assert_eq!(snapshot.tracks.len(), 3);
+        assert!(audio_thread.view_samples().count() > 0);
+    }
+}
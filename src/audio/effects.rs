@@ -0,0 +1,125 @@
+//! Insert effects for tracks and the master bus.
+//!
+//! A chain is an ordered list of effects that can be switched on and off and reordered, the same
+//! way the image post-process stack works. Every chain is stereo and keeps separate state for the
+//! left and right channels.
+
+pub mod bitcrusher;
+pub mod delay;
+pub mod distortion;
+pub mod reverb;
+
+use bitcrusher::Bitcrusher;
+use delay::Delay;
+use distortion::Distortion;
+use reverb::Reverb;
+
+pub enum AudioEffect {
+    Distortion(Distortion),
+    Bitcrusher(Bitcrusher),
+    Delay(Delay),
+    Reverb(Reverb),
+}
+
+impl AudioEffect {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AudioEffect::Distortion(_) => "distortion",
+            AudioEffect::Bitcrusher(_) => "bitcrusher",
+            AudioEffect::Delay(_) => "delay",
+            AudioEffect::Reverb(_) => "reverb",
+        }
+    }
+
+    /// `bpm` is used by the tempo-synced effects
+    pub fn process(&mut self, input: f32, channel: usize, bpm: f32) -> f32 {
+        match self {
+            AudioEffect::Distortion(e) => e.process(input),
+            AudioEffect::Bitcrusher(e) => e.process(input, channel),
+            AudioEffect::Delay(e) => e.process(input, channel, bpm),
+            AudioEffect::Reverb(e) => e.process(input, channel),
+        }
+    }
+}
+
+pub struct EffectSlot {
+    pub effect: AudioEffect,
+    pub active: bool,
+}
+
+pub struct EffectChain {
+    pub slots: Vec<EffectSlot>,
+}
+
+impl EffectChain {
+    /// Every effect once, all of them inactive
+    pub fn new(sample_rate: u32, channel_count: usize) -> Self {
+        let effects = [
+            AudioEffect::Distortion(Distortion::new()),
+            AudioEffect::Bitcrusher(Bitcrusher::new(channel_count)),
+            AudioEffect::Delay(Delay::new(sample_rate, channel_count)),
+            AudioEffect::Reverb(Reverb::new(sample_rate, channel_count)),
+        ];
+
+        Self {
+            slots: effects
+                .into_iter()
+                .map(|effect| EffectSlot {
+                    effect,
+                    active: false,
+                })
+                .collect(),
+        }
+    }
+
+    /// Chains created with two channels only
+    pub fn process_frame(&mut self, frame: [f32; 2], bpm: f32) -> [f32; 2] {
+        let mut frame = frame;
+        for slot in self.slots.iter_mut().filter(|slot| slot.active) {
+            for (channel, value) in frame.iter_mut().enumerate() {
+                *value = slot.effect.process(*value, channel, bpm);
+            }
+        }
+        frame
+    }
+}
+
+fn mix(dry: f32, wet: f32, mix: f32) -> f32 {
+    dry + (wet - dry) * mix
+}
+
+#[cfg(test)]
+mod effects_tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 1000;
+
+    #[test]
+    fn test_inactive_chain_is_transparent() {
+        let mut chain = EffectChain::new(SAMPLE_RATE, 2);
+        for i in 0..100 {
+            let input = [(i as f32 * 0.1).sin(), (i as f32 * 0.2).cos()];
+            assert_eq!(chain.process_frame(input, 120.0), input);
+        }
+    }
+
+    #[test]
+    fn test_delay_follows_tempo() {
+        let mut delay = Delay::new(SAMPLE_RATE, 1);
+        delay.steps = 1;
+        delay.feedback = 0.0;
+        delay.mix = 1.0;
+
+        // A step at 120 bpm is 125 ms, 125 samples at this rate
+        let output: Vec<f32> = (0..200)
+            .map(|i| delay.process(if i == 0 { 1.0 } else { 0.0 }, 0, 120.0))
+            .collect();
+        let peak = output
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .unwrap()
+            .0;
+        assert_eq!(peak, 125);
+    }
+}
@@ -0,0 +1,34 @@
+use super::mix;
+
+/// Reduces the bit depth and holds every sample for `downsample` samples
+pub struct Bitcrusher {
+    pub bits: u32,
+    pub downsample: u32,
+    pub mix: f32,
+    held: Vec<f32>,
+    counter: Vec<u32>,
+}
+
+impl Bitcrusher {
+    pub fn new(channel_count: usize) -> Self {
+        Self {
+            bits: 6,
+            downsample: 4,
+            mix: 1.0,
+            held: vec![0.0; channel_count],
+            counter: vec![0; channel_count],
+        }
+    }
+
+    pub fn process(&mut self, input: f32, channel: usize) -> f32 {
+        let held = &mut self.held[channel];
+        let counter = &mut self.counter[channel];
+        if *counter == 0 {
+            let levels = (1 << (self.bits.clamp(1, 16) - 1)) as f32;
+            *held = (input * levels).round() / levels;
+        }
+        *counter = (*counter + 1) % self.downsample.max(1);
+
+        mix(input, *held, self.mix)
+    }
+}
@@ -0,0 +1,48 @@
+use super::mix;
+use crate::audio::transport::{MIN_BPM, STEPS_PER_BEAT};
+
+pub const MAX_STEPS: u32 = 8;
+
+/// Feedback delay synced to the transport, the time is a number of steps
+pub struct Delay {
+    pub steps: u32,
+    pub feedback: f32,
+    pub mix: f32,
+    sample_rate: f32,
+    buffers: Vec<Vec<f32>>,
+    write_index: Vec<usize>,
+}
+
+impl Delay {
+    pub fn new(sample_rate: u32, channel_count: usize) -> Self {
+        // Long enough for the longest delay at the slowest tempo
+        let max_time = MAX_STEPS as f32 * 60.0 / (MIN_BPM * STEPS_PER_BEAT as f32);
+        let length = (max_time * sample_rate as f32) as usize + 1;
+
+        Self {
+            steps: 3,
+            feedback: 0.4,
+            mix: 0.3,
+            sample_rate: sample_rate as f32,
+            buffers: vec![vec![0.0; length]; channel_count],
+            write_index: vec![0; channel_count],
+        }
+    }
+
+    pub fn process(&mut self, input: f32, channel: usize, bpm: f32) -> f32 {
+        let buffer = &mut self.buffers[channel];
+        let write_index = &mut self.write_index[channel];
+        let step_time = 60.0 / (bpm * STEPS_PER_BEAT as f32);
+        let delay_samples =
+            (self.steps.clamp(1, MAX_STEPS) as f32 * step_time * self.sample_rate).round() as usize;
+        let length = buffer.len();
+        let delay_samples = delay_samples.clamp(1, length - 1);
+
+        let read_index = (*write_index + length - delay_samples) % length;
+        let delayed = buffer[read_index];
+        buffer[*write_index] = input + delayed * self.feedback.clamp(0.0, 0.95);
+        *write_index = (*write_index + 1) % length;
+
+        mix(input, delayed, self.mix)
+    }
+}
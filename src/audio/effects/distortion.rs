@@ -0,0 +1,23 @@
+use super::mix;
+
+/// Tanh waveshaper, the output is scaled back so a full-scale input stays at full scale
+pub struct Distortion {
+    pub drive: f32,
+    pub mix: f32,
+}
+
+impl Distortion {
+    pub fn new() -> Self {
+        Self {
+            drive: 4.0,
+            mix: 1.0,
+        }
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        let drive = self.drive.max(1.0);
+        let wet = (input * drive).tanh() / drive.tanh();
+
+        mix(input, wet, self.mix)
+    }
+}
@@ -0,0 +1,114 @@
+use super::mix;
+
+/// Comb and allpass lengths from Freeverb, tuned for 44.1 kHz
+const COMB_LENGTHS: [usize; 4] = [1116, 1188, 1277, 1356];
+const ALLPASS_LENGTHS: [usize; 2] = [556, 441];
+const ALLPASS_FEEDBACK: f32 = 0.5;
+/// Extra length for every other channel so left and right decorrelate
+const STEREO_SPREAD: usize = 23;
+
+/// Small Schroeder reverb, four damped combs in parallel into two allpasses
+pub struct Reverb {
+    pub room_size: f32,
+    pub damping: f32,
+    pub mix: f32,
+    channels: Vec<ReverbChannel>,
+}
+
+struct ReverbChannel {
+    combs: Vec<Comb>,
+    allpasses: Vec<Allpass>,
+}
+
+impl Reverb {
+    pub fn new(sample_rate: u32, channel_count: usize) -> Self {
+        let scale = sample_rate as f32 / 44100.0;
+        let scaled = |length: usize| ((length as f32 * scale) as usize).max(1);
+
+        Self {
+            room_size: 0.5,
+            damping: 0.5,
+            mix: 0.25,
+            channels: (0..channel_count)
+                .map(|channel| {
+                    let spread = (channel % 2) * STEREO_SPREAD;
+                    ReverbChannel {
+                        combs: COMB_LENGTHS
+                            .iter()
+                            .map(|length| Comb::new(scaled(length + spread)))
+                            .collect(),
+                        allpasses: ALLPASS_LENGTHS
+                            .iter()
+                            .map(|length| Allpass::new(scaled(length + spread)))
+                            .collect(),
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    pub fn process(&mut self, input: f32, channel: usize) -> f32 {
+        let channel = &mut self.channels[channel];
+        let feedback = 0.7 + self.room_size.clamp(0.0, 1.0) * 0.28;
+        let damping = self.damping.clamp(0.0, 1.0);
+
+        let mut wet = 0.0;
+        for comb in &mut channel.combs {
+            wet += comb.process(input, feedback, damping);
+        }
+        wet /= channel.combs.len() as f32;
+        for allpass in &mut channel.allpasses {
+            wet = allpass.process(wet);
+        }
+
+        mix(input, wet, self.mix)
+    }
+}
+
+struct Comb {
+    buffer: Vec<f32>,
+    index: usize,
+    filter_state: f32,
+}
+
+impl Comb {
+    fn new(length: usize) -> Self {
+        Self {
+            buffer: vec![0.0; length],
+            index: 0,
+            filter_state: 0.0,
+        }
+    }
+
+    fn process(&mut self, input: f32, feedback: f32, damping: f32) -> f32 {
+        let output = self.buffer[self.index];
+        self.filter_state = output + (self.filter_state - output) * damping;
+        self.buffer[self.index] = input + self.filter_state * feedback;
+        self.index = (self.index + 1) % self.buffer.len();
+
+        output
+    }
+}
+
+struct Allpass {
+    buffer: Vec<f32>,
+    index: usize,
+}
+
+impl Allpass {
+    fn new(length: usize) -> Self {
+        Self {
+            buffer: vec![0.0; length],
+            index: 0,
+        }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let buffered = self.buffer[self.index];
+        let output = buffered - input;
+        self.buffer[self.index] = input + buffered * ALLPASS_FEEDBACK;
+        self.index = (self.index + 1) % self.buffer.len();
+
+        output
+    }
+}
@@ -1,80 +1,110 @@
+/// Gated ADSR envelope, times are in seconds and levels are in 0.0..=1.0
+///
+/// Every stage moves from the current level, so retriggering during a release or changing the
+/// levels while a note is held never jumps.
 pub struct Envelope {
-    time: f32,
+    level: f32,
+    release_rate: f32,
     state: EnvelopeState,
     pub attack: f32,
     pub decay: f32,
-    pub sustain: f32,
+    pub sustain_level: f32,
     pub release: f32,
+    pub peak_level: f32,
 }
 
 impl Envelope {
-    pub fn new(attack: f32, decay: f32, sustain: f32, release: f32) -> Self {
+    pub fn new(attack: f32, decay: f32, sustain_level: f32, release: f32) -> Self {
         Self {
-            time: 0.0,
-            state: EnvelopeState::Attack,
+            level: 0.0,
+            release_rate: 0.0,
+            state: EnvelopeState::None,
             attack,
             decay,
-            sustain,
+            sustain_level,
             release,
+            peak_level: 1.0,
         }
     }
 
+    /// Note on, attack starts from the current level
+    pub fn gate_on(&mut self) {
+        self.state = EnvelopeState::Attack;
+    }
+
+    /// Note off, release starts from the current level
+    pub fn gate_off(&mut self) {
+        if self.state == EnvelopeState::None || self.state == EnvelopeState::Release {
+            return;
+        }
+        self.state = EnvelopeState::Release;
+        self.release_rate = if self.release > 0.0 {
+            self.level / self.release
+        } else {
+            f32::INFINITY
+        };
+    }
+
+    /// Done releasing, or never started
+    pub fn is_idle(&self) -> bool {
+        self.state == EnvelopeState::None
+    }
+
     pub fn update(&mut self, delta_time: f32) -> f32 {
-        self.time += delta_time;
+        let peak_level = self.peak_level.clamp(0.0, 1.0);
+        let sustain_level = self.sustain_level.clamp(0.0, peak_level);
 
         match self.state {
             EnvelopeState::Attack => {
-                if self.time >= self.attack {
+                let rate = rate(peak_level, self.attack);
+                self.level = approach(self.level, peak_level, rate * delta_time);
+                if self.level >= peak_level {
                     self.state = EnvelopeState::Decay;
-                    self.time = 0.0;
-                    1.0
-                } else {
-                    lerp(self.time / self.attack, 0.0, 1.0)
                 }
             }
             EnvelopeState::Decay => {
-                if self.time >= self.decay {
+                let rate = rate(peak_level - sustain_level, self.decay);
+                self.level = approach(self.level, sustain_level, rate * delta_time);
+                if self.level == sustain_level {
                     self.state = EnvelopeState::Sustain;
-                    self.time = 0.0;
-                    0.6
-                } else {
-                    lerp(self.time / self.decay, 1.0, 0.6)
                 }
             }
             EnvelopeState::Sustain => {
-                if self.time >= self.sustain {
-                    self.state = EnvelopeState::Release;
-                    self.time = 0.0;
-                    0.5
-                } else {
-                    lerp(self.time / self.sustain, 0.6, 0.5)
-                }
+                // Follows sustain level changes at the decay rate while the gate is held
+                let rate = rate(peak_level - sustain_level, self.decay);
+                self.level = approach(self.level, sustain_level, rate * delta_time);
             }
             EnvelopeState::Release => {
-                if self.time >= self.release {
+                self.level = approach(self.level, 0.0, self.release_rate * delta_time);
+                if self.level <= 0.0 {
                     self.state = EnvelopeState::None;
-                    self.time = 0.0;
-                    0.0
-                } else {
-                    lerp(self.time / self.release, 0.5, 0.0)
                 }
             }
-            EnvelopeState::None => 0.0,
+            EnvelopeState::None => self.level = 0.0,
         }
+
+        self.level
     }
+}
 
-    // Obsolete
-    pub fn reset(&mut self) {
-        self.time = 0.0;
-        self.state = EnvelopeState::Attack;
+/// Level change per second to cover `distance` in `duration` seconds
+fn rate(distance: f32, duration: f32) -> f32 {
+    if duration > 0.0 {
+        distance.max(f32::EPSILON) / duration
+    } else {
+        f32::INFINITY
     }
 }
 
-fn lerp(t: f32, a: f32, b: f32) -> f32 {
-    let t = t.clamp(0.0, 1.0);
-    return a + t * (b - a);
+fn approach(value: f32, target: f32, step: f32) -> f32 {
+    if value < target {
+        (value + step).min(target)
+    } else {
+        (value - step).max(target)
+    }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 enum EnvelopeState {
     Attack,
     Decay,
@@ -82,3 +112,48 @@ enum EnvelopeState {
     Release,
     None,
 }
+
+#[cfg(test)]
+mod envelope_tests {
+    use super::*;
+
+    const DELTA_TIME: f32 = 1.0 / 1000.0;
+
+    fn run(envelope: &mut Envelope, seconds: f32) -> Vec<f32> {
+        let steps = (seconds / DELTA_TIME) as usize;
+        (0..steps).map(|_| envelope.update(DELTA_TIME)).collect()
+    }
+
+    fn max_jump(levels: &[f32]) -> f32 {
+        levels
+            .windows(2)
+            .map(|w| (w[1] - w[0]).abs())
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn test_sustain_holds_until_gate_off() {
+        let mut envelope = Envelope::new(0.01, 0.01, 0.4, 0.1);
+        envelope.gate_on();
+        let levels = run(&mut envelope, 1.0);
+        assert_eq!(*levels.last().unwrap(), 0.4);
+
+        envelope.gate_off();
+        let levels = run(&mut envelope, 0.2);
+        assert_eq!(*levels.last().unwrap(), 0.0);
+    }
+
+    #[test]
+    fn test_retrigger_is_continuous() {
+        let mut envelope = Envelope::new(0.05, 0.05, 0.5, 0.05);
+        envelope.gate_on();
+        let mut levels = run(&mut envelope, 0.02);
+        envelope.gate_off();
+        levels.extend(run(&mut envelope, 0.01));
+        envelope.gate_on();
+        levels.extend(run(&mut envelope, 0.2));
+
+        // Slowest full-scale slope is 1.0 / 0.05 per second
+        assert!(max_jump(&levels) <= DELTA_TIME / 0.05 + f32::EPSILON);
+    }
+}
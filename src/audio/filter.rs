@@ -0,0 +1,134 @@
+/// Two-pole state-variable filter in the trapezoidal form, stable while the cutoff is modulated
+/// every sample
+pub struct Filter {
+    pub filter_type: FilterType,
+    /// Hz before envelope modulation
+    pub cutoff: f32,
+    /// 0.0..=1.0, self-oscillation is never reached
+    pub resonance: f32,
+    /// Octaves the cutoff moves at full filter envelope, can be negative
+    pub envelope_amount: f32,
+    sample_rate: f32,
+    ic1eq: f32,
+    ic2eq: f32,
+}
+
+pub const MIN_CUTOFF: f32 = 20.0;
+pub const MAX_CUTOFF: f32 = 20000.0;
+pub const MAX_ENVELOPE_AMOUNT: f32 = 6.0;
+
+impl Filter {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            filter_type: FilterType::LowPass,
+            cutoff: MAX_CUTOFF,
+            resonance: 0.0,
+            envelope_amount: 0.0,
+            sample_rate: sample_rate as f32,
+            ic1eq: 0.0,
+            ic2eq: 0.0,
+        }
+    }
+
+    /// `envelope` is the filter envelope level in 0.0..=1.0, `modulation` moves the cutoff by
+    /// that many octaves on top of it
+    pub fn process(&mut self, input: f32, envelope: f32, modulation: f32) -> f32 {
+        let octaves = self.envelope_amount * envelope + modulation;
+        let cutoff = self.cutoff * 2.0_f32.powf(octaves);
+        let cutoff = cutoff.clamp(MIN_CUTOFF, self.sample_rate * 0.45);
+
+        let g = (std::f32::consts::PI * cutoff / self.sample_rate).tan();
+        let k = 2.0 - 1.96 * self.resonance.clamp(0.0, 1.0);
+        let a1 = 1.0 / (1.0 + g * (g + k));
+        let a2 = g * a1;
+        let a3 = g * a2;
+
+        let v3 = input - self.ic2eq;
+        let v1 = a1 * self.ic1eq + a2 * v3;
+        let v2 = self.ic2eq + a2 * self.ic1eq + a3 * v3;
+        self.ic1eq = 2.0 * v1 - self.ic1eq;
+        self.ic2eq = 2.0 * v2 - self.ic2eq;
+
+        match self.filter_type {
+            FilterType::LowPass => v2,
+            FilterType::HighPass => input - k * v1 - v2,
+            FilterType::BandPass => v1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterType {
+    LowPass,
+    HighPass,
+    BandPass,
+}
+
+impl FilterType {
+    pub fn to_u8(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(FilterType::LowPass),
+            1 => Some(FilterType::HighPass),
+            2 => Some(FilterType::BandPass),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod filter_tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 44100;
+
+    /// Peak output level for a sine at `frequency` after the filter settles
+    fn response(filter: &mut Filter, frequency: f32) -> f32 {
+        let mut peak: f32 = 0.0;
+        for i in 0..SAMPLE_RATE as usize / 2 {
+            let t = i as f32 / SAMPLE_RATE as f32;
+            let output = filter.process((std::f32::consts::TAU * frequency * t).sin(), 0.0, 0.0);
+            if i > SAMPLE_RATE as usize / 4 {
+                peak = peak.max(output.abs());
+            }
+        }
+        peak
+    }
+
+    #[test]
+    fn test_low_pass() {
+        let mut filter = Filter::new(SAMPLE_RATE);
+        filter.cutoff = 500.0;
+        assert!(response(&mut filter, 100.0) > 0.9);
+        assert!(response(&mut filter, 5000.0) < 0.05);
+    }
+
+    #[test]
+    fn test_high_pass() {
+        let mut filter = Filter::new(SAMPLE_RATE);
+        filter.filter_type = FilterType::HighPass;
+        filter.cutoff = 2000.0;
+        assert!(response(&mut filter, 100.0) < 0.05);
+        assert!(response(&mut filter, 10000.0) > 0.9);
+    }
+
+    #[test]
+    fn test_envelope_opens_filter() {
+        let mut filter = Filter::new(SAMPLE_RATE);
+        filter.cutoff = 100.0;
+        filter.envelope_amount = 5.0;
+        // Fully open envelope puts the cutoff at 3200 Hz
+        let mut peak: f32 = 0.0;
+        for i in 0..SAMPLE_RATE as usize / 2 {
+            let t = i as f32 / SAMPLE_RATE as f32;
+            let output = filter.process((std::f32::consts::TAU * 1000.0 * t).sin(), 1.0, 0.0);
+            if i > SAMPLE_RATE as usize / 4 {
+                peak = peak.max(output.abs());
+            }
+        }
+        assert!(peak > 0.9);
+    }
+}
@@ -0,0 +1,111 @@
+use super::oscillator_type::OscillatorType;
+use kopek::oscillator::WaveType;
+use std::f32::consts::TAU;
+
+pub const MIN_RATIO: f32 = 0.25;
+pub const MAX_RATIO: f32 = 16.0;
+/// Peak phase deviation of the carrier in radians
+pub const MAX_INDEX: f32 = 10.0;
+/// Phase deviation of the modulator at full feedback, past this it turns into noise
+const MAX_FEEDBACK: f32 = 1.5;
+
+/// Two sine operators, the modulator moves the phase of the carrier and can modulate itself.
+/// The index and the feedback are lowered as the note gets higher so the sidebands stay below
+/// Nyquist.
+pub struct FmOscillator {
+    /// Modulator frequency as a multiple of the carrier frequency
+    pub ratio: f32,
+    pub index: f32,
+    /// 0.0..=1.0
+    pub feedback: f32,
+    frequency: f32,
+    sample_rate: f32,
+    carrier_phase: f32,
+    modulator_phase: f32,
+    /// Last two modulator outputs, averaged to keep the feedback loop from oscillating
+    previous: [f32; 2],
+}
+
+impl FmOscillator {
+    /// Index and feedback that keep the sidebands below Nyquist at the current frequency
+    fn limits(&self) -> (f32, f32) {
+        let nyquist = self.sample_rate * 0.5;
+        let modulator_frequency = (self.frequency * self.ratio).max(1.0);
+        // Carson's rule, nearly all the energy is within (index + 1) modulator frequencies of
+        // the carrier
+        let max_index = ((nyquist - self.frequency) / modulator_frequency - 1.0).max(0.0);
+        // Feedback adds harmonics of the modulator, roughly eight of them at full feedback
+        let max_feedback = (nyquist / (modulator_frequency * 8.0)).min(1.0);
+        (
+            self.index.clamp(0.0, MAX_INDEX).min(max_index),
+            self.feedback.clamp(0.0, 1.0) * max_feedback,
+        )
+    }
+}
+
+impl OscillatorType for FmOscillator {
+    fn new(sample_rate: f32) -> Self {
+        Self {
+            ratio: 1.0,
+            index: 1.0,
+            feedback: 0.0,
+            frequency: 440.0,
+            sample_rate,
+            carrier_phase: 0.0,
+            modulator_phase: 0.0,
+            previous: [0.0; 2],
+        }
+    }
+
+    fn frequency(&self) -> f32 {
+        self.frequency
+    }
+
+    fn set_frequency(&mut self, frequency: f32) {
+        self.frequency = frequency;
+    }
+
+    /// Both operators are sines
+    fn wave_type(&self) -> WaveType {
+        WaveType::Sine
+    }
+
+    fn set_wave_type(&mut self, _wave_type: WaveType) {}
+
+    fn run(&mut self) -> f32 {
+        let (index, feedback) = self.limits();
+        let feedback_phase = (self.previous[0] + self.previous[1]) * 0.5 * feedback * MAX_FEEDBACK;
+        let modulator = (TAU * self.modulator_phase + feedback_phase).sin();
+        self.previous = [modulator, self.previous[0]];
+        let value = (TAU * self.carrier_phase + index * modulator).sin();
+
+        let ratio = self.ratio.clamp(MIN_RATIO, MAX_RATIO);
+        self.carrier_phase = (self.carrier_phase + self.frequency / self.sample_rate).fract();
+        self.modulator_phase =
+            (self.modulator_phase + self.frequency * ratio / self.sample_rate).fract();
+
+        value
+    }
+}
+
+#[cfg(test)]
+mod fm_oscillator_tests {
+    use super::*;
+
+    #[test]
+    fn test_index_falls_with_pitch() {
+        let mut fm = FmOscillator::new(44100.0);
+        fm.index = MAX_INDEX;
+        fm.feedback = 1.0;
+        fm.set_frequency(110.0);
+        assert_eq!(fm.limits(), (MAX_INDEX, 1.0));
+
+        fm.set_frequency(5000.0);
+        let (index, feedback) = fm.limits();
+        assert!(index < 4.0 && feedback < 1.0);
+        // Too high for any sidebands, a plain sine is left
+        fm.set_frequency(15000.0);
+        assert_eq!(fm.limits().0, 0.0);
+        assert!((0..1000).all(|_| fm.run().abs() <= 1.0));
+    }
+}
@@ -0,0 +1,167 @@
+//! Microphone or line-in as a track of the engine.
+//!
+//! `CpalInput` fills a ring buffer at the device's rate, the audio thread reads it here one
+//! sample at a time and resamples it to the engine's rate. The input comes muted so an open
+//! microphone doesn't feed back through the speakers before anyone asks for it.
+
+use super::mixer::{equal_power, ChannelStrip, LevelMeter};
+use hound::{SampleFormat, WavSpec, WavWriter};
+use ringbuf::HeapConsumer;
+
+/// Input that has piled up beyond this is skipped, in seconds
+const MAX_LATENCY: f32 = 0.05;
+
+struct Source {
+    consumer: HeapConsumer<f32>,
+    /// Input samples per engine sample
+    step: f32,
+    max_buffered: usize,
+    previous: f32,
+    current: f32,
+    phase: f32,
+}
+
+pub struct LiveInput {
+    pub strip: ChannelStrip,
+    pub meter: LevelMeter,
+    source: Option<Source>,
+    sample_rate: u32,
+    /// Last sample before the channel strip
+    sample: f32,
+}
+
+impl LiveInput {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            strip: ChannelStrip {
+                mute: true,
+                ..ChannelStrip::default()
+            },
+            meter: LevelMeter::new(sample_rate),
+            source: None,
+            sample_rate,
+            sample: 0.0,
+        }
+    }
+
+    /// Starts reading `consumer`, which is filled at `input_rate`
+    pub fn connect(&mut self, consumer: HeapConsumer<f32>, input_rate: u32) {
+        self.source = Some(Source {
+            consumer,
+            step: input_rate as f32 / self.sample_rate as f32,
+            max_buffered: (input_rate as f32 * MAX_LATENCY) as usize,
+            previous: 0.0,
+            current: 0.0,
+            phase: 0.0,
+        });
+    }
+
+    pub fn disconnect(&mut self) {
+        self.source = None;
+        self.sample = 0.0;
+    }
+
+    /// Mono input of the last `run`, before gain and pan
+    pub fn sample(&self) -> f32 {
+        self.sample
+    }
+
+    /// Reads the next sample, silence when disconnected or when the device is behind
+    pub fn run(&mut self) -> f32 {
+        let Some(source) = &mut self.source else {
+            return 0.0;
+        };
+        if source.consumer.len() > source.max_buffered {
+            let excess = source.consumer.len() - source.max_buffered;
+            source.consumer.skip(excess);
+        }
+        source.phase += source.step;
+        while source.phase >= 1.0 {
+            source.previous = source.current;
+            source.current = source.consumer.pop().unwrap_or(0.0);
+            source.phase -= 1.0;
+        }
+        self.sample = source.previous + (source.current - source.previous) * source.phase;
+        self.sample
+    }
+
+    /// Stereo frame after the channel strip, the input is mono so it is panned with equal power
+    pub fn process(&mut self, is_any_solo: bool) -> [f32; 2] {
+        let sample = self.run();
+        if !self.strip.is_audible(is_any_solo) {
+            self.meter.process([0.0, 0.0]);
+            return [0.0, 0.0];
+        }
+        let frame = equal_power(sample * self.strip.gain, self.strip.pan);
+        self.meter.process(frame);
+        frame
+    }
+}
+
+/// Writes a mono recording of the input as 32-bit float
+pub fn write_recording(path: &str, samples: &[f32], sample_rate: u32) -> Result<(), hound::Error> {
+    let spec = WavSpec {
+        channels: 1,
+        sample_rate,
+        bits_per_sample: 32,
+        sample_format: SampleFormat::Float,
+    };
+    let mut writer = WavWriter::create(path, spec)?;
+    for &sample in samples {
+        writer.write_sample(sample)?;
+    }
+    writer.finalize()
+}
+
+#[cfg(test)]
+mod live_input_tests {
+    use super::*;
+    use ringbuf::HeapRb;
+
+    const SAMPLE_RATE: u32 = 1000;
+
+    #[test]
+    fn test_silent_and_muted_by_default() {
+        let mut live_input = LiveInput::new(SAMPLE_RATE);
+        assert_eq!(live_input.run(), 0.0);
+
+        let (mut producer, consumer) = HeapRb::new(64).split();
+        live_input.connect(consumer, SAMPLE_RATE);
+        producer.push_slice(&[1.0; 8]);
+        for _ in 0..2 {
+            assert_eq!(live_input.process(false), [0.0, 0.0]);
+        }
+        assert_eq!(live_input.sample(), 1.0);
+        assert_eq!(live_input.meter.level(), 0.0);
+    }
+
+    #[test]
+    fn test_resamples_to_engine_rate() {
+        let mut live_input = LiveInput::new(SAMPLE_RATE);
+        let (mut producer, consumer) = HeapRb::new(64).split();
+        // Twice the engine's rate, every other input sample is heard one sample late
+        live_input.connect(consumer, SAMPLE_RATE * 2);
+        producer.push_slice(&[0.0, 1.0, 2.0, 3.0, 4.0, 5.0]);
+        let samples: Vec<f32> = (0..3).map(|_| live_input.run()).collect();
+        assert_eq!(samples, [0.0, 2.0, 4.0]);
+
+        // Half the rate, the missing samples are interpolated
+        let (mut producer, consumer) = HeapRb::new(64).split();
+        live_input.connect(consumer, SAMPLE_RATE / 2);
+        producer.push_slice(&[2.0, 4.0]);
+        let samples: Vec<f32> = (0..6).map(|_| live_input.run()).collect();
+        assert_eq!(samples, [0.0, 0.0, 1.0, 2.0, 3.0, 4.0]);
+    }
+
+    #[test]
+    fn test_skips_excess_latency() {
+        let mut live_input = LiveInput::new(SAMPLE_RATE);
+        let (mut producer, consumer) = HeapRb::new(256).split();
+        live_input.connect(consumer, SAMPLE_RATE);
+        let samples: Vec<f32> = (0..200).map(|i| i as f32).collect();
+        producer.push_slice(&samples);
+        // 50 ms are kept, the oldest samples are dropped
+        live_input.run();
+        assert_eq!(live_input.run(), 150.0);
+    }
+}
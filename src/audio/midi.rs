@@ -0,0 +1,566 @@
+//! Standard MIDI File import onto the step grid and export of whole songs.
+//!
+//! Octave 1 of `kopek::utils::Octave` is taken to be the scientific octave, so C1 is MIDI note
+//! 24 and the five supported octaves cover notes 24 to 83.
+
+use super::{
+    arpeggiator::ChordType,
+    arrangement::Section,
+    sequencer::MAX_LENGTH,
+    song::{int_to_key, int_to_octave, to_note_data, NoteData, Song, TrackData},
+    step::Step,
+    transport::{BEATS_PER_BAR, STEPS_PER_BAR, STEPS_PER_BEAT},
+};
+use kopek::utils::{Key, Note};
+use midly::{
+    num::{u15, u24, u28, u4, u7},
+    Format, Header, MetaMessage, MidiMessage, Smf, Timing, TrackEvent, TrackEventKind,
+};
+use std::fmt;
+
+pub const LOWEST_NOTE: u8 = 24;
+pub const HIGHEST_NOTE: u8 = LOWEST_NOTE + 5 * 12 - 1;
+/// Shortest gate an imported note gets, same as the sequencer window allows
+const MIN_GATE: f32 = 0.05;
+/// Resolution of exported files, enough for gates down to `MIN_GATE`
+const EXPORT_TICKS_PER_BEAT: u16 = 96;
+const CHANNEL_COUNT: usize = 16;
+
+/// Which notes of the file end up in the pattern
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MidiSource {
+    Track(usize),
+    Channel(u8),
+}
+
+pub struct MidiImport {
+    pub steps: Vec<Step>,
+    /// First tempo in the file, if there is one
+    pub bpm: Option<f32>,
+    pub imported: usize,
+    pub issues: Vec<NoteIssue>,
+}
+
+/// A note that didn't make it into the pattern as it was written
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NoteIssue {
+    pub key: u8,
+    pub step: usize,
+    pub reason: IssueReason,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IssueReason {
+    /// Moved by whole octaves into the supported range
+    Clamped,
+    /// Another note already starts on the same step
+    Overlapping,
+    /// Starts after the longest possible pattern
+    PastEnd,
+}
+
+impl fmt::Display for NoteIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self.reason {
+            IssueReason::Clamped => "clamped into the octave range",
+            IssueReason::Overlapping => "dropped, overlaps another note",
+            IssueReason::PastEnd => "dropped, past the last step",
+        };
+        write!(f, "note {} at step {}: {}", self.key, self.step, reason)
+    }
+}
+
+#[derive(Debug)]
+pub enum MidiError {
+    Io(std::io::Error),
+    Parse(midly::Error),
+    /// Only files timed in ticks per beat can be put on a grid
+    TimecodeTiming,
+    NoSuchTrack(usize),
+    NoNotes,
+}
+
+impl fmt::Display for MidiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MidiError::Io(e) => write!(f, "{e}"),
+            MidiError::Parse(e) => write!(f, "{e}"),
+            MidiError::TimecodeTiming => write!(f, "timecode based midi files are not supported"),
+            MidiError::NoSuchTrack(track) => write!(f, "there is no track {track}"),
+            MidiError::NoNotes => write!(f, "no notes found"),
+        }
+    }
+}
+
+pub fn import_file(path: &str, source: MidiSource) -> Result<MidiImport, MidiError> {
+    let bytes = std::fs::read(path).map_err(MidiError::Io)?;
+    import(&bytes, source)
+}
+
+/// Quantizes the notes of `source` to the nearest step, monophonic and at most `MAX_LENGTH` steps
+pub fn import(bytes: &[u8], source: MidiSource) -> Result<MidiImport, MidiError> {
+    let smf = Smf::parse(bytes).map_err(MidiError::Parse)?;
+    let ticks_per_beat = match smf.header.timing {
+        Timing::Metrical(ticks) => ticks.as_int() as f64,
+        Timing::Timecode(..) => return Err(MidiError::TimecodeTiming),
+    };
+    let ticks_per_step = ticks_per_beat / STEPS_PER_BEAT as f64;
+
+    let tracks: Vec<_> = match source {
+        MidiSource::Track(index) => {
+            vec![smf.tracks.get(index).ok_or(MidiError::NoSuchTrack(index))?]
+        }
+        MidiSource::Channel(_) => smf.tracks.iter().collect(),
+    };
+
+    let mut bpm = None;
+    let mut notes = Vec::new();
+    for track in smf.tracks.iter() {
+        if bpm.is_some() {
+            break;
+        }
+        for event in track {
+            if let TrackEventKind::Meta(MetaMessage::Tempo(tempo)) = event.kind {
+                bpm = Some(60_000_000.0 / tempo.as_int() as f32);
+                break;
+            }
+        }
+    }
+    for track in tracks {
+        notes.extend(track_notes(track, source));
+    }
+    if notes.is_empty() {
+        return Err(MidiError::NoNotes);
+    }
+    // Earlier notes first, the highest one wins when several start together
+    notes.sort_by(|a, b| a.start.cmp(&b.start).then(b.key.cmp(&a.key)));
+
+    let mut steps = vec![Step::default(); MAX_LENGTH];
+    let mut is_taken = [false; MAX_LENGTH];
+    let mut length = 0;
+    let mut imported = 0;
+    let mut issues = Vec::new();
+    for note in notes {
+        let step = (note.start as f64 / ticks_per_step).round() as usize;
+        let mut issue = |reason| {
+            issues.push(NoteIssue {
+                key: note.key,
+                step,
+                reason,
+            })
+        };
+        if step >= MAX_LENGTH {
+            issue(IssueReason::PastEnd);
+            continue;
+        }
+        if is_taken[step] {
+            issue(IssueReason::Overlapping);
+            continue;
+        }
+        let (midi_note, is_clamped) = clamp_note(note.key);
+        if is_clamped {
+            issue(IssueReason::Clamped);
+        }
+
+        let duration = (note.end - note.start) as f64 / ticks_per_step;
+        steps[step] = Step {
+            note: midi_to_note(midi_note),
+            velocity: note.velocity as f32 / 127.0,
+            gate: (duration as f32).clamp(MIN_GATE, 1.0),
+            ..Step::default()
+        };
+        is_taken[step] = true;
+        length = length.max(step + 1);
+        imported += 1;
+    }
+
+    // Whole bars so the pattern loops the way it was written
+    let bar = STEPS_PER_BAR as usize;
+    steps.truncate((length.div_ceil(bar) * bar).clamp(1, MAX_LENGTH));
+
+    Ok(MidiImport {
+        steps,
+        bpm,
+        imported,
+        issues,
+    })
+}
+
+pub fn export_file(path: &str, song: &Song) -> Result<(), MidiError> {
+    std::fs::write(path, export(song)).map_err(MidiError::Io)
+}
+
+/// Writes a type 1 file with one track per sequencer, track `n` plays on channel `n` so the file
+/// can be imported back by track or by channel. The first track also carries the tempo and the
+/// time signature. An active arrangement is written once from start to end, otherwise the first
+/// patterns are repeated until the longest one ends, the same way they loop against each other
+/// while playing. Swing and step probabilities are not written.
+pub fn export(song: &Song) -> Vec<u8> {
+    let ticks_per_step = (EXPORT_TICKS_PER_BEAT / STEPS_PER_BEAT as u16) as u32;
+    let sequences = song_sequences(song);
+    let length = sequences.iter().map(Vec::len).max().unwrap_or(0);
+    let names: Vec<String> = (0..song.tracks.len())
+        .map(|i| format!("sequencer {}", i + 1))
+        .collect();
+
+    let mut smf = Smf::new(Header::new(
+        Format::Parallel,
+        Timing::Metrical(u15::new(EXPORT_TICKS_PER_BEAT)),
+    ));
+    for (i, (sequence, name)) in sequences.iter().zip(&names).enumerate() {
+        let mut events = vec![meta_event(MetaMessage::TrackName(name.as_bytes()))];
+        if i == 0 {
+            let tempo = (60_000_000.0 / song.bpm).round() as u32;
+            events.push(meta_event(MetaMessage::Tempo(u24::new(tempo))));
+            events.push(meta_event(MetaMessage::TimeSignature(
+                BEATS_PER_BAR as u8,
+                2,
+                24,
+                8,
+            )));
+        }
+        let channel = u4::new((i % CHANNEL_COUNT) as u8);
+        let notes = sequence_notes(sequence, length, ticks_per_step);
+        events.extend(to_track_events(notes, channel));
+        let end_tick = length as u64 * ticks_per_step as u64;
+        let last_tick = last_event_tick(&events);
+        events.push(TrackEvent {
+            delta: u28::new(end_tick.saturating_sub(last_tick) as u32),
+            kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+        });
+        smf.tracks.push(events);
+    }
+
+    let mut bytes = Vec::new();
+    smf.write_std(&mut bytes)
+        .expect("writing to a vector can't fail");
+    bytes
+}
+
+/// MIDI note number of a note, rests have none
+pub fn note_to_midi(note: Note) -> Option<u8> {
+    if note.key == Key::Rest {
+        return None;
+    }
+    Some(LOWEST_NOTE + note.octave as u8 * 12 + note.key as u8)
+}
+
+/// Only notes between `LOWEST_NOTE` and `HIGHEST_NOTE`
+pub fn midi_to_note(midi_note: u8) -> Note {
+    let offset = midi_note.clamp(LOWEST_NOTE, HIGHEST_NOTE) - LOWEST_NOTE;
+    Note {
+        octave: int_to_octave((offset / 12) as i32),
+        key: int_to_key((offset % 12) as i32),
+    }
+}
+
+/// Moves notes outside the supported range by whole octaves so they keep their pitch class
+fn clamp_note(midi_note: u8) -> (u8, bool) {
+    let mut clamped = midi_note;
+    while clamped < LOWEST_NOTE {
+        clamped += 12;
+    }
+    while clamped > HIGHEST_NOTE {
+        clamped -= 12;
+    }
+    (clamped, clamped != midi_note)
+}
+
+struct MidiNote {
+    start: u64,
+    end: u64,
+    key: u8,
+    velocity: u8,
+}
+
+/// Patterns missing from a track fall back to its first one
+fn pattern(track: &TrackData, index: usize) -> &[NoteData] {
+    track
+        .patterns
+        .get(index)
+        .or(track.patterns.first())
+        .map_or(&[], Vec::as_slice)
+}
+
+/// Steps every track plays from the start of the song to its end
+fn song_sequences(song: &Song) -> Vec<Vec<NoteData>> {
+    let sections = &song.arrangement.sections;
+    if !song.arrangement.active || sections.is_empty() {
+        return song
+            .tracks
+            .iter()
+            .map(|track| pattern(track, 0).to_vec())
+            .collect();
+    }
+
+    let mut sequences = vec![Vec::new(); song.tracks.len()];
+    for section in sections.iter().map(Section::from) {
+        let longest = song
+            .tracks
+            .iter()
+            .enumerate()
+            .map(|(i, track)| pattern(track, section.pattern(i)).len())
+            .max()
+            .unwrap_or(0);
+        let steps = section.bars(longest) as usize * STEPS_PER_BAR as usize;
+        for (i, (track, sequence)) in song.tracks.iter().zip(&mut sequences).enumerate() {
+            let pattern = pattern(track, section.pattern(i));
+            if pattern.is_empty() {
+                let rest = to_note_data(&Step::default());
+                sequence.extend(std::iter::repeat(rest).take(steps));
+            } else {
+                sequence.extend(pattern.iter().cycle().take(steps).copied());
+            }
+        }
+    }
+    sequences
+}
+
+fn meta_event(message: MetaMessage) -> TrackEvent {
+    TrackEvent {
+        delta: u28::new(0),
+        kind: TrackEventKind::Meta(message),
+    }
+}
+
+/// Notes of a pattern repeated over `length` steps, sliding notes are held into the next step
+fn sequence_notes(sequence: &[NoteData], length: usize, ticks_per_step: u32) -> Vec<MidiNote> {
+    if sequence.is_empty() {
+        return Vec::new();
+    }
+    (0..length)
+        .flat_map(|step| {
+            let data = &sequence[step % sequence.len()];
+            let note = Note {
+                octave: int_to_octave(data.octave),
+                key: int_to_key(data.key),
+            };
+            let root = note_to_midi(note);
+            // Chords are written as played together, the arpeggiator isn't
+            let intervals = data
+                .chord
+                .and_then(ChordType::from_u8)
+                .map_or(&[0][..], ChordType::intervals);
+            let gate = if data.slide { 1.0 } else { data.gate };
+            let start = step as u64 * ticks_per_step as u64;
+            let duration = (gate.clamp(MIN_GATE, 1.0) * ticks_per_step as f32).round() as u64;
+            let velocity = (data.velocity * 127.0).round().clamp(1.0, 127.0) as u8;
+            intervals.iter().filter_map(move |&interval| {
+                Some(MidiNote {
+                    start,
+                    end: start + duration.max(1),
+                    key: root?.checked_add(interval).filter(|&key| key <= 127)?,
+                    velocity,
+                })
+            })
+        })
+        .collect()
+}
+
+fn to_track_events(notes: Vec<MidiNote>, channel: u4) -> Vec<TrackEvent<'static>> {
+    // Note offs sort before note ons on the same tick so repeated keys retrigger
+    let mut messages = Vec::with_capacity(notes.len() * 2);
+    for note in notes {
+        let key = u7::new(note.key);
+        messages.push((
+            note.start,
+            1,
+            MidiMessage::NoteOn {
+                key,
+                vel: u7::new(note.velocity),
+            },
+        ));
+        messages.push((
+            note.end,
+            0,
+            MidiMessage::NoteOff {
+                key,
+                vel: u7::new(0),
+            },
+        ));
+    }
+    messages.sort_by_key(|(tick, order, _)| (*tick, *order));
+
+    let mut tick = 0;
+    messages
+        .into_iter()
+        .map(|(event_tick, _, message)| {
+            let delta = event_tick - tick;
+            tick = event_tick;
+            TrackEvent {
+                delta: u28::new(delta as u32),
+                kind: TrackEventKind::Midi { channel, message },
+            }
+        })
+        .collect()
+}
+
+fn last_event_tick(events: &[TrackEvent]) -> u64 {
+    events.iter().map(|event| event.delta.as_int() as u64).sum()
+}
+
+fn track_notes(track: &[midly::TrackEvent], source: MidiSource) -> Vec<MidiNote> {
+    let mut notes = Vec::new();
+    // Start tick and velocity of the sounding notes by key
+    let mut held: [Option<(u64, u8)>; 128] = [None; 128];
+    let mut tick = 0;
+    for event in track {
+        tick += event.delta.as_int() as u64;
+        let TrackEventKind::Midi { channel, message } = event.kind else {
+            continue;
+        };
+        if let MidiSource::Channel(source_channel) = source {
+            if channel.as_int() != source_channel {
+                continue;
+            }
+        }
+        let (key, velocity) = match message {
+            MidiMessage::NoteOn { key, vel } => (key.as_int(), vel.as_int()),
+            MidiMessage::NoteOff { key, .. } => (key.as_int(), 0),
+            _ => continue,
+        };
+
+        if let Some((start, held_velocity)) = held[key as usize].take() {
+            notes.push(MidiNote {
+                start,
+                end: tick,
+                key,
+                velocity: held_velocity,
+            });
+        }
+        if velocity > 0 {
+            held[key as usize] = Some((tick, velocity));
+        }
+    }
+
+    notes
+}
+
+#[cfg(test)]
+mod midi_tests {
+    use super::*;
+    use crate::audio::{audio_engine::AudioEngine, song};
+    use kopek::utils::Octave;
+
+    const TICKS_PER_BEAT: u16 = 96;
+    const TICKS_PER_STEP: u32 = TICKS_PER_BEAT as u32 / STEPS_PER_BEAT;
+
+    fn note_event(delta: u32, channel: u8, key: u8, vel: u8) -> TrackEvent<'static> {
+        TrackEvent {
+            delta: u28::new(delta),
+            kind: TrackEventKind::Midi {
+                channel: u4::new(channel),
+                message: MidiMessage::NoteOn {
+                    key: u7::new(key),
+                    vel: u7::new(vel),
+                },
+            },
+        }
+    }
+
+    fn write(tracks: Vec<Vec<TrackEvent<'static>>>) -> Vec<u8> {
+        let mut smf = Smf::new(Header::new(
+            Format::Parallel,
+            Timing::Metrical(u15::new(TICKS_PER_BEAT)),
+        ));
+        smf.tracks = tracks;
+        let mut bytes = Vec::new();
+        smf.write_std(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_note_mapping() {
+        let note = midi_to_note(60);
+        assert_eq!(note.octave, Octave::Fourth);
+        assert_eq!(note.key, Key::C);
+        assert_eq!(note_to_midi(note), Some(60));
+    }
+
+    #[test]
+    fn test_import_quantizes_and_reports() {
+        let tempo = TrackEvent {
+            delta: u28::new(0),
+            kind: TrackEventKind::Meta(MetaMessage::Tempo(u24::new(500_000))),
+        };
+        let bytes = write(vec![vec![
+            tempo,
+            // C4 a little late for step 0, half a step long
+            note_event(2, 0, 60, 127),
+            note_event(TICKS_PER_STEP / 2, 0, 60, 0),
+            // Two notes on step 2, the higher one is kept
+            note_event(TICKS_PER_STEP * 2 - TICKS_PER_STEP / 2 - 2, 0, 62, 64),
+            note_event(0, 0, 64, 64),
+            note_event(TICKS_PER_STEP, 0, 62, 0),
+            note_event(0, 0, 64, 0),
+            // Too high, ends up two octaves lower
+            note_event(0, 0, 96, 100),
+            note_event(TICKS_PER_STEP, 0, 96, 0),
+            // Other channel
+            note_event(0, 1, 50, 100),
+            note_event(TICKS_PER_STEP, 1, 50, 0),
+        ]]);
+
+        let import = import(&bytes, MidiSource::Channel(0)).unwrap();
+        assert_eq!(import.bpm, Some(120.0));
+        assert_eq!(import.steps.len(), STEPS_PER_BAR as usize);
+        assert_eq!(import.imported, 3);
+        assert_eq!(note_to_midi(import.steps[0].note), Some(60));
+        assert!((import.steps[0].gate - 0.5).abs() < 0.01);
+        assert!(import.steps[1].is_rest());
+        assert_eq!(note_to_midi(import.steps[2].note), Some(64));
+        assert_eq!(note_to_midi(import.steps[3].note), Some(84 - 12));
+        assert_eq!(
+            import.issues.iter().map(|i| i.reason).collect::<Vec<_>>(),
+            vec![IssueReason::Overlapping, IssueReason::Clamped]
+        );
+    }
+
+    #[test]
+    fn test_export_round_trip() {
+        let mut audio_engine = AudioEngine::new(44100);
+        audio_engine.transport.set_bpm(90.0);
+        let mut song = song::to_song(&audio_engine);
+        song.tracks[0].patterns[0] = vec![
+            note_data(3, 0, 0.25, false),
+            note_data(3, 12, 0.5, false),
+            note_data(3, 7, 0.5, true),
+            note_data(4, 0, 0.75, false),
+        ];
+        song.tracks[1].patterns[0] = (0..32).map(|_| note_data(1, 9, 0.5, false)).collect();
+
+        let bytes = export(&song);
+        let smf = Smf::parse(&bytes).unwrap();
+        assert_eq!(smf.header.format, Format::Parallel);
+        assert_eq!(smf.tracks.len(), song.tracks.len());
+
+        let by_track = import(&bytes, MidiSource::Track(0)).unwrap();
+        assert_eq!(by_track.bpm.map(f32::round), Some(90.0));
+        assert!(by_track.issues.is_empty());
+        // The four steps repeat for as long as the 32 step pattern of the second track
+        assert_eq!(by_track.steps.len(), 32);
+        assert_eq!(by_track.imported, 24);
+        assert_eq!(note_to_midi(by_track.steps[0].note), Some(60));
+        assert!((by_track.steps[0].gate - 0.25).abs() < 0.01);
+        assert!(by_track.steps[1].is_rest());
+        assert_eq!(by_track.steps[2].gate, 1.0);
+        assert_eq!(note_to_midi(by_track.steps[7].note), Some(72));
+
+        let by_channel = import(&bytes, MidiSource::Channel(1)).unwrap();
+        assert_eq!(by_channel.imported, 32);
+        assert_eq!(note_to_midi(by_channel.steps[31].note), Some(45));
+    }
+
+    fn note_data(octave: i32, key: i32, gate: f32, slide: bool) -> NoteData {
+        NoteData {
+            octave,
+            key,
+            velocity: 1.0,
+            gate,
+            slide,
+            probability: 1.0,
+            offset: 0.0,
+            chord: None,
+        }
+    }
+}
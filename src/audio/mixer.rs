@@ -0,0 +1,160 @@
+use std::f32::consts::{FRAC_PI_4, SQRT_2};
+
+pub const DEFAULT_GAIN: f32 = 0.5;
+/// Level the limiter keeps the master bus under
+const LIMITER_THRESHOLD: f32 = 0.9;
+/// Time for the limiter's gain reduction to fall back by 63%
+const LIMITER_RELEASE_TIME: f32 = 0.1;
+/// Time for a level meter to fall back by 63%
+const METER_RELEASE_TIME: f32 = 0.3;
+
+/// Per-track gain and pan, mute and solo
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelStrip {
+    pub gain: f32,
+    /// -1.0 is left and 1.0 is right
+    pub pan: f32,
+    pub mute: bool,
+    pub solo: bool,
+}
+
+impl Default for ChannelStrip {
+    fn default() -> Self {
+        Self {
+            gain: DEFAULT_GAIN,
+            pan: 0.0,
+            mute: false,
+            solo: false,
+        }
+    }
+}
+
+impl ChannelStrip {
+    /// A muted track is silent, as is any track that isn't soloed while another one is
+    pub fn is_audible(&self, is_any_solo: bool) -> bool {
+        !self.mute && (self.solo || !is_any_solo)
+    }
+
+    /// Equal-power pan. Tracks place their voices with `position`, so a centered sound comes in
+    /// at the same level on both sides and keeps its power wherever the track is panned
+    pub fn process(&self, frame: [f32; 2]) -> [f32; 2] {
+        let [left, right] = equal_power(self.gain, self.pan);
+        [frame[0] * left, frame[1] * right]
+    }
+}
+
+/// Equal-power pan of a mono signal, -1.0 is left and 1.0 is right
+pub fn equal_power(input: f32, pan: f32) -> [f32; 2] {
+    let angle = (pan.clamp(-1.0, 1.0) + 1.0) * FRAC_PI_4;
+    [input * angle.cos(), input * angle.sin()]
+}
+
+/// Equal-power placement inside a track, unchanged on both sides in the center so the pan law
+/// is only applied once by the track's strip
+pub fn position(input: f32, pan: f32) -> [f32; 2] {
+    equal_power(input * SQRT_2, pan)
+}
+
+/// Peak limiter with instant attack, loud parts are turned down instead of clipped
+pub struct Limiter {
+    envelope: f32,
+    release_coefficient: f32,
+}
+
+impl Limiter {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            envelope: 0.0,
+            release_coefficient: (-1.0 / (LIMITER_RELEASE_TIME * sample_rate as f32)).exp(),
+        }
+    }
+
+    pub fn process(&mut self, frame: [f32; 2]) -> [f32; 2] {
+        let peak = frame[0].abs().max(frame[1].abs());
+        self.envelope = peak.max(self.envelope * self.release_coefficient);
+        let gain = if self.envelope > LIMITER_THRESHOLD {
+            LIMITER_THRESHOLD / self.envelope
+        } else {
+            1.0
+        };
+        [frame[0] * gain, frame[1] * gain]
+    }
+}
+
+/// Peak level of a track with instant attack and a slow fall, for meters and visuals
+pub struct LevelMeter {
+    level: f32,
+    release_coefficient: f32,
+}
+
+impl LevelMeter {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            level: 0.0,
+            release_coefficient: (-1.0 / (METER_RELEASE_TIME * sample_rate as f32)).exp(),
+        }
+    }
+
+    pub fn process(&mut self, frame: [f32; 2]) {
+        let peak = frame[0].abs().max(frame[1].abs());
+        self.level = peak.max(self.level * self.release_coefficient);
+    }
+
+    pub fn level(&self) -> f32 {
+        self.level
+    }
+}
+
+#[cfg(test)]
+mod mixer_tests {
+    use super::*;
+
+    #[test]
+    fn test_equal_power_pan() {
+        for pan in [-1.0, -0.3, 0.0, 0.5, 1.0] {
+            let [left, right] = equal_power(1.0, pan);
+            assert!((left * left + right * right - 1.0).abs() < 1e-6);
+        }
+        let [left, right] = equal_power(1.0, -1.0);
+        assert!(left > 0.999 && right.abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_strip_pan_keeps_power() {
+        let mut strip = ChannelStrip {
+            gain: 1.0,
+            ..ChannelStrip::default()
+        };
+        let centered = position(1.0, 0.0);
+        for pan in [-1.0, -0.3, 0.0, 0.5, 1.0] {
+            strip.pan = pan;
+            let [left, right] = strip.process(centered);
+            assert!((left * left + right * right - 1.0).abs() < 1e-6);
+        }
+        strip.pan = -1.0;
+        let [left, right] = strip.process(centered);
+        assert!(left > 0.999 && right.abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_solo_and_mute() {
+        let mut strip = ChannelStrip::default();
+        assert!(strip.is_audible(false));
+        assert!(!strip.is_audible(true));
+        strip.solo = true;
+        assert!(strip.is_audible(true));
+        strip.mute = true;
+        assert!(!strip.is_audible(true));
+    }
+
+    #[test]
+    fn test_limiter_stays_under_threshold() {
+        let mut limiter = Limiter::new(1000);
+        for i in 0..1000 {
+            let value = 4.0 * (i as f32 * 0.1).sin();
+            let [left, right] = limiter.process([value, -value]);
+            assert!(left.abs() <= LIMITER_THRESHOLD + 1e-6);
+            assert!(right.abs() <= LIMITER_THRESHOLD + 1e-6);
+        }
+    }
+}
@@ -1,28 +1,90 @@
-use super::{lfo::LFO, oscillator_type::OscillatorType, vco::VCO};
+use super::{
+    fm_oscillator::FmOscillator, lfo::LFO, oscillator_type::OscillatorType,
+    transport::STEPS_PER_BEAT, vco::VCO, wavetable_oscillator::WavetableOscillator,
+};
 use kopek::oscillator::WaveType;
+use std::sync::Arc;
+
+/// Which oscillator the VCO section plays
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OscillatorMode {
+    Vco,
+    Fm,
+    Wavetable,
+}
+
+impl OscillatorMode {
+    pub fn to_u8(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(OscillatorMode::Vco),
+            1 => Some(OscillatorMode::Fm),
+            2 => Some(OscillatorMode::Wavetable),
+            _ => None,
+        }
+    }
+}
 
 pub struct ModulatedOscillator {
+    pub mode: OscillatorMode,
     vco: VCO,
+    pub fm: FmOscillator,
+    pub wavetable: WavetableOscillator,
     lfo: LFO,
     frequency: f32,
+    /// Steps per LFO cycle when the LFO follows the tempo
+    pub lfo_sync: Option<u32>,
 }
 
 impl ModulatedOscillator {
     pub fn new(sample_rate: u32) -> Self {
         Self {
+            mode: OscillatorMode::Vco,
             vco: VCO::new(sample_rate as f32),
+            fm: FmOscillator::new(sample_rate as f32),
+            wavetable: WavetableOscillator::new(sample_rate as f32),
             lfo: LFO::new(sample_rate as f32),
             frequency: 440.0,
+            lfo_sync: None,
         }
     }
 
-    pub fn run(&mut self) -> f32 {
-        let modulation = self.lfo.run() * 10.0;
-        let vco_frequency = self.frequency + modulation;
-        self.vco.set_frequency(vco_frequency);
-        let signal = self.vco.run();
+    /// Advances the LFO, which runs even when it isn't routed anywhere so its phase is stable
+    pub fn run_lfo(&mut self, bpm: f32) -> f32 {
+        if let Some(steps) = self.lfo_sync {
+            let frequency = bpm * STEPS_PER_BEAT as f32 / (60.0 * steps.max(1) as f32);
+            self.lfo.set_frequency(frequency);
+        }
+        self.lfo.run()
+    }
+
+    /// `pitch` is an offset from the frequency in semitones
+    pub fn run(&mut self, pitch: f32) -> f32 {
+        let vco_frequency = self.frequency * 2.0_f32.powf(pitch / 12.0);
+        match self.mode {
+            OscillatorMode::Vco => run_oscillator(&mut self.vco, vco_frequency),
+            OscillatorMode::Fm => run_oscillator(&mut self.fm, vco_frequency),
+            OscillatorMode::Wavetable => run_oscillator(&mut self.wavetable, vco_frequency),
+        }
+    }
 
-        signal
+    /// Takes the sound of `other`, phases and the LFO are left alone. Voices use it to follow the
+    /// track's oscillator.
+    pub fn copy_settings(&mut self, other: &ModulatedOscillator) {
+        self.mode = other.mode;
+        if self.vco.wave_type() != other.vco.wave_type() {
+            self.vco.set_wave_type(other.vco.wave_type());
+        }
+        self.fm.ratio = other.fm.ratio;
+        self.fm.index = other.fm.index;
+        self.fm.feedback = other.fm.feedback;
+        if !Arc::ptr_eq(&self.wavetable.table, &other.wavetable.table) {
+            self.wavetable.table = Arc::clone(&other.wavetable.table);
+        }
+        self.wavetable.position = other.wavetable.position;
     }
 
     pub fn frequency(&self) -> f32 {
@@ -57,3 +119,8 @@ impl ModulatedOscillator {
         self.lfo.set_wave_type(wave_type);
     }
 }
+
+fn run_oscillator(oscillator: &mut impl OscillatorType, frequency: f32) -> f32 {
+    oscillator.set_frequency(frequency);
+    oscillator.run()
+}
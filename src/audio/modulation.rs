@@ -0,0 +1,149 @@
+//! Routes the LFO and the envelopes of a track to its parameters.
+//!
+//! Every route adds `depth * source` to its destination, depths are in the destination's unit:
+//! semitones for pitch, octaves for cutoff and plain amounts for the rest.
+
+pub const MAX_ROUTES: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ModSource {
+    Lfo,
+    Envelope,
+    FilterEnvelope,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ModDestination {
+    Pitch,
+    Volume,
+    NoiseMix,
+    Cutoff,
+    Pan,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModRoute {
+    pub source: ModSource,
+    pub destination: ModDestination,
+    pub depth: f32,
+}
+
+/// Summed modulation of one sample
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Modulation {
+    pub pitch: f32,
+    pub volume: f32,
+    pub noise_mix: f32,
+    pub cutoff: f32,
+    pub pan: f32,
+}
+
+pub struct ModMatrix {
+    pub routes: Vec<ModRoute>,
+}
+
+impl ModMatrix {
+    /// Slight vibrato, close to the fixed LFO routing tracks used to have
+    pub fn new() -> Self {
+        Self {
+            routes: vec![ModRoute {
+                source: ModSource::Lfo,
+                destination: ModDestination::Pitch,
+                depth: 0.4,
+            }],
+        }
+    }
+
+    pub fn evaluate(&self, lfo: f32, envelope: f32, filter_envelope: f32) -> Modulation {
+        let mut modulation = Modulation::default();
+        for route in &self.routes {
+            let value = route.depth
+                * match route.source {
+                    ModSource::Lfo => lfo,
+                    ModSource::Envelope => envelope,
+                    ModSource::FilterEnvelope => filter_envelope,
+                };
+            match route.destination {
+                ModDestination::Pitch => modulation.pitch += value,
+                ModDestination::Volume => modulation.volume += value,
+                ModDestination::NoiseMix => modulation.noise_mix += value,
+                ModDestination::Cutoff => modulation.cutoff += value,
+                ModDestination::Pan => modulation.pan += value,
+            }
+        }
+        modulation
+    }
+}
+
+impl ModSource {
+    pub fn to_u8(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(ModSource::Lfo),
+            1 => Some(ModSource::Envelope),
+            2 => Some(ModSource::FilterEnvelope),
+            _ => None,
+        }
+    }
+}
+
+impl ModDestination {
+    pub fn to_u8(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(ModDestination::Pitch),
+            1 => Some(ModDestination::Volume),
+            2 => Some(ModDestination::NoiseMix),
+            3 => Some(ModDestination::Cutoff),
+            4 => Some(ModDestination::Pan),
+            _ => None,
+        }
+    }
+
+    /// Range of the depth slider
+    pub fn max_depth(self) -> f32 {
+        match self {
+            ModDestination::Pitch => 24.0,
+            ModDestination::Cutoff => 6.0,
+            ModDestination::Volume | ModDestination::NoiseMix | ModDestination::Pan => 1.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod modulation_tests {
+    use super::*;
+
+    #[test]
+    fn test_routes_add_up() {
+        let matrix = ModMatrix {
+            routes: vec![
+                ModRoute {
+                    source: ModSource::Lfo,
+                    destination: ModDestination::Cutoff,
+                    depth: 2.0,
+                },
+                ModRoute {
+                    source: ModSource::FilterEnvelope,
+                    destination: ModDestination::Cutoff,
+                    depth: -1.0,
+                },
+                ModRoute {
+                    source: ModSource::Envelope,
+                    destination: ModDestination::Pan,
+                    depth: 0.5,
+                },
+            ],
+        };
+        let modulation = matrix.evaluate(0.5, 1.0, 0.25);
+        assert_eq!(modulation.cutoff, 0.75);
+        assert_eq!(modulation.pan, 0.5);
+        assert_eq!(modulation.pitch, 0.0);
+    }
+}
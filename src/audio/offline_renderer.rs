@@ -0,0 +1,165 @@
+//! Renders the sequencers to a wav file without opening an audio device.
+//!
+//! The same `AudioEngine` as the live stream is run as fast as possible, so a bounce is faster
+//! than realtime and sounds the same as what is heard.
+
+use super::{
+    audio_engine::AudioEngine,
+    song,
+    transport::{Transport, STEPS_PER_BAR},
+};
+use hound::{SampleFormat, WavSpec, WavWriter};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BitDepth {
+    Int16,
+    Float32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ExportSettings {
+    pub sample_rate: u32,
+    pub bars: u32,
+    pub bit_depth: BitDepth,
+}
+
+impl Default for ExportSettings {
+    fn default() -> Self {
+        Self {
+            sample_rate: 44100,
+            bars: 4,
+            bit_depth: BitDepth::Int16,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    Song(song::SongError),
+    Wav(hound::Error),
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportError::Song(e) => write!(f, "can't load the song: {e}"),
+            ExportError::Wav(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// Loads `song.json` into a fresh engine and bounces it to `path`
+pub fn export_song(path: &str, settings: &ExportSettings) -> Result<(), ExportError> {
+    let mut audio_engine = AudioEngine::new(settings.sample_rate);
+    song::load_song(&mut audio_engine).map_err(ExportError::Song)?;
+
+    export_wav(path, &mut audio_engine, settings).map_err(ExportError::Wav)
+}
+
+/// Bounces a copy of the given engine from the first step, the engine itself is not advanced
+pub fn export_engine(
+    path: &str,
+    audio_engine: &AudioEngine,
+    settings: &ExportSettings,
+) -> Result<(), hound::Error> {
+    let song = song::to_song(audio_engine);
+    let mut copy = AudioEngine::new(settings.sample_rate);
+    song::apply_song(&song, &mut copy);
+
+    export_wav(path, &mut copy, settings)
+}
+
+pub fn export_wav(
+    path: &str,
+    audio_engine: &mut AudioEngine,
+    settings: &ExportSettings,
+) -> Result<(), hound::Error> {
+    let samples = render(audio_engine, settings.bars);
+
+    let spec = WavSpec {
+        channels: 2,
+        sample_rate: settings.sample_rate,
+        bits_per_sample: match settings.bit_depth {
+            BitDepth::Int16 => 16,
+            BitDepth::Float32 => 32,
+        },
+        sample_format: match settings.bit_depth {
+            BitDepth::Int16 => SampleFormat::Int,
+            BitDepth::Float32 => SampleFormat::Float,
+        },
+    };
+    let mut writer = WavWriter::create(path, spec)?;
+    for sample in samples.into_iter().flatten() {
+        match settings.bit_depth {
+            BitDepth::Int16 => {
+                writer.write_sample((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)?
+            }
+            BitDepth::Float32 => writer.write_sample(sample)?,
+        }
+    }
+    writer.finalize()
+}
+
+/// Plays the engine from its current position for `bars` bars and returns the stereo frames
+pub fn render(audio_engine: &mut AudioEngine, bars: u32) -> Vec<[f32; 2]> {
+    audio_engine.play();
+    let end = audio_engine.transport.steps() + (bars * STEPS_PER_BAR) as f64;
+    let mut frames =
+        Vec::with_capacity(bar_length(&audio_engine.transport) as usize * bars as usize);
+    while audio_engine.transport.steps() < end {
+        frames.push(audio_engine.update());
+    }
+
+    frames
+}
+
+/// Length of a bar in samples at the transport's current tempo
+pub fn bar_length(transport: &Transport) -> u32 {
+    (STEPS_PER_BAR as f64 / transport.steps_per_sample()).ceil() as u32
+}
+
+#[cfg(test)]
+mod offline_renderer_tests {
+    use super::*;
+    use hound::WavReader;
+
+    const SAMPLE_RATE: u32 = 1000;
+
+    #[test]
+    fn test_renders_whole_bars() {
+        let mut audio_engine = AudioEngine::new(SAMPLE_RATE);
+        let bar = bar_length(&audio_engine.transport) as usize;
+        let frames = render(&mut audio_engine, 3);
+        assert!(frames.len().abs_diff(3 * bar) <= 1);
+
+        // Half the tempo, twice the frames
+        audio_engine
+            .transport
+            .set_bpm(audio_engine.transport.bpm() / 2.0);
+        let frames = render(&mut audio_engine, 1);
+        assert!(frames.len().abs_diff(2 * bar) <= 1);
+    }
+
+    #[test]
+    fn test_export_wav() {
+        let path = std::env::temp_dir().join(format!("fo_rma_{}_export.wav", std::process::id()));
+        let path = path.to_str().unwrap();
+        let settings = ExportSettings {
+            sample_rate: SAMPLE_RATE,
+            bars: 2,
+            bit_depth: BitDepth::Float32,
+        };
+        let mut audio_engine = AudioEngine::new(SAMPLE_RATE);
+        let bar = bar_length(&audio_engine.transport) as usize;
+        export_wav(path, &mut audio_engine, &settings).unwrap();
+
+        let reader = WavReader::open(path).unwrap();
+        let spec = reader.spec();
+        assert_eq!(spec.channels, 2);
+        assert_eq!(spec.sample_rate, SAMPLE_RATE);
+        assert_eq!(spec.sample_format, SampleFormat::Float);
+        assert!((reader.duration() as usize).abs_diff(2 * bar) <= 1);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,227 @@
+//! Generative pattern tools, the audio counterpart of the maze and bicycle generators.
+//!
+//! Rhythms come from the Euclidean algorithm and melodies from a random walk through a scale.
+//! Both are deterministic, the same settings and seed always give the same pattern.
+
+use super::{
+    midi::{midi_to_note, note_to_midi},
+    sampler::ROOT_NOTE,
+    sequencer::MAX_LENGTH,
+    step::Step,
+};
+use kopek::utils::{Key, Note};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+pub const MAX_OCTAVES: u8 = 4;
+/// Furthest the walk moves in one step, in scale degrees
+pub const MAX_LEAP: u8 = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scale {
+    Major,
+    Minor,
+    Dorian,
+    Phrygian,
+    Lydian,
+    Mixolydian,
+    HarmonicMinor,
+    MajorPentatonic,
+    MinorPentatonic,
+    Blues,
+    Chromatic,
+}
+
+impl Scale {
+    pub const ALL: [Scale; 11] = [
+        Scale::Major,
+        Scale::Minor,
+        Scale::Dorian,
+        Scale::Phrygian,
+        Scale::Lydian,
+        Scale::Mixolydian,
+        Scale::HarmonicMinor,
+        Scale::MajorPentatonic,
+        Scale::MinorPentatonic,
+        Scale::Blues,
+        Scale::Chromatic,
+    ];
+
+    /// Semitones above the root within one octave
+    pub fn intervals(self) -> &'static [u8] {
+        match self {
+            Scale::Major => &[0, 2, 4, 5, 7, 9, 11],
+            Scale::Minor => &[0, 2, 3, 5, 7, 8, 10],
+            Scale::Dorian => &[0, 2, 3, 5, 7, 9, 10],
+            Scale::Phrygian => &[0, 1, 3, 5, 7, 8, 10],
+            Scale::Lydian => &[0, 2, 4, 6, 7, 9, 11],
+            Scale::Mixolydian => &[0, 2, 4, 5, 7, 9, 10],
+            Scale::HarmonicMinor => &[0, 2, 3, 5, 7, 8, 11],
+            Scale::MajorPentatonic => &[0, 2, 4, 7, 9],
+            Scale::MinorPentatonic => &[0, 3, 5, 7, 10],
+            Scale::Blues => &[0, 3, 5, 6, 7, 10],
+            Scale::Chromatic => &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Scale::Major => "major",
+            Scale::Minor => "minor",
+            Scale::Dorian => "dorian",
+            Scale::Phrygian => "phrygian",
+            Scale::Lydian => "lydian",
+            Scale::Mixolydian => "mixolydian",
+            Scale::HarmonicMinor => "harmonic minor",
+            Scale::MajorPentatonic => "major pentatonic",
+            Scale::MinorPentatonic => "minor pentatonic",
+            Scale::Blues => "blues",
+            Scale::Chromatic => "chromatic",
+        }
+    }
+}
+
+/// `hits` onsets spread as evenly as possible over `steps`, turned right by `rotation` steps.
+/// Without rotation the first step is always a hit.
+pub fn euclidean(hits: usize, steps: usize, rotation: usize) -> Vec<bool> {
+    let steps = steps.clamp(1, MAX_LENGTH);
+    let hits = hits.min(steps);
+    (0..steps)
+        .map(|i| {
+            let i = (i + steps - rotation % steps) % steps;
+            i * hits % steps < hits
+        })
+        .collect()
+}
+
+/// Wanders up and down a scale from its root, each note at most `max_leap` degrees from the one
+/// before and never outside `octaves` octaves
+#[derive(Debug, Clone, Copy)]
+pub struct RandomWalk {
+    pub seed: u64,
+    /// Lowest note the walk starts on
+    pub root: Note,
+    pub scale: Scale,
+    pub octaves: u8,
+    pub max_leap: u8,
+}
+
+impl RandomWalk {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            root: midi_to_note(ROOT_NOTE - 12),
+            scale: Scale::MinorPentatonic,
+            octaves: 2,
+            max_leap: 2,
+        }
+    }
+
+    pub fn notes(&self, count: usize) -> Vec<Note> {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let intervals = self.scale.intervals();
+        let root = note_to_midi(self.root).unwrap_or(ROOT_NOTE);
+        let top = intervals.len() as i32 * self.octaves.clamp(1, MAX_OCTAVES) as i32;
+        let leap = self.max_leap.clamp(1, MAX_LEAP) as i32;
+        let mut degree = 0_i32;
+        (0..count)
+            .map(|i| {
+                if i > 0 {
+                    degree += rng.random_range(-leap..=leap);
+                    // Bounces off the ends of the range instead of sticking to them
+                    if degree < 0 {
+                        degree = -degree;
+                    }
+                    if degree > top {
+                        degree = 2 * top - degree;
+                    }
+                    degree = degree.clamp(0, top);
+                }
+                let octave = degree / intervals.len() as i32;
+                let interval = intervals[degree as usize % intervals.len()] as i32;
+                let midi_note = root as i32 + 12 * octave + interval;
+                midi_to_note(midi_note.clamp(0, u8::MAX as i32) as u8)
+            })
+            .collect()
+    }
+}
+
+/// The pattern takes the rhythm's length. Hits keep their note, hits on rests play
+/// `ROOT_NOTE`, the other steps become rests.
+pub fn apply_rhythm(pattern: &mut Vec<Step>, rhythm: &[bool]) {
+    pattern.resize(rhythm.len().clamp(1, MAX_LENGTH), Step::default());
+    for (step, &is_hit) in pattern.iter_mut().zip(rhythm) {
+        if !is_hit {
+            step.note.key = Key::Rest;
+        } else if step.is_rest() {
+            step.note = midi_to_note(ROOT_NOTE);
+        }
+    }
+}
+
+/// Every step that plays gets the next note of the walk, a pattern of rests gets a note on
+/// every step
+pub fn apply_melody(pattern: &mut [Step], walk: &RandomWalk) {
+    let is_empty = pattern.iter().all(Step::is_rest);
+    let mut notes = walk.notes(pattern.len()).into_iter();
+    for step in pattern.iter_mut() {
+        if is_empty || !step.is_rest() {
+            if let Some(note) = notes.next() {
+                step.note = note;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod pattern_generator_tests {
+    use super::*;
+
+    fn to_string(rhythm: &[bool]) -> String {
+        rhythm
+            .iter()
+            .map(|&hit| if hit { 'x' } else { '.' })
+            .collect()
+    }
+
+    #[test]
+    fn test_euclidean() {
+        assert_eq!(to_string(&euclidean(3, 8, 0)), "x..x..x.");
+        assert_eq!(to_string(&euclidean(5, 8, 0)), "x.x.xx.x");
+        assert_eq!(to_string(&euclidean(3, 8, 2)), "x.x..x..");
+        assert_eq!(to_string(&euclidean(0, 4, 0)), "....");
+        assert_eq!(to_string(&euclidean(9, 4, 1)), "xxxx");
+    }
+
+    #[test]
+    fn test_random_walk_is_reproducible_and_in_scale() {
+        let mut walk = RandomWalk::new(7);
+        walk.scale = Scale::Major;
+        let notes = walk.notes(64);
+        assert_eq!(
+            notes.iter().map(|n| note_to_midi(*n)).collect::<Vec<_>>(),
+            walk.notes(64)
+                .iter()
+                .map(|n| note_to_midi(*n))
+                .collect::<Vec<_>>()
+        );
+
+        let root = note_to_midi(walk.root).unwrap();
+        for pair in notes.windows(2) {
+            let [a, b] = [pair[0], pair[1]].map(|n| note_to_midi(n).unwrap());
+            assert!((root..=root + 24).contains(&b));
+            assert!(Scale::Major.intervals().contains(&((b - root) % 12)));
+            // Two degrees of a major scale are at most four semitones apart
+            assert!(a.abs_diff(b) <= 4);
+        }
+    }
+
+    #[test]
+    fn test_melody_follows_rhythm() {
+        let mut pattern = vec![Step::default(); 4];
+        apply_rhythm(&mut pattern, &euclidean(3, 8, 0));
+        assert_eq!(pattern.len(), 8);
+        apply_melody(&mut pattern, &RandomWalk::new(1));
+        let playing: Vec<bool> = pattern.iter().map(|step| !step.is_rest()).collect();
+        assert_eq!(to_string(&playing), "x..x..x.");
+    }
+}
@@ -0,0 +1,198 @@
+//! Sound counterparts of the image post-process effects, applied to the master bus.
+//!
+//! They follow the VFX window's stack, the same effects in the same order, so the picture and
+//! the sound degrade together. Like the compute shaders they have no settings of their own.
+
+use crate::shader_utils::Effect;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// Level of the added hiss
+const NOISE_LEVEL: f32 = 0.02;
+/// Samples every held sample lasts, the shader's block size
+const PIXEL_SIZE: u32 = 8;
+/// Amplitude the signal is quantized to
+const STEP_SIZE: f32 = 0.125;
+const TREMOLO_FREQUENCY: f32 = 4.0;
+/// Share of the level the tremolo takes away at its lowest
+const TREMOLO_DEPTH: f32 = 0.6;
+/// Gate openings per beat, every other 1/32 note is silent
+const INTERLACE_RATE: f32 = 8.0;
+/// Cutoff of the low-pass that blurs the sound
+const WATERCOLOR_CUTOFF: f32 = 1200.0;
+/// Side level relative to mid
+const CHROMOSTEREOPSIS_WIDTH: f32 = 2.0;
+/// Right channel lags the left by this much, in seconds
+const ANAGLYPH_OFFSET: f32 = 0.01;
+
+pub struct PostProcess {
+    /// Active effects, in the order of the image stack
+    effects: Vec<Effect>,
+    rng: StdRng,
+    held: [f32; 2],
+    hold_counter: u32,
+    tremolo_phase: f32,
+    interlace_phase: f32,
+    low_pass: [f32; 2],
+    low_pass_coefficient: f32,
+    /// Past right channel samples for the anaglyph offset
+    delay_line: Vec<f32>,
+    delay_index: usize,
+    sample_rate: u32,
+}
+
+impl PostProcess {
+    pub fn new(sample_rate: u32) -> Self {
+        let delay_length = ((ANAGLYPH_OFFSET * sample_rate as f32) as usize).max(1);
+        Self {
+            effects: Vec::new(),
+            rng: StdRng::seed_from_u64(0),
+            held: [0.0; 2],
+            hold_counter: 0,
+            tremolo_phase: 0.0,
+            interlace_phase: 0.0,
+            low_pass: [0.0; 2],
+            low_pass_coefficient: (-std::f32::consts::TAU * WATERCOLOR_CUTOFF / sample_rate as f32)
+                .exp(),
+            delay_line: vec![0.0; delay_length],
+            delay_index: 0,
+            sample_rate,
+        }
+    }
+
+    /// Replaces the active effects, in the order they are applied
+    pub fn set_effects(&mut self, effects: Vec<Effect>) {
+        self.effects = effects;
+    }
+
+    /// `bpm` keeps the interlace gate in time
+    pub fn process_frame(&mut self, frame: [f32; 2], bpm: f32) -> [f32; 2] {
+        let mut frame = frame;
+        for i in 0..self.effects.len() {
+            frame = self.apply(self.effects[i], frame);
+        }
+        self.advance(bpm);
+        frame
+    }
+
+    fn apply(&mut self, effect: Effect, [left, right]: [f32; 2]) -> [f32; 2] {
+        match effect {
+            Effect::None => [left, right],
+            Effect::Noise => {
+                let mut hiss = || (self.rng.random::<f32>() * 2.0 - 1.0) * NOISE_LEVEL;
+                [left + hiss(), right + hiss()]
+            }
+            // Sample-and-hold, the first sample of every block is held for the whole block
+            Effect::Pixelate => {
+                if self.hold_counter == 0 {
+                    self.held = [left, right];
+                }
+                self.held
+            }
+            Effect::InvertColor => [-left, -right],
+            Effect::Wave => {
+                let lfo = 0.5 + 0.5 * (std::f32::consts::TAU * self.tremolo_phase).cos();
+                let gain = 1.0 - TREMOLO_DEPTH * (1.0 - lfo);
+                [left * gain, right * gain]
+            }
+            Effect::Interlace => {
+                if self.interlace_phase < 0.5 {
+                    [left, right]
+                } else {
+                    [0.0, 0.0]
+                }
+            }
+            Effect::FlipAxis => [right, left],
+            Effect::Grayscale => {
+                let mono = (left + right) * 0.5;
+                [mono, mono]
+            }
+            Effect::Step => [left, right].map(|x| (x / STEP_SIZE).round() * STEP_SIZE),
+            Effect::Watercolor => {
+                let coefficient = self.low_pass_coefficient;
+                for (state, input) in self.low_pass.iter_mut().zip([left, right]) {
+                    *state = input + (*state - input) * coefficient;
+                }
+                self.low_pass
+            }
+            Effect::Chromostereopsis => {
+                let mid = (left + right) * 0.5;
+                let side = (left - right) * 0.5 * CHROMOSTEREOPSIS_WIDTH;
+                [mid + side, mid - side]
+            }
+            Effect::Anaglyph => {
+                let delayed = self.delay_line[self.delay_index];
+                self.delay_line[self.delay_index] = right;
+                self.delay_index = (self.delay_index + 1) % self.delay_line.len();
+                [left, delayed]
+            }
+        }
+    }
+
+    fn advance(&mut self, bpm: f32) {
+        let sample_rate = self.sample_rate as f32;
+        self.hold_counter = (self.hold_counter + 1) % PIXEL_SIZE;
+        self.tremolo_phase = (self.tremolo_phase + TREMOLO_FREQUENCY / sample_rate).fract();
+        self.interlace_phase =
+            (self.interlace_phase + bpm / 60.0 * INTERLACE_RATE / sample_rate).fract();
+    }
+}
+
+#[cfg(test)]
+mod post_process_tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 1000;
+
+    fn run(post_process: &mut PostProcess, frames: &[[f32; 2]]) -> Vec<[f32; 2]> {
+        frames
+            .iter()
+            .map(|frame| post_process.process_frame(*frame, 120.0))
+            .collect()
+    }
+
+    #[test]
+    fn test_stereo_effects_and_order() {
+        let mut post_process = PostProcess::new(SAMPLE_RATE);
+        assert_eq!(run(&mut post_process, &[[0.5, 0.25]]), [[0.5, 0.25]]);
+
+        post_process.set_effects(vec![Effect::FlipAxis, Effect::InvertColor]);
+        assert_eq!(run(&mut post_process, &[[0.5, 0.25]]), [[-0.25, -0.5]]);
+        post_process.set_effects(vec![Effect::Grayscale]);
+        assert_eq!(run(&mut post_process, &[[0.5, 0.25]]), [[0.375, 0.375]]);
+
+        // Quantizing before or after the mono sum gives different results
+        post_process.set_effects(vec![Effect::Step, Effect::Grayscale]);
+        assert_eq!(run(&mut post_process, &[[0.32, 0.0]]), [[0.1875, 0.1875]]);
+        post_process.set_effects(vec![Effect::Grayscale, Effect::Step]);
+        assert_eq!(run(&mut post_process, &[[0.32, 0.0]]), [[0.125, 0.125]]);
+    }
+
+    #[test]
+    fn test_pixelate_holds_blocks() {
+        let mut post_process = PostProcess::new(SAMPLE_RATE);
+        post_process.set_effects(vec![Effect::Pixelate]);
+        let input: Vec<[f32; 2]> = (0..16).map(|i| [i as f32, -(i as f32)]).collect();
+        let output = run(&mut post_process, &input);
+        assert!(output[..8].iter().all(|frame| *frame == [0.0, -0.0]));
+        assert!(output[8..].iter().all(|frame| *frame == [8.0, -8.0]));
+    }
+
+    #[test]
+    fn test_anaglyph_and_interlace() {
+        let mut post_process = PostProcess::new(SAMPLE_RATE);
+        post_process.set_effects(vec![Effect::Anaglyph]);
+        let input: Vec<[f32; 2]> = (0..20).map(|i| [1.0, i as f32]).collect();
+        let output = run(&mut post_process, &input);
+        // 10 ms at 1 kHz
+        assert_eq!(output[9], [1.0, 0.0]);
+        assert_eq!(output[15], [1.0, 5.0]);
+
+        // 120 bpm is 16 gate cycles per second, 62.5 samples each
+        let mut post_process = PostProcess::new(SAMPLE_RATE);
+        post_process.set_effects(vec![Effect::Interlace]);
+        let output = run(&mut post_process, &[[1.0, 1.0]; 63]);
+        assert_eq!(output[31], [1.0, 1.0]);
+        assert_eq!(output[32], [0.0, 0.0]);
+        assert_eq!(output[62], [0.0, 0.0]);
+    }
+}
@@ -0,0 +1,189 @@
+use hound::{SampleFormat, WavReader};
+use std::{path::Path, sync::Arc};
+
+/// MIDI note that plays a sample at the pitch it was recorded at, C4
+pub const ROOT_NOTE: u8 = 60;
+pub const MAX_CHOKE_GROUP: u8 = 8;
+/// Fade out of choked voices, short enough to sound like a cut but without a click
+const CHOKE_TIME: f32 = 0.005;
+
+/// A WAV file mixed down to mono, shared between the copies of an engine
+#[derive(Clone)]
+pub struct Sample {
+    /// As it was loaded, `song` stores it relative to the song file
+    pub path: String,
+    data: Arc<[f32]>,
+    sample_rate: u32,
+}
+
+impl Sample {
+    pub fn load(path: &str) -> Result<Self, hound::Error> {
+        let (data, sample_rate) = read_mono(path)?;
+        Ok(Self::from_data(path, data, sample_rate))
+    }
+
+    pub fn from_data(path: &str, data: Vec<f32>, sample_rate: u32) -> Self {
+        Self {
+            path: path.to_string(),
+            data: data.into(),
+            sample_rate,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn seconds(&self) -> f32 {
+        self.len() as f32 / self.sample_rate as f32
+    }
+}
+
+/// Reads a WAV file of any format as mono samples in -1.0..=1.0 and its sample rate
+pub fn read_mono(path: &str) -> Result<(Vec<f32>, u32), hound::Error> {
+    let mut reader = WavReader::open(Path::new(path))?;
+    let spec = reader.spec();
+    let samples: Vec<f32> = match spec.sample_format {
+        SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+        SampleFormat::Int => {
+            let scale = 1.0 / (1_i64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|sample| sample.map(|sample| sample as f32 * scale))
+                .collect::<Result<_, _>>()?
+        }
+    };
+    let channels = spec.channels.max(1) as usize;
+    let data = samples
+        .chunks(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect();
+
+    Ok((data, spec.sample_rate))
+}
+
+/// One-shot sample voice, a trigger plays the sample to its end unless it is retriggered or
+/// choked by another sampler in the same group
+pub struct Sampler {
+    pub sample: Option<Sample>,
+    /// Samplers in the same group cut each other off, like open and closed hi-hats
+    pub choke_group: Option<u8>,
+    sample_rate: u32,
+    position: f64,
+    increment: f64,
+    gain: f32,
+    is_playing: bool,
+    /// Gain lost per sample while choked
+    fade: f32,
+}
+
+impl Sampler {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample: None,
+            choke_group: None,
+            sample_rate,
+            position: 0.0,
+            increment: 1.0,
+            gain: 0.0,
+            is_playing: false,
+            fade: 0.0,
+        }
+    }
+
+    /// Starts the sample `offset` (0.0..1.0) into its length, `pitch` is the playback speed
+    /// relative to the recording
+    pub fn trigger(&mut self, pitch: f64, offset: f32, velocity: f32) {
+        let Some(sample) = &self.sample else {
+            return;
+        };
+        self.increment = pitch * sample.sample_rate as f64 / self.sample_rate as f64;
+        self.position = offset.clamp(0.0, 1.0) as f64 * sample.len() as f64;
+        self.gain = velocity.clamp(0.0, 1.0);
+        self.fade = 0.0;
+        self.is_playing = true;
+    }
+
+    pub fn choke(&mut self) {
+        if self.is_playing && self.fade == 0.0 {
+            self.fade = self.gain / (CHOKE_TIME * self.sample_rate as f32);
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.is_playing
+    }
+
+    pub fn run(&mut self) -> f32 {
+        let Some(sample) = &self.sample else {
+            return 0.0;
+        };
+        if !self.is_playing {
+            return 0.0;
+        }
+
+        let index = self.position as usize;
+        if index + 1 >= sample.len() {
+            self.is_playing = false;
+            return 0.0;
+        }
+        // Linear interpolation is enough for drums, pitched way up they alias a little
+        let fraction = (self.position - index as f64) as f32;
+        let value = sample.data[index] + (sample.data[index + 1] - sample.data[index]) * fraction;
+        let value = value * self.gain;
+
+        self.position += self.increment;
+        if self.fade > 0.0 {
+            self.gain -= self.fade;
+            if self.gain <= 0.0 {
+                self.is_playing = false;
+            }
+        }
+
+        value
+    }
+}
+
+#[cfg(test)]
+mod sampler_tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 1000;
+
+    fn ramp_sampler() -> Sampler {
+        let mut sampler = Sampler::new(SAMPLE_RATE);
+        let data = (0..100).map(|i| i as f32 / 100.0).collect();
+        sampler.sample = Some(Sample::from_data("ramp.wav", data, SAMPLE_RATE));
+        sampler
+    }
+
+    #[test]
+    fn test_pitch_and_offset() {
+        let mut sampler = ramp_sampler();
+        sampler.trigger(2.0, 0.5, 1.0);
+        assert!((sampler.run() - 0.5).abs() < 1e-6);
+        // An octave up plays twice as fast
+        assert!((sampler.run() - 0.52).abs() < 1e-6);
+
+        let played = std::iter::from_fn(|| sampler.is_playing().then(|| sampler.run())).count();
+        assert_eq!(played, 24);
+    }
+
+    #[test]
+    fn test_choke_fades_out() {
+        let mut sampler = ramp_sampler();
+        sampler.trigger(1.0, 0.0, 1.0);
+        sampler.run();
+        sampler.choke();
+        let fade_samples = (CHOKE_TIME * SAMPLE_RATE as f32).ceil() as usize;
+        for _ in 0..=fade_samples {
+            sampler.run();
+        }
+        assert!(!sampler.is_playing());
+        assert_eq!(sampler.run(), 0.0);
+    }
+}
@@ -1,99 +1,377 @@
-use super::modulated_oscillator::ModulatedOscillator;
-use crate::audio::envelope::Envelope;
+use super::{
+    arpeggiator::{ArpEvent, Arpeggiator},
+    arrangement::MAX_PATTERNS,
+    effects::EffectChain,
+    midi::note_to_midi,
+    mixer::{self, ChannelStrip, LevelMeter},
+    modulated_oscillator::ModulatedOscillator,
+    modulation::ModMatrix,
+    sampler::{Sampler, ROOT_NOTE},
+    step::Step,
+    transport::{StepPosition, STEPS_PER_BAR},
+    tuning::{self, Tuning},
+    voice::{VoiceAllocator, VoiceSettings},
+};
+use crate::audio::{envelope::Envelope, filter::Filter};
 use kopek::{
     noise_generator::{NoiseGenerator, NoiseType},
     oscillator::WaveType,
-    utils::{Key, Note},
+    utils::Note,
 };
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+pub const MAX_LENGTH: usize = 64;
+pub const DEFAULT_WAVE_VOLUME: f32 = 0.9;
+pub const DEFAULT_NOISE_VOLUME: f32 = 0.1;
+/// Part of a step that counts as on beat for visuals
+const BEAT_DURATION: f32 = 1.0 / 3.0;
+
+/// What a track plays its steps with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VoiceType {
+    Synth,
+    Sampler,
+}
+
+impl VoiceType {
+    pub fn to_u8(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(VoiceType::Synth),
+            1 => Some(VoiceType::Sampler),
+            _ => None,
+        }
+    }
+}
 
 pub struct Sequencer {
     pub is_running: bool,
+    pub voice_type: VoiceType,
     pub modulated_oscillator: ModulatedOscillator,
     pub noise_generator: NoiseGenerator,
-    beat_index: u32,
-    prev_beat_index: u32,
-    length: u8,
-    freq: f32,
-    pub sequence: Vec<Note>,
-    tick_period: f32,
-    beat_duration: f32,
+    pub sampler: Sampler,
+    /// Plays the synth steps, the oscillator, envelopes and filter of the sequencer are the
+    /// settings every voice follows
+    pub voices: VoiceAllocator,
+    pub arpeggiator: Arpeggiator,
+    /// Set when the sampler was triggered by the last update, so the engine can choke the group
+    is_sample_triggered: bool,
+    beat_index: u64,
+    prev_beat_index: u64,
+    gate_open: bool,
+    is_sliding: bool,
+    /// Always at least one pattern
+    patterns: Vec<Vec<Step>>,
+    pattern_index: usize,
+    /// Pattern to switch to on the next bar
+    queued_pattern: Option<usize>,
+    /// Step the current pattern started on, so switched patterns play from their first step
+    pattern_start: u64,
     on_beat: bool,
     wave_volume: f32,
     pub noise_volume: f32,
     pub envelope: Envelope,
+    pub filter: Filter,
+    pub filter_envelope: Envelope,
+    pub modulation: ModMatrix,
+    pub effects: EffectChain,
+    pub strip: ChannelStrip,
+    /// Level after the channel strip, silent while the track can't be heard
+    pub meter: LevelMeter,
+    delta_time: f32,
+    rng: StdRng,
 }
 
 impl Sequencer {
     pub fn new(
-        bpm: u16,
         sample_rate: u32,
-        channel_count: u32,
         sequence: Vec<Note>, // song
     ) -> Self {
-        let tick_period = (sample_rate * 60) as f32 / bpm as f32;
-        let beat_duration = tick_period / 3.0;
-        println!("Sequencer: {bpm}, {sample_rate}, {channel_count}, {tick_period}");
-
         let noise_generator = NoiseGenerator::new();
 
         const FACTOR: f32 = 0.2;
-        Self {
+        let mut sequencer = Self {
             is_running: false,
+            voice_type: VoiceType::Synth,
             modulated_oscillator: ModulatedOscillator::new(sample_rate),
             noise_generator,
+            sampler: Sampler::new(sample_rate),
+            voices: VoiceAllocator::new(sample_rate),
+            arpeggiator: Arpeggiator::new(),
+            is_sample_triggered: false,
             beat_index: 0,
-            prev_beat_index: 0,
-            length: sequence.len() as u8,
-            freq: sequence[0].get(),
-            sequence,
-            tick_period,
-            beat_duration,
+            prev_beat_index: u64::MAX,
+            gate_open: false,
+            is_sliding: false,
+            patterns: Vec::new(),
+            pattern_index: 0,
+            queued_pattern: None,
+            pattern_start: 0,
             on_beat: false,
-            wave_volume: 0.9,
-            noise_volume: 0.1,
-            envelope: Envelope::new(0.1 * FACTOR, 0.1 * FACTOR, 0.2 * FACTOR, 0.1 * FACTOR),
-        }
+            wave_volume: DEFAULT_WAVE_VOLUME,
+            noise_volume: DEFAULT_NOISE_VOLUME,
+            envelope: Envelope::new(0.1 * FACTOR, 0.1 * FACTOR, 0.6, 0.1 * FACTOR),
+            filter: Filter::new(sample_rate),
+            filter_envelope: Envelope::new(0.01, 0.2, 0.0, 0.1),
+            modulation: ModMatrix::new(),
+            effects: EffectChain::new(sample_rate, 2),
+            strip: ChannelStrip::default(),
+            meter: LevelMeter::new(sample_rate),
+            delta_time: 1.0 / sample_rate as f32,
+            // Seeded so offline renders of the same song are identical
+            rng: StdRng::seed_from_u64(0),
+        };
+        // An empty sequence becomes a single rest like any other empty pattern
+        sequencer.set_patterns(vec![sequence.into_iter().map(Step::new).collect()]);
+        sequencer
     }
 
-    pub fn update(&mut self, elapsed_samples: u32) -> f32 {
-        let remainder = elapsed_samples % self.tick_period as u32;
-        self.on_beat = remainder > 0 && remainder < self.beat_duration as u32;
-        self.beat_index = elapsed_samples / self.tick_period as u32;
-        let step_index = (self.beat_index % self.length as u32) as usize;
+    /// Renders one stereo frame at the transport's position, steps are triggered when the
+    /// position enters a new step and nothing is triggered without a position. Notes sound at
+    /// the frequencies of `tuning`.
+    pub fn update(
+        &mut self,
+        position: Option<StepPosition>,
+        bpm: f32,
+        tuning: &Tuning,
+    ) -> [f32; 2] {
+        self.on_beat = false;
+        self.is_sample_triggered = false;
+        if let Some(position) = position {
+            self.advance(position, tuning);
+        }
 
-        self.freq = self.sequence[step_index].get();
-        self.modulated_oscillator.set_frequency(self.freq);
-        let mut wave_value = self.modulated_oscillator.run();
-        wave_value = wave_value * self.wave_volume;
+        let lfo = self.modulated_oscillator.run_lfo(bpm);
+        if self.voice_type == VoiceType::Sampler {
+            // Samples are one-shots, the amp envelope and the gate don't shape them
+            let envelope = self.envelope.update(self.delta_time);
+            let filter_envelope = self.filter_envelope.update(self.delta_time);
+            let modulation = self.modulation.evaluate(lfo, envelope, filter_envelope);
+            let value = self.sampler.run();
+            let value = self
+                .filter
+                .process(value, filter_envelope, modulation.cutoff);
+            return mixer::position(value * (1.0 + modulation.volume).max(0.0), modulation.pan);
+        }
 
-        let mut noise_value = if self.sequence[step_index].key != Key::Rest {
-            self.noise_generator.run()
-        } else {
-            0.0
-        };
-        noise_value = noise_value * self.noise_volume;
-        // self.freq = self.sequence[step_index].get();
-        // self.modulated_oscillator.frequency_mut(self.freq);
-        // let mut value = self.modulated_oscillator.run();
-        // value = self.noise_generator.run();
+        let noise = self.noise_generator.run();
+        let frame = self.voices.render(&VoiceSettings {
+            oscillator: &self.modulated_oscillator,
+            envelope: &self.envelope,
+            filter: &self.filter,
+            filter_envelope: &self.filter_envelope,
+            modulation: &self.modulation,
+            wave_volume: self.wave_volume,
+            noise,
+            noise_volume: self.noise_volume,
+            lfo,
+        });
+        // Keeps the frequency readable for the interface and the visuals
+        self.modulated_oscillator
+            .set_frequency(self.voices.frequency());
+
+        frame
+    }
+
+    fn advance(&mut self, position: StepPosition, tuning: &Tuning) {
+        self.on_beat = position.phase > 0.0 && position.phase < BEAT_DURATION;
+        self.beat_index = position.step;
+        let is_new_step = self.prev_beat_index != self.beat_index;
+        if is_new_step && self.beat_index % STEPS_PER_BAR as u64 == 0 {
+            if let Some(pattern) = self.queued_pattern.take() {
+                self.pattern_index = pattern;
+                self.pattern_start = self.beat_index;
+            }
+        }
+        let sequence = &self.patterns[self.pattern_index];
+        let step_index =
+            (self.beat_index.saturating_sub(self.pattern_start) % sequence.len() as u64) as usize;
+        let step = sequence[step_index];
 
-        if self.prev_beat_index != self.beat_index {
+        if is_new_step {
             self.prev_beat_index = self.beat_index;
-            self.envelope.reset(); // envelope should never reset actually
+            self.trigger(step, tuning);
+        } else if self.gate_open && !self.is_sliding && position.phase >= step.gate {
+            self.gate_off();
+        }
+
+        match self.arpeggiator.update(position, &mut self.rng) {
+            ArpEvent::NoteOn { step, semitones } => {
+                self.gate_off();
+                self.play(step, semitones, false, tuning);
+            }
+            ArpEvent::NoteOff => self.gate_off(),
+            ArpEvent::None => {}
+        }
+    }
+
+    /// Starts a step, rests and steps that fail their probability roll close the gate but leave
+    /// an arpeggiated chord playing
+    fn trigger(&mut self, step: Step, tuning: &Tuning) {
+        let is_triggered = !step.is_rest()
+            && (step.probability >= 1.0 || self.rng.random::<f32>() < step.probability);
+        if !is_triggered {
+            // Rests keep the previous frequency so the release tail doesn't change pitch
+            if self.gate_open {
+                self.release();
+            }
+            return;
+        }
+
+        self.arpeggiator.stop();
+        // A slide from the previous step keeps the gate open and glides to the new note, chords
+        // always start over
+        let glide = self.is_sliding && self.gate_open && step.chord.is_none();
+        if !glide {
+            // Steps with a full gate are still held
+            self.gate_off();
+        }
+        match step.chord {
+            Some(chord) if self.arpeggiator.is_active() => {
+                // The arpeggiator plays the notes from the next update
+                self.arpeggiator
+                    .hold(step, chord.intervals(), self.beat_index);
+                self.is_sliding = false;
+                return;
+            }
+            // Samplers play one sample at a time, chords play their root
+            Some(chord) if self.voice_type == VoiceType::Synth => {
+                // With fewer voices than notes the chord would steal its own notes, songs saved
+                // before polyphony have a single voice per track
+                self.voices.set_min_voices(chord.intervals().len());
+                for &semitones in chord.intervals() {
+                    self.play(step, semitones, false, tuning);
+                }
+                self.voices.set_min_voices(0);
+            }
+            _ => self.play(step, 0, glide, tuning),
+        }
+        self.gate_open = true;
+        self.is_sliding = step.slide && self.voice_type == VoiceType::Synth;
+    }
+
+    /// Plays `step` raised by `semitones` on the voices or the sampler, samplers play at the
+    /// speed that moves `ROOT_NOTE` in standard pitch to the note's frequency. Chord and arpeggio
+    /// notes are keys above the step's note, so the tuning applies to them as well.
+    fn play(&mut self, step: Step, semitones: u8, glide: bool, tuning: &Tuning) {
+        let midi_note = note_to_midi(step.note).unwrap_or_default();
+        let frequency = tuning.frequency(midi_note.saturating_add(semitones));
+        if self.voice_type == VoiceType::Sampler {
+            let pitch = frequency as f64 / tuning::standard_frequency(ROOT_NOTE) as f64;
+            self.sampler.trigger(pitch, step.offset, step.velocity);
+            self.is_sample_triggered = true;
+            // The amp envelope doesn't shape the sample but routes can still use it
+            self.envelope.gate_on();
+            self.filter_envelope.gate_on();
+            return;
+        }
+        self.voices.note_on(frequency, step.velocity, glide);
+    }
+
+    /// Closes the gate so held notes release, the current step is not retriggered and a held
+    /// chord goes on arpeggiating from the next note
+    pub fn release(&mut self) {
+        self.is_sliding = false;
+        self.gate_off();
+    }
+
+    fn gate_off(&mut self) {
+        self.gate_open = false;
+        self.envelope.gate_off();
+        self.filter_envelope.gate_off();
+        self.voices.note_off();
+    }
+
+    /// Releases and forgets the current step so the next position always triggers, a queued
+    /// pattern starts right away
+    pub fn reset(&mut self) {
+        self.release();
+        self.arpeggiator.stop();
+        self.prev_beat_index = u64::MAX;
+        self.pattern_start = 0;
+        if let Some(pattern) = self.queued_pattern.take() {
+            self.pattern_index = pattern;
+        }
+    }
+
+    pub fn patterns(&self) -> &[Vec<Step>] {
+        &self.patterns
+    }
+
+    pub fn pattern_mut(&mut self, pattern: usize) -> Option<&mut Vec<Step>> {
+        self.patterns.get_mut(pattern)
+    }
+
+    /// Replaces every pattern, the playing and the queued one are kept if they still exist
+    pub fn set_patterns(&mut self, mut patterns: Vec<Vec<Step>>) {
+        patterns.truncate(MAX_PATTERNS);
+        if patterns.is_empty() {
+            patterns.push(vec![Step::default()]);
+        }
+        for pattern in &mut patterns {
+            pattern.resize(pattern.len().clamp(1, MAX_LENGTH), Step::default());
+        }
+        self.patterns = patterns;
+        self.pattern_index = self.pattern_index.min(self.patterns.len() - 1);
+        self.queued_pattern = self.queued_pattern.filter(|&i| i < self.patterns.len());
+    }
+
+    /// Adds a copy of `pattern` after the last one and returns its index
+    pub fn duplicate_pattern(&mut self, pattern: usize) -> Option<usize> {
+        if self.patterns.len() >= MAX_PATTERNS {
+            return None;
         }
-        const DELTA_TIME: f32 = 1 as f32 / 44100 as f32;
-        let envelope = self.envelope.update(DELTA_TIME);
-        // if envelope > 0.0 {
-        //     println!("{:?}", envelope);
-        // }
-        let mut value = wave_value + noise_value;
-        value *= envelope;
+        let copy = self.patterns.get(pattern)?.clone();
+        self.patterns.push(copy);
+        Some(self.patterns.len() - 1)
+    }
+
+    /// The last pattern can't be removed, later patterns move down by one
+    pub fn remove_pattern(&mut self, pattern: usize) {
+        if self.patterns.len() <= 1 || pattern >= self.patterns.len() {
+            return;
+        }
+        self.patterns.remove(pattern);
+        let shift = |index: usize| if index > pattern { index - 1 } else { index };
+        self.pattern_index = shift(self.pattern_index).min(self.patterns.len() - 1);
+        self.queued_pattern = self
+            .queued_pattern
+            .map(|index| shift(index).min(self.patterns.len() - 1));
+    }
+
+    /// Pattern currently playing
+    pub fn pattern_index(&self) -> usize {
+        self.pattern_index
+    }
+
+    pub fn queued_pattern(&self) -> Option<usize> {
+        self.queued_pattern
+    }
+
+    /// Switches to `pattern` at the start of the next bar, it plays from its first step
+    pub fn queue_pattern(&mut self, pattern: usize) {
+        if pattern < self.patterns.len() {
+            self.queued_pattern = Some(pattern);
+        }
+    }
+
+    pub fn length(&self, pattern: usize) -> usize {
+        self.patterns.get(pattern).map_or(0, Vec::len)
+    }
 
-        value
+    /// Steps added at the end are rests, there is always at least one step and at most
+    /// `MAX_LENGTH`. Returns the length the pattern ended up with, `None` without such a pattern
+    pub fn set_length(&mut self, pattern: usize, length: usize) -> Option<usize> {
+        let sequence = self.patterns.get_mut(pattern)?;
+        sequence.resize(length.clamp(1, MAX_LENGTH), Step::default());
+        Some(sequence.len())
     }
 
     // Current number of beats played, similar to elapsed time
-    pub fn beat_index(&self) -> u32 {
+    pub fn beat_index(&self) -> u64 {
         self.beat_index
     }
 
@@ -102,12 +380,15 @@ impl Sequencer {
         self.on_beat
     }
 
-    pub fn frequency(&self) -> f32 {
-        self.modulated_oscillator.frequency()
+    /// Choke group of the sampler if the last update started a sample
+    pub fn choke_trigger(&self) -> Option<u8> {
+        self.is_sample_triggered
+            .then_some(self.sampler.choke_group)
+            .flatten()
     }
 
-    pub fn set_frequency(&mut self, frequency: f32) {
-        self.modulated_oscillator.set_frequency(frequency);
+    pub fn frequency(&self) -> f32 {
+        self.modulated_oscillator.frequency()
     }
 
     pub fn vco_wave_type(&self) -> WaveType {
@@ -150,3 +431,189 @@ impl Sequencer {
         self.wave_volume = volume.clamp(0.0, 1.0);
     }
 }
+
+#[cfg(test)]
+mod sequencer_tests {
+    use super::*;
+    use crate::audio::{
+        arpeggiator::ChordType, midi::midi_to_note, modulated_oscillator::OscillatorMode,
+        tuning::TuningSystem,
+    };
+
+    const SAMPLE_RATE: u32 = 1000;
+
+    #[test]
+    fn test_chords_get_a_voice_per_note() {
+        let mut sequencer = Sequencer::new(SAMPLE_RATE, Vec::new());
+        sequencer.voices.polyphony = 1;
+        sequencer.set_patterns(vec![vec![Step {
+            chord: Some(ChordType::Major),
+            ..Step::new(midi_to_note(60))
+        }]]);
+        let position = StepPosition {
+            step: 0,
+            phase: 0.0,
+        };
+        sequencer.update(Some(position), 120.0, &Tuning::default());
+        assert_eq!(sequencer.voices.active_voices(), 3);
+        // The setting is the song's, the chord only borrows voices
+        assert_eq!(sequencer.voices.polyphony, 1);
+    }
+
+    #[test]
+    fn test_chords_follow_the_tuning() {
+        let mut sequencer = Sequencer::new(SAMPLE_RATE, Vec::new());
+        sequencer.set_patterns(vec![vec![Step {
+            chord: Some(ChordType::Major),
+            ..Step::new(midi_to_note(60))
+        }]]);
+        let tuning = Tuning {
+            system: TuningSystem::Just,
+            ..Tuning::default()
+        };
+        let position = StepPosition {
+            step: 0,
+            phase: 0.0,
+        };
+        sequencer.update(Some(position), 120.0, &tuning);
+        // The fifth is played last, a pure 3/2 above the root instead of the equal tempered one
+        let fifth = tuning.frequency(60) * 1.5;
+        assert!((sequencer.voices.frequency() - fifth).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_sampler_steps_open_both_envelopes() {
+        let mut sequencer = Sequencer::new(SAMPLE_RATE, Vec::new());
+        sequencer.voice_type = VoiceType::Sampler;
+        sequencer.set_patterns(vec![vec![Step::new(midi_to_note(60))]]);
+        let position = StepPosition {
+            step: 0,
+            phase: 0.0,
+        };
+        sequencer.update(Some(position), 120.0, &Tuning::default());
+        assert!(!sequencer.envelope.is_idle());
+        assert!(!sequencer.filter_envelope.is_idle());
+    }
+
+    #[test]
+    fn test_set_length_returns_the_applied_length() {
+        let mut sequencer = Sequencer::new(SAMPLE_RATE, Vec::new());
+        sequencer.set_patterns(vec![vec![Step::default(); 16]]);
+        assert_eq!(sequencer.set_length(0, 32), Some(32));
+        assert_eq!(sequencer.set_length(0, MAX_LENGTH + 1), Some(MAX_LENGTH));
+        assert_eq!(sequencer.set_length(0, 0), Some(1));
+        assert_eq!(sequencer.set_length(1, 16), None);
+    }
+
+    fn at(step: u64, phase: f32) -> Option<StepPosition> {
+        Some(StepPosition { step, phase })
+    }
+
+    #[test]
+    fn test_probability() {
+        let tuning = Tuning::default();
+        let mut sequencer = Sequencer::new(SAMPLE_RATE, Vec::new());
+        sequencer.set_patterns(vec![vec![
+            Step {
+                probability: 0.0,
+                ..Step::new(midi_to_note(60))
+            };
+            16
+        ]]);
+        for step in 0..16 {
+            sequencer.update(at(step, 0.0), 120.0, &tuning);
+            assert_eq!(sequencer.voices.active_voices(), 0);
+        }
+
+        let notes = [60, 62, 64, 65];
+        sequencer.set_patterns(vec![notes
+            .iter()
+            .map(|&note| Step::new(midi_to_note(note)))
+            .collect()]);
+        for step in 16..32 {
+            sequencer.update(at(step, 0.0), 120.0, &tuning);
+            let note = notes[step as usize % notes.len()];
+            assert_eq!(sequencer.voices.frequency(), tuning.frequency(note));
+        }
+    }
+
+    #[test]
+    fn test_gate_closes_at_its_length() {
+        let tuning = Tuning::default();
+        let mut sequencer = Sequencer::new(SAMPLE_RATE, Vec::new());
+        sequencer.set_patterns(vec![vec![Step {
+            gate: 0.5,
+            ..Step::new(midi_to_note(60))
+        }]]);
+        sequencer.update(at(0, 0.0), 120.0, &tuning);
+        assert!(sequencer.gate_open);
+        sequencer.update(at(0, 0.4), 120.0, &tuning);
+        assert!(sequencer.gate_open);
+        sequencer.update(at(0, 0.5), 120.0, &tuning);
+        assert!(!sequencer.gate_open);
+    }
+
+    #[test]
+    fn test_slide_glides_the_current_voice() {
+        let tuning = Tuning::default();
+        let mut sequencer = Sequencer::new(SAMPLE_RATE, Vec::new());
+        sequencer.set_patterns(vec![vec![
+            Step {
+                slide: true,
+                ..Step::new(midi_to_note(60))
+            },
+            Step::new(midi_to_note(72)),
+        ]]);
+        sequencer.update(at(0, 0.0), 120.0, &tuning);
+        // The gate stays open past its length into the next step
+        sequencer.update(at(0, 0.9), 120.0, &tuning);
+        assert!(sequencer.gate_open);
+        sequencer.update(at(1, 0.0), 120.0, &tuning);
+        assert_eq!(sequencer.voices.active_voices(), 1);
+        let frequency = sequencer.voices.frequency();
+        assert!(frequency > tuning.frequency(60) && frequency < tuning.frequency(72));
+
+        // Without the slide the next step starts a voice of its own
+        sequencer.set_patterns(vec![vec![
+            Step::new(midi_to_note(60)),
+            Step::new(midi_to_note(72)),
+        ]]);
+        sequencer.update(at(2, 0.0), 120.0, &tuning);
+        let voices = sequencer.voices.active_voices();
+        sequencer.update(at(3, 0.0), 120.0, &tuning);
+        assert_eq!(sequencer.voices.frequency(), tuning.frequency(72));
+        assert_eq!(sequencer.voices.active_voices(), voices + 1);
+    }
+
+    #[test]
+    fn test_velocity_scales_the_voice() {
+        let tuning = Tuning::default();
+        let render = |velocity: f32| {
+            let mut sequencer = Sequencer::new(SAMPLE_RATE, Vec::new());
+            sequencer.noise_volume = 0.0;
+            sequencer.modulated_oscillator.mode = OscillatorMode::Fm;
+            sequencer.set_patterns(vec![vec![Step {
+                velocity,
+                ..Step::new(midi_to_note(60))
+            }]]);
+            let mut level = sequencer.update(at(0, 0.0), 120.0, &tuning)[0].abs();
+            for i in 1..50 {
+                level += sequencer.update(at(0, i as f32 * 0.001), 120.0, &tuning)[0].abs();
+            }
+            level
+        };
+        let loud = render(1.0);
+        let quiet = render(0.25);
+        assert!(loud > 0.0);
+        assert!((quiet / loud - 0.25).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_empty_sequence_gets_a_rest() {
+        let mut sequencer = Sequencer::new(SAMPLE_RATE, Vec::new());
+        assert_eq!(sequencer.length(0), 1);
+        assert!(sequencer.patterns()[0][0].is_rest());
+        sequencer.update(at(3, 0.0), 120.0, &Tuning::default());
+        assert_eq!(sequencer.voices.active_voices(), 0);
+    }
+}
@@ -1,119 +1,1022 @@
-use super::sequencer::Sequencer;
+use super::{
+    arpeggiator::{ArpMode, Arpeggiator, ChordType},
+    arrangement::{Arrangement, Section},
+    audio_engine::{create_sequencer, AudioEngine, DEFAULT_MASTER_GAIN},
+    effects::{AudioEffect, EffectChain, EffectSlot},
+    envelope::Envelope,
+    filter::{FilterType, MAX_CUTOFF},
+    mixer::{ChannelStrip, DEFAULT_GAIN},
+    modulated_oscillator::{ModulatedOscillator, OscillatorMode},
+    modulation::{ModDestination, ModMatrix, ModRoute, ModSource},
+    sampler::Sample,
+    sequencer::{Sequencer, VoiceType, DEFAULT_NOISE_VOLUME, DEFAULT_WAVE_VOLUME},
+    step::{Step, DEFAULT_GATE},
+    transport::DEFAULT_BPM,
+    tuning::{
+        Scale, Tuning, TuningSystem, DEFAULT_DIVISIONS, DEFAULT_REFERENCE, DEFAULT_ROOT,
+        MAX_DIVISIONS, MAX_REFERENCE, MAX_ROOT, MAX_TRANSPOSE, MIN_REFERENCE,
+    },
+    voice::{VoiceAllocator, DEFAULT_DETUNE, DEFAULT_POLYPHONY, DEFAULT_SPREAD},
+    wavetable_oscillator::Wavetable,
+};
 use kopek::{
     noise_generator::NoiseType,
     oscillator::WaveType,
-    utils::{Key, Octave},
+    utils::{Key, Note, Octave},
 };
 use serde::{Deserialize, Serialize};
 use std::{
+    fmt,
     fs::File,
     io::{Error, Read, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
 };
 
-pub fn save_song(sequencers: &Vec<Sequencer>) -> Result<(), Error> {
-    let mut seri_sequencers: [SequencerData; 3] = [SequencerData::default(); 3];
-    let mut envelopes: [EnvelopeData; 3] = [EnvelopeData::default(); 3];
-    let mut sequences: [[NoteData; 16]; 3] = [[NoteData::default(); 16]; 3];
-    for sequencer_index in 0..3 {
-        seri_sequencers[sequencer_index].vco_wave_type =
-            sequencers[sequencer_index].vco_wave_type().to_u8();
-        seri_sequencers[sequencer_index].lfo_wave_type =
-            sequencers[sequencer_index].lfo_wave_type().to_u8();
-        seri_sequencers[sequencer_index].noise_type =
-            sequencers[sequencer_index].noise_type() as u8;
-
-        envelopes[sequencer_index].attack = sequencers[sequencer_index].envelope.attack;
-        envelopes[sequencer_index].decay = sequencers[sequencer_index].envelope.decay;
-        envelopes[sequencer_index].sustain = sequencers[sequencer_index].envelope.sustain;
-        envelopes[sequencer_index].release = sequencers[sequencer_index].envelope.release;
-
-        for note_index in 0..16 {
-            sequences[sequencer_index][note_index] = NoteData {
-                octave: sequencers[sequencer_index].sequence[note_index].octave as i32,
-                key: sequencers[sequencer_index].sequence[note_index].key as i32,
-            }
+pub const SONG_VERSION: u32 = 14;
+const SONG_PATH: &str = "song.json";
+
+#[derive(Debug)]
+pub enum SongError {
+    Io(std::io::Error),
+    Parse(serde_json::Error),
+}
+
+impl fmt::Display for SongError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SongError::Io(e) => write!(f, "{e}"),
+            SongError::Parse(e) => write!(f, "{e}"),
         }
     }
+}
 
-    let song = Song {
-        sequencers: seri_sequencers,
-        envelopes,
-        sequences,
-    };
+pub fn save_song(audio_engine: &AudioEngine) -> Result<(), Error> {
+    let mut song = to_song(audio_engine);
+    let song_dir = song_dir(SONG_PATH);
+    for path in song.file_paths() {
+        *path = to_song_relative(path, &song_dir);
+    }
 
     let serialized = serde_json::to_string_pretty(&song).unwrap();
 
-    let mut file = File::create("song.json").unwrap();
+    let mut file = File::create(SONG_PATH).unwrap();
     file.write_all(serialized.as_bytes())
 }
 
-pub fn load_song(sequencers: &mut Vec<Sequencer>) {
-    let mut file = File::open("song.json").unwrap();
-    let mut contents = String::new();
-    file.read_to_string(&mut contents).unwrap();
+pub fn load_song(audio_engine: &mut AudioEngine) -> Result<(), SongError> {
+    load_song_from(SONG_PATH, audio_engine)
+}
 
-    let song: Song = serde_json::from_str(&contents).unwrap();
+/// The engine is left as it is when the song can't be read
+fn load_song_from(song_path: &str, audio_engine: &mut AudioEngine) -> Result<(), SongError> {
+    let mut file = File::open(song_path).map_err(SongError::Io)?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).map_err(SongError::Io)?;
 
-    for sequencer_index in 0..3 {
-        sequencers[sequencer_index].set_vco_wave_type(
-            WaveType::from_u8(song.sequencers[sequencer_index].vco_wave_type).unwrap(),
-        );
-        sequencers[sequencer_index].set_lfo_wave_type(
-            WaveType::from_u8(song.sequencers[sequencer_index].lfo_wave_type).unwrap(),
-        );
-        sequencers[sequencer_index].set_noise_type(
-            NoiseType::from_u8(song.sequencers[sequencer_index].noise_type).unwrap(),
+    let mut song = parse_song(&contents).map_err(SongError::Parse)?;
+    if song.version > SONG_VERSION {
+        eprintln!(
+            "{song_path} has version {}, newer than {}, some settings may be lost",
+            song.version, SONG_VERSION
         );
+    }
+    let song_dir = song_dir(song_path);
+    for path in song.file_paths() {
+        *path = song_dir.join(&path).to_string_lossy().into_owned();
+    }
 
-        sequencers[sequencer_index].envelope.attack = song.envelopes[sequencer_index].attack;
-        sequencers[sequencer_index].envelope.decay = song.envelopes[sequencer_index].decay;
-        sequencers[sequencer_index].envelope.sustain = song.envelopes[sequencer_index].sustain;
-        sequencers[sequencer_index].envelope.release = song.envelopes[sequencer_index].release;
+    apply_song(&song, audio_engine);
+    Ok(())
+}
 
-        for note_index in 0..16 {
-            let note = &mut sequencers[sequencer_index].sequence[note_index];
-            let note_data = song.sequences[sequencer_index][note_index];
-            note.octave = int_to_octave(note_data.octave);
-            note.key = int_to_key(note_data.key);
+fn song_dir(song_path: &str) -> PathBuf {
+    match Path::new(song_path).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+/// Sample paths are stored relative to the song so songs can be moved together with their
+/// samples, samples outside the song's directory keep their full path
+fn to_song_relative(path: &str, song_dir: &Path) -> String {
+    let relative = std::fs::canonicalize(path)
+        .ok()
+        .zip(std::fs::canonicalize(song_dir).ok())
+        .and_then(|(path, dir)| path.strip_prefix(dir).ok().map(Path::to_path_buf));
+    match relative {
+        Some(relative) => relative.to_string_lossy().into_owned(),
+        None => path.to_string(),
+    }
+}
+
+pub fn parse_song(contents: &str) -> Result<Song, serde_json::Error> {
+    let mut song: Song = match serde_json::from_str(contents)? {
+        SongFile::Versioned(song) => song,
+        SongFile::Legacy(legacy) => legacy.into(),
+    };
+    // Songs saved before patterns have a single sequence per track
+    for track in &mut song.tracks {
+        if track.patterns.is_empty() {
+            track.patterns.push(std::mem::take(&mut track.sequence));
         }
     }
+
+    Ok(song)
+}
+
+pub fn to_song(audio_engine: &AudioEngine) -> Song {
+    let tracks = audio_engine
+        .sequencers
+        .iter()
+        .map(|sequencer| TrackData {
+            sequencer: SequencerData {
+                vco_wave_type: sequencer.vco_wave_type().to_u8(),
+                lfo_wave_type: sequencer.lfo_wave_type().to_u8(),
+                noise_type: sequencer.noise_type() as u8,
+                voice_type: sequencer.voice_type.to_u8(),
+                wave_volume: sequencer.volume(),
+                noise_volume: sequencer.noise_volume,
+            },
+            envelope: to_envelope(&sequencer.envelope),
+            filter: FilterData {
+                filter_type: sequencer.filter.filter_type.to_u8(),
+                cutoff: sequencer.filter.cutoff,
+                resonance: sequencer.filter.resonance,
+                envelope_amount: sequencer.filter.envelope_amount,
+                envelope: to_envelope(&sequencer.filter_envelope),
+            },
+            modulation: ModulationData {
+                lfo_frequency: Some(sequencer.lfo_frequency()),
+                lfo_sync: sequencer.modulated_oscillator.lfo_sync,
+                routes: to_routes(&sequencer.modulation),
+            },
+            effects: to_effects(&sequencer.effects),
+            mixer: to_mixer(&sequencer.strip),
+            oscillator: OscillatorData {
+                mode: sequencer.modulated_oscillator.mode.to_u8(),
+                fm_ratio: sequencer.modulated_oscillator.fm.ratio,
+                fm_index: sequencer.modulated_oscillator.fm.index,
+                fm_feedback: sequencer.modulated_oscillator.fm.feedback,
+                wavetable: sequencer.modulated_oscillator.wavetable.table.path.clone(),
+                wavetable_position: sequencer.modulated_oscillator.wavetable.position,
+            },
+            sampler: SamplerData {
+                path: sequencer.sampler.sample.as_ref().map(|s| s.path.clone()),
+                choke_group: sequencer.sampler.choke_group,
+            },
+            voices: VoiceData {
+                polyphony: sequencer.voices.polyphony,
+                unison: sequencer.voices.unison,
+                detune: sequencer.voices.detune,
+                spread: sequencer.voices.spread,
+            },
+            arpeggiator: ArpeggiatorData {
+                mode: sequencer.arpeggiator.mode.to_u8(),
+                rate: sequencer.arpeggiator.rate,
+            },
+            sequence: Vec::new(),
+            patterns: sequencer
+                .patterns()
+                .iter()
+                .map(|pattern| pattern.iter().map(to_note_data).collect())
+                .collect(),
+        })
+        .collect();
+
+    Song {
+        version: SONG_VERSION,
+        bpm: audio_engine.transport.bpm(),
+        swing: audio_engine.transport.swing(),
+        master_effects: to_effects(&audio_engine.master_effects),
+        master_gain: audio_engine.master_gain,
+        input: to_mixer(&audio_engine.input.strip),
+        tuning: to_tuning(&audio_engine.tuning),
+        arrangement: ArrangementData {
+            active: audio_engine.arrangement.active,
+            sections: audio_engine
+                .arrangement
+                .sections
+                .iter()
+                .map(|section| SectionData {
+                    patterns: section.patterns.clone(),
+                    repeats: section.repeats,
+                })
+                .collect(),
+        },
+        tracks,
+    }
+}
+
+fn to_envelope(envelope: &Envelope) -> EnvelopeData {
+    EnvelopeData {
+        attack: envelope.attack,
+        decay: envelope.decay,
+        sustain_level: envelope.sustain_level,
+        release: envelope.release,
+        peak_level: envelope.peak_level,
+    }
 }
 
-pub fn clear_song(sequencers: &mut Vec<Sequencer>) {
-    for sequencer in sequencers {
-        for note in &mut sequencer.sequence {
-            note.octave = Octave::Third;
-            note.key = Key::Rest;
+fn to_tuning(tuning: &Tuning) -> TuningData {
+    TuningData {
+        system: tuning.system.to_u8(),
+        reference: tuning.reference,
+        divisions: tuning.divisions,
+        root: tuning.root,
+        transpose: tuning.transpose,
+        scale: tuning.scale.as_ref().map(|s| s.path.clone()),
+    }
+}
+
+fn to_mixer(strip: &ChannelStrip) -> MixerData {
+    MixerData {
+        gain: strip.gain,
+        pan: strip.pan,
+        mute: strip.mute,
+        solo: strip.solo,
+    }
+}
+
+fn to_routes(modulation: &ModMatrix) -> Vec<RouteData> {
+    modulation
+        .routes
+        .iter()
+        .map(|route| RouteData {
+            source: route.source.to_u8(),
+            destination: route.destination.to_u8(),
+            depth: route.depth,
+        })
+        .collect()
+}
+
+fn to_effects(effects: &EffectChain) -> Vec<EffectData> {
+    effects
+        .slots
+        .iter()
+        .map(|slot| {
+            let active = slot.active;
+            match &slot.effect {
+                AudioEffect::Distortion(e) => EffectData::Distortion {
+                    active,
+                    drive: e.drive,
+                    mix: e.mix,
+                },
+                AudioEffect::Bitcrusher(e) => EffectData::Bitcrusher {
+                    active,
+                    bits: e.bits,
+                    downsample: e.downsample,
+                    mix: e.mix,
+                },
+                AudioEffect::Delay(e) => EffectData::Delay {
+                    active,
+                    steps: e.steps,
+                    feedback: e.feedback,
+                    mix: e.mix,
+                },
+                AudioEffect::Reverb(e) => EffectData::Reverb {
+                    active,
+                    room_size: e.room_size,
+                    damping: e.damping,
+                    mix: e.mix,
+                },
+            }
+        })
+        .collect()
+}
+
+/// Creates or removes sequencers so there is one per track, the playhead is left where it is
+pub fn apply_song(song: &Song, audio_engine: &mut AudioEngine) {
+    let sample_rate = audio_engine.sample_rate();
+    let sequencers = &mut audio_engine.sequencers;
+    sequencers.truncate(song.tracks.len());
+    while sequencers.len() < song.tracks.len() {
+        sequencers.push(create_sequencer(sample_rate));
+    }
+    for (sequencer, track) in sequencers.iter_mut().zip(&song.tracks) {
+        apply_track(track, sequencer);
+    }
+    apply_song_settings(song, audio_engine);
+}
+
+/// Tempo, tuning, master bus and arrangement, the tracks are left as they are
+pub fn apply_song_settings(song: &Song, audio_engine: &mut AudioEngine) {
+    apply_master_settings(song, audio_engine);
+    audio_engine.arrangement = to_arrangement(&song.arrangement, audio_engine.sequencers.len());
+}
+
+/// Tempo, tuning and master bus, the parts of the song that don't allocate
+pub fn apply_master_settings(song: &Song, audio_engine: &mut AudioEngine) {
+    audio_engine.transport.set_bpm(song.bpm);
+    audio_engine.transport.set_swing(song.swing);
+    apply_effects(&song.master_effects, &mut audio_engine.master_effects);
+    audio_engine.master_gain = song.master_gain;
+    audio_engine.input.strip = to_strip(&song.input);
+    apply_tuning(&song.tuning, &mut audio_engine.tuning);
+}
+
+/// Sections with one pattern entry for each of `track_count` tracks
+pub fn to_arrangement(data: &ArrangementData, track_count: usize) -> Arrangement {
+    let mut arrangement = Arrangement {
+        sections: data.sections.iter().map(Section::from).collect(),
+        active: data.active,
+    };
+    arrangement.set_track_count(track_count);
+    arrangement
+}
+
+/// Settings and patterns of one track, voices that are playing carry on
+pub fn apply_track(track: &TrackData, sequencer: &mut Sequencer) {
+    apply_track_settings(track, sequencer);
+    sequencer.modulation.routes = to_mod_routes(&track.modulation);
+    sequencer.set_patterns(to_step_patterns(track));
+}
+
+/// Everything but the patterns and modulation routes, which are the parts that allocate
+pub fn apply_track_settings(track: &TrackData, sequencer: &mut Sequencer) {
+    let vco_wave_type = WaveType::from_u8(track.sequencer.vco_wave_type).unwrap();
+    if vco_wave_type != sequencer.vco_wave_type() {
+        sequencer.set_vco_wave_type(vco_wave_type);
+    }
+    let lfo_wave_type = WaveType::from_u8(track.sequencer.lfo_wave_type).unwrap();
+    if lfo_wave_type != sequencer.lfo_wave_type() {
+        sequencer.set_lfo_wave_type(lfo_wave_type);
+    }
+    sequencer.set_noise_type(NoiseType::from_u8(track.sequencer.noise_type).unwrap());
+    sequencer.voice_type =
+        VoiceType::from_u8(track.sequencer.voice_type).unwrap_or(VoiceType::Synth);
+    sequencer.set_volume(track.sequencer.wave_volume);
+    sequencer.noise_volume = track.sequencer.noise_volume;
+    apply_oscillator(&track.oscillator, &mut sequencer.modulated_oscillator);
+    apply_sampler(&track.sampler, sequencer);
+    apply_voices(&track.voices, &mut sequencer.voices);
+    apply_arpeggiator(&track.arpeggiator, &mut sequencer.arpeggiator);
+
+    apply_envelope(&track.envelope, &mut sequencer.envelope);
+    sequencer.filter.filter_type =
+        FilterType::from_u8(track.filter.filter_type).unwrap_or(FilterType::LowPass);
+    sequencer.filter.cutoff = track.filter.cutoff;
+    sequencer.filter.resonance = track.filter.resonance;
+    sequencer.filter.envelope_amount = track.filter.envelope_amount;
+    apply_envelope(&track.filter.envelope, &mut sequencer.filter_envelope);
+
+    if let Some(lfo_frequency) = track.modulation.lfo_frequency {
+        sequencer.set_lfo_frequency(lfo_frequency);
+    }
+    sequencer.modulated_oscillator.lfo_sync = track.modulation.lfo_sync;
+    apply_effects(&track.effects, &mut sequencer.effects);
+    sequencer.strip = to_strip(&track.mixer);
+}
+
+/// Routes with an unknown source or destination are dropped
+pub fn to_mod_routes(modulation: &ModulationData) -> Vec<ModRoute> {
+    modulation
+        .routes
+        .iter()
+        .filter_map(|route| {
+            Some(ModRoute {
+                source: ModSource::from_u8(route.source)?,
+                destination: ModDestination::from_u8(route.destination)?,
+                depth: route.depth,
+            })
+        })
+        .collect()
+}
+
+pub fn to_step_patterns(track: &TrackData) -> Vec<Vec<Step>> {
+    track
+        .patterns
+        .iter()
+        .map(|pattern| pattern.iter().map(to_step).collect())
+        .collect()
+}
+
+pub fn to_note_data(step: &Step) -> NoteData {
+    NoteData {
+        octave: step.note.octave as i32,
+        key: step.note.key as i32,
+        velocity: step.velocity,
+        gate: step.gate,
+        slide: step.slide,
+        probability: step.probability,
+        offset: step.offset,
+        chord: step.chord.map(ChordType::to_u8),
+    }
+}
+
+pub fn to_step(note_data: &NoteData) -> Step {
+    Step {
+        note: Note {
+            octave: int_to_octave(note_data.octave),
+            key: int_to_key(note_data.key),
+        },
+        velocity: note_data.velocity,
+        gate: note_data.gate,
+        slide: note_data.slide,
+        probability: note_data.probability,
+        offset: note_data.offset,
+        chord: note_data.chord.and_then(ChordType::from_u8),
+    }
+}
+
+/// Wavetables that are already loaded are kept, missing files fall back to the built-in table
+fn apply_oscillator(data: &OscillatorData, oscillator: &mut ModulatedOscillator) {
+    oscillator.mode = OscillatorMode::from_u8(data.mode).unwrap_or(OscillatorMode::Vco);
+    oscillator.fm.ratio = data.fm_ratio;
+    oscillator.fm.index = data.fm_index;
+    oscillator.fm.feedback = data.fm_feedback;
+    oscillator.wavetable.position = data.wavetable_position;
+    if oscillator.wavetable.table.path == data.wavetable {
+        return;
+    }
+    oscillator.wavetable.table = match &data.wavetable {
+        Some(path) => Wavetable::load(path).map(Arc::new).unwrap_or_else(|e| {
+            eprintln!("Error loading wavetable {path}: {e}");
+            Wavetable::basic()
+        }),
+        None => Wavetable::basic(),
+    };
+}
+
+fn apply_voices(data: &VoiceData, voices: &mut VoiceAllocator) {
+    voices.polyphony = data.polyphony;
+    voices.unison = data.unison;
+    voices.detune = data.detune;
+    voices.spread = data.spread;
+}
+
+fn apply_arpeggiator(data: &ArpeggiatorData, arpeggiator: &mut Arpeggiator) {
+    arpeggiator.mode = ArpMode::from_u8(data.mode).unwrap_or(ArpMode::Off);
+    arpeggiator.rate = data.rate;
+}
+
+/// Samples that are already loaded are kept, missing files leave the sampler silent
+fn apply_sampler(data: &SamplerData, sequencer: &mut Sequencer) {
+    let sampler = &mut sequencer.sampler;
+    sampler.choke_group = data.choke_group;
+    let Some(path) = &data.path else {
+        sampler.sample = None;
+        return;
+    };
+    if sampler.sample.as_ref().is_some_and(|s| &s.path == path) {
+        return;
+    }
+    sampler.sample = match Sample::load(path) {
+        Ok(sample) => Some(sample),
+        Err(e) => {
+            eprintln!("Error loading sample {path}: {e}");
+            None
+        }
+    };
+}
+
+/// Values are kept to the ranges the interface allows, a reference of 0 would silence every
+/// track. Scales that are already loaded are kept, missing files fall back to standard pitch.
+fn apply_tuning(data: &TuningData, tuning: &mut Tuning) {
+    tuning.system = TuningSystem::from_u8(data.system).unwrap_or(TuningSystem::Equal);
+    tuning.reference = data.reference.clamp(MIN_REFERENCE, MAX_REFERENCE);
+    tuning.divisions = data.divisions.clamp(1, MAX_DIVISIONS);
+    tuning.root = data.root.min(MAX_ROOT);
+    tuning.transpose = data.transpose.clamp(-MAX_TRANSPOSE, MAX_TRANSPOSE);
+    if tuning.scale.as_ref().map(|s| &s.path) == data.scale.as_ref() {
+        return;
+    }
+    tuning.scale = data
+        .scale
+        .as_ref()
+        .and_then(|path| match Scale::load(path) {
+            Ok(scale) => Some(Arc::new(scale)),
+            Err(e) => {
+                eprintln!("Error loading scale {path}: {e}");
+                None
+            }
+        });
+}
+
+fn apply_envelope(data: &EnvelopeData, envelope: &mut Envelope) {
+    envelope.attack = data.attack;
+    envelope.decay = data.decay;
+    envelope.sustain_level = data.sustain_level;
+    envelope.release = data.release;
+    envelope.peak_level = data.peak_level;
+}
+
+fn to_strip(data: &MixerData) -> ChannelStrip {
+    ChannelStrip {
+        gain: data.gain,
+        pan: data.pan,
+        mute: data.mute,
+        solo: data.solo,
+    }
+}
+
+/// Reorders the chain to match the song, effects the song doesn't mention are switched off
+fn apply_effects(effects: &[EffectData], chain: &mut EffectChain) {
+    for slot in &mut chain.slots {
+        slot.active = false;
+    }
+
+    let mut position = 0;
+    for data in effects {
+        let found = chain.slots[position..]
+            .iter_mut()
+            .position(|slot| apply_effect(data, slot));
+        if let Some(index) = found {
+            chain.slots.swap(position, position + index);
+            position += 1;
+        }
+    }
+}
+
+/// Copies the settings if `data` is for the effect in `slot`
+fn apply_effect(data: &EffectData, slot: &mut EffectSlot) -> bool {
+    match (*data, &mut slot.effect) {
+        (EffectData::Distortion { active, drive, mix }, AudioEffect::Distortion(e)) => {
+            slot.active = active;
+            e.drive = drive;
+            e.mix = mix;
+        }
+        (
+            EffectData::Bitcrusher {
+                active,
+                bits,
+                downsample,
+                mix,
+            },
+            AudioEffect::Bitcrusher(e),
+        ) => {
+            slot.active = active;
+            e.bits = bits;
+            e.downsample = downsample;
+            e.mix = mix;
+        }
+        (
+            EffectData::Delay {
+                active,
+                steps,
+                feedback,
+                mix,
+            },
+            AudioEffect::Delay(e),
+        ) => {
+            slot.active = active;
+            e.steps = steps;
+            e.feedback = feedback;
+            e.mix = mix;
+        }
+        (
+            EffectData::Reverb {
+                active,
+                room_size,
+                damping,
+                mix,
+            },
+            AudioEffect::Reverb(e),
+        ) => {
+            slot.active = active;
+            e.room_size = room_size;
+            e.damping = damping;
+            e.mix = mix;
+        }
+        _ => return false,
+    }
+    true
+}
+
+pub fn clear_song(audio_engine: &mut AudioEngine) {
+    for sequencer in &mut audio_engine.sequencers {
+        for pattern in 0..sequencer.patterns().len() {
+            if let Some(steps) = sequencer.pattern_mut(pattern) {
+                steps.fill(Step::default());
+            }
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Song {
-    pub sequencers: [SequencerData; 3],
-    pub envelopes: [EnvelopeData; 3],
-    pub sequences: [[NoteData; 16]; 3],
+    pub version: u32,
+    /// Quarter notes per minute
+    #[serde(default = "default_bpm")]
+    pub bpm: f32,
+    #[serde(default)]
+    pub swing: f32,
+    #[serde(default)]
+    pub master_effects: Vec<EffectData>,
+    #[serde(default = "default_master_gain")]
+    pub master_gain: f32,
+    /// Live input's channel strip
+    #[serde(default = "default_input_mixer")]
+    pub input: MixerData,
+    #[serde(default)]
+    pub tuning: TuningData,
+    #[serde(default)]
+    pub arrangement: ArrangementData,
+    pub tracks: Vec<TrackData>,
+}
+
+impl Song {
+    /// Files the song loads, relative to the song file once saved
+    fn file_paths(&mut self) -> impl Iterator<Item = &mut String> {
+        self.tuning
+            .scale
+            .iter_mut()
+            .chain(self.tracks.iter_mut().flat_map(TrackData::file_paths))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrackData {
+    pub sequencer: SequencerData,
+    pub envelope: EnvelopeData,
+    #[serde(default)]
+    pub filter: FilterData,
+    #[serde(default)]
+    pub modulation: ModulationData,
+    #[serde(default)]
+    pub effects: Vec<EffectData>,
+    #[serde(default)]
+    pub mixer: MixerData,
+    #[serde(default)]
+    pub oscillator: OscillatorData,
+    #[serde(default)]
+    pub sampler: SamplerData,
+    #[serde(default = "default_legacy_voices")]
+    pub voices: VoiceData,
+    #[serde(default)]
+    pub arpeggiator: ArpeggiatorData,
+    /// Only read from songs saved before patterns, `parse_song` moves it into `patterns`
+    #[serde(default, skip_serializing)]
+    sequence: Vec<NoteData>,
+    #[serde(default)]
+    pub patterns: Vec<Vec<NoteData>>,
 }
 
-#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+impl TrackData {
+    /// Files the track loads, relative to the song file once saved
+    fn file_paths(&mut self) -> impl Iterator<Item = &mut String> {
+        self.sampler
+            .path
+            .iter_mut()
+            .chain(self.oscillator.wavetable.iter_mut())
+    }
+}
+
+/// Songs saved before versioning, always three tracks of 16 steps
+#[derive(Debug, Deserialize)]
+struct LegacySong {
+    sequencers: [SequencerData; 3],
+    envelopes: [EnvelopeData; 3],
+    sequences: [[NoteData; 16]; 3],
+}
+
+impl From<LegacySong> for Song {
+    fn from(legacy: LegacySong) -> Self {
+        let tracks = (0..3)
+            .map(|i| TrackData {
+                sequencer: legacy.sequencers[i],
+                envelope: legacy.envelopes[i],
+                filter: FilterData::default(),
+                modulation: ModulationData::default(),
+                effects: Vec::new(),
+                mixer: MixerData::default(),
+                oscillator: OscillatorData::default(),
+                sampler: SamplerData::default(),
+                voices: default_legacy_voices(),
+                arpeggiator: ArpeggiatorData::default(),
+                sequence: Vec::new(),
+                patterns: vec![legacy.sequences[i].to_vec()],
+            })
+            .collect();
+
+        Song {
+            version: 0,
+            bpm: DEFAULT_BPM,
+            swing: 0.0,
+            master_effects: Vec::new(),
+            master_gain: DEFAULT_MASTER_GAIN,
+            input: default_input_mixer(),
+            tuning: TuningData::default(),
+            arrangement: ArrangementData::default(),
+            tracks,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum SongFile {
+    Versioned(Song),
+    Legacy(LegacySong),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct SequencerData {
     pub vco_wave_type: u8,
     pub lfo_wave_type: u8,
     pub noise_type: u8,
+    #[serde(default)]
+    pub voice_type: u8,
+    #[serde(default = "default_wave_volume")]
+    pub wave_volume: f32,
+    #[serde(default = "default_noise_volume")]
+    pub noise_volume: f32,
 }
 
-#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+/// Older songs stored `sustain` as a duration, it is ignored and the levels fall back to the
+/// values the envelope used to hard-code
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
 pub struct EnvelopeData {
     pub attack: f32,
     pub decay: f32,
-    pub sustain: f32,
+    #[serde(default = "default_sustain_level")]
+    pub sustain_level: f32,
     pub release: f32,
+    #[serde(default = "default_peak_level")]
+    pub peak_level: f32,
+}
+
+/// Songs without a filter get one that is fully open
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FilterData {
+    pub filter_type: u8,
+    pub cutoff: f32,
+    pub resonance: f32,
+    pub envelope_amount: f32,
+    pub envelope: EnvelopeData,
+}
+
+impl Default for FilterData {
+    fn default() -> Self {
+        Self {
+            filter_type: FilterType::LowPass.to_u8(),
+            cutoff: MAX_CUTOFF,
+            resonance: 0.0,
+            envelope_amount: 0.0,
+            envelope: EnvelopeData {
+                attack: 0.01,
+                decay: 0.2,
+                sustain_level: 0.0,
+                release: 0.1,
+                peak_level: 1.0,
+            },
+        }
+    }
+}
+
+/// Songs without modulation get the vibrato tracks used to have, the LFO keeps its rate
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModulationData {
+    #[serde(default)]
+    pub lfo_frequency: Option<f32>,
+    #[serde(default)]
+    pub lfo_sync: Option<u32>,
+    pub routes: Vec<RouteData>,
+}
+
+impl Default for ModulationData {
+    fn default() -> Self {
+        Self {
+            lfo_frequency: None,
+            lfo_sync: None,
+            routes: to_routes(&ModMatrix::new()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RouteData {
+    pub source: u8,
+    pub destination: u8,
+    pub depth: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct MixerData {
+    pub gain: f32,
+    pub pan: f32,
+    pub mute: bool,
+    pub solo: bool,
+}
+
+impl Default for MixerData {
+    fn default() -> Self {
+        Self {
+            gain: DEFAULT_GAIN,
+            pan: 0.0,
+            mute: false,
+            solo: false,
+        }
+    }
+}
+
+/// Songs without it play in standard pitch
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TuningData {
+    pub system: u8,
+    pub reference: f32,
+    pub divisions: u32,
+    pub root: u8,
+    pub transpose: i32,
+    /// Scala file, relative to the song file once saved
+    pub scale: Option<String>,
+}
+
+impl Default for TuningData {
+    fn default() -> Self {
+        Self {
+            system: TuningSystem::Equal.to_u8(),
+            reference: DEFAULT_REFERENCE,
+            divisions: DEFAULT_DIVISIONS,
+            root: DEFAULT_ROOT,
+            transpose: 0,
+            scale: None,
+        }
+    }
+}
+
+/// Songs without it use the VCO
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OscillatorData {
+    pub mode: u8,
+    pub fm_ratio: f32,
+    pub fm_index: f32,
+    pub fm_feedback: f32,
+    /// Built-in table when there is none, relative to the song file once saved
+    pub wavetable: Option<String>,
+    pub wavetable_position: f32,
+}
+
+impl Default for OscillatorData {
+    fn default() -> Self {
+        Self {
+            mode: OscillatorMode::Vco.to_u8(),
+            fm_ratio: 1.0,
+            fm_index: 1.0,
+            fm_feedback: 0.0,
+            wavetable: None,
+            wavetable_position: 0.0,
+        }
+    }
+}
+
+/// `path` is relative to the song file once saved
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct SamplerData {
+    pub path: Option<String>,
+    pub choke_group: Option<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct VoiceData {
+    pub polyphony: usize,
+    pub unison: usize,
+    pub detune: f32,
+    pub spread: f32,
+}
+
+impl Default for VoiceData {
+    fn default() -> Self {
+        Self {
+            polyphony: DEFAULT_POLYPHONY,
+            unison: 1,
+            detune: DEFAULT_DETUNE,
+            spread: DEFAULT_SPREAD,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ArpeggiatorData {
+    pub mode: u8,
+    /// Notes per bar
+    pub rate: u32,
+}
+
+impl Default for ArpeggiatorData {
+    fn default() -> Self {
+        Self {
+            mode: ArpMode::Off.to_u8(),
+            rate: 16,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct ArrangementData {
+    pub active: bool,
+    pub sections: Vec<SectionData>,
+}
+
+/// Pattern index by track
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SectionData {
+    pub patterns: Vec<usize>,
+    pub repeats: u32,
+}
+
+impl From<&SectionData> for Section {
+    fn from(data: &SectionData) -> Self {
+        Self {
+            patterns: data.patterns.clone(),
+            repeats: data.repeats,
+        }
+    }
+}
+
+/// Effects in chain order
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EffectData {
+    Distortion {
+        active: bool,
+        drive: f32,
+        mix: f32,
+    },
+    Bitcrusher {
+        active: bool,
+        bits: u32,
+        downsample: u32,
+        mix: f32,
+    },
+    Delay {
+        active: bool,
+        steps: u32,
+        feedback: f32,
+        mix: f32,
+    },
+    Reverb {
+        active: bool,
+        room_size: f32,
+        damping: f32,
+        mix: f32,
+    },
+}
+
+fn default_wave_volume() -> f32 {
+    DEFAULT_WAVE_VOLUME
+}
+
+fn default_noise_volume() -> f32 {
+    DEFAULT_NOISE_VOLUME
+}
+
+fn default_master_gain() -> f32 {
+    DEFAULT_MASTER_GAIN
+}
+
+/// Muted, an open microphone shouldn't play through the speakers when a song loads
+fn default_input_mixer() -> MixerData {
+    MixerData {
+        mute: true,
+        ..MixerData::default()
+    }
+}
+
+/// Songs without voices play one per track, the way they were written
+fn default_legacy_voices() -> VoiceData {
+    VoiceData {
+        polyphony: 1,
+        ..VoiceData::default()
+    }
+}
+
+fn default_bpm() -> f32 {
+    DEFAULT_BPM
 }
 
-#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+fn default_sustain_level() -> f32 {
+    0.6
+}
+
+fn default_peak_level() -> f32 {
+    1.0
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct NoteData {
     pub octave: i32,
     pub key: i32,
+    #[serde(default = "default_one")]
+    pub velocity: f32,
+    #[serde(default = "default_gate")]
+    pub gate: f32,
+    #[serde(default)]
+    pub slide: bool,
+    #[serde(default = "default_one")]
+    pub probability: f32,
+    #[serde(default)]
+    pub offset: f32,
+    #[serde(default)]
+    pub chord: Option<u8>,
+}
+
+fn default_one() -> f32 {
+    1.0
+}
+
+fn default_gate() -> f32 {
+    DEFAULT_GATE
 }
 
 pub fn int_to_key(i: i32) -> Key {
@@ -145,3 +1048,221 @@ pub fn int_to_octave(i: i32) -> Octave {
         _ => Octave::Third,
     }
 }
+
+#[cfg(test)]
+mod song_tests {
+    use super::*;
+
+    /// Unique to the test run so runs at the same time don't share files
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("fo_rma_{}_{name}", std::process::id()))
+    }
+
+    #[test]
+    fn test_missing_song_is_an_error() {
+        let mut audio_engine = AudioEngine::new(44100);
+        let path = temp_path("missing.json");
+        let result = load_song_from(path.to_str().unwrap(), &mut audio_engine);
+        assert!(matches!(result, Err(SongError::Io(_))));
+    }
+
+    #[test]
+    fn test_legacy_song() {
+        let song = parse_song(include_str!("../../song.json")).unwrap();
+        assert_eq!(song.version, 0);
+        assert_eq!(song.bpm, DEFAULT_BPM);
+        assert_eq!(song.tracks.len(), 3);
+        for track in &song.tracks {
+            assert_eq!(track.patterns[0].len(), 16);
+            assert_eq!(track.envelope.peak_level, 1.0);
+            assert_eq!(track.voices.polyphony, 1);
+        }
+    }
+
+    #[test]
+    fn test_new_tracks_keep_their_voices() {
+        let audio_engine = AudioEngine::new(44100);
+        let song = to_song(&audio_engine);
+        assert_eq!(song.tracks[0].voices, VoiceData::default());
+    }
+
+    #[test]
+    fn test_versioned_song() {
+        let json = r#"{
+            "version": 1,
+            "tracks": [{
+                "sequencer": { "vco_wave_type": 0, "lfo_wave_type": 0, "noise_type": 0 },
+                "envelope": { "attack": 0.1, "decay": 0.1, "sustain_level": 0.5, "release": 0.1, "peak_level": 0.8 },
+                "sequence": [{ "octave": 2, "key": 0 }, { "octave": 2, "key": 12 }]
+            }]
+        }"#;
+        let song = parse_song(json).unwrap();
+        assert_eq!(song.version, 1);
+        assert_eq!(song.tracks.len(), 1);
+        assert_eq!(song.tracks[0].patterns.len(), 1);
+        assert_eq!(song.tracks[0].patterns[0].len(), 2);
+        assert_eq!(song.tracks[0].envelope.sustain_level, 0.5);
+        assert_eq!(song.bpm, DEFAULT_BPM);
+        assert_eq!(song.tracks[0].filter.cutoff, MAX_CUTOFF);
+        assert_eq!(song.tracks[0].modulation.routes.len(), 1);
+        assert_eq!(song.tracks[0].sequencer.wave_volume, DEFAULT_WAVE_VOLUME);
+        assert!(song.input.mute);
+        assert_eq!(song.tuning, TuningData::default());
+    }
+
+    #[test]
+    fn test_tempo_round_trip() {
+        let mut audio_engine = AudioEngine::new(44100);
+        audio_engine.transport.set_bpm(93.5);
+        audio_engine.transport.set_swing(0.4);
+        let json = serde_json::to_string(&to_song(&audio_engine)).unwrap();
+
+        let mut loaded = AudioEngine::new(44100);
+        apply_song(&parse_song(&json).unwrap(), &mut loaded);
+        assert_eq!(loaded.transport.bpm(), 93.5);
+        assert_eq!(loaded.transport.swing(), 0.4);
+    }
+
+    #[test]
+    fn test_tuning_round_trip() {
+        let scale_path = temp_path("tuning_test.scl");
+        std::fs::write(&scale_path, "fifths\n2\n3/2\n2/1\n").unwrap();
+        let mut audio_engine = AudioEngine::new(44100);
+        audio_engine.tuning = Tuning {
+            system: TuningSystem::Scala,
+            reference: 432.0,
+            transpose: -3,
+            scale: Some(Arc::new(
+                Scale::load(&scale_path.to_string_lossy()).unwrap(),
+            )),
+            ..Tuning::default()
+        };
+        let json = serde_json::to_string(&to_song(&audio_engine)).unwrap();
+
+        let mut loaded = AudioEngine::new(44100);
+        apply_song(&parse_song(&json).unwrap(), &mut loaded);
+        assert_eq!(loaded.tuning, audio_engine.tuning);
+
+        // A scale that can't be found plays in standard pitch
+        let mut song = to_song(&audio_engine);
+        song.tuning.scale = Some(String::from("missing.scl"));
+        apply_song(&song, &mut loaded);
+        assert_eq!(loaded.tuning.scale, None);
+        assert_eq!(loaded.tuning.frequency(72), 432.0);
+        std::fs::remove_file(scale_path).unwrap();
+
+        song.tuning = TuningData {
+            reference: 0.0,
+            divisions: 0,
+            root: 200,
+            transpose: 1000,
+            ..TuningData::default()
+        };
+        apply_song(&song, &mut loaded);
+        assert_eq!(loaded.tuning.reference, MIN_REFERENCE);
+        assert_eq!(loaded.tuning.divisions, 1);
+        assert_eq!(loaded.tuning.root, MAX_ROOT);
+        assert_eq!(loaded.tuning.transpose, MAX_TRANSPOSE);
+    }
+
+    #[test]
+    fn test_effects_round_trip() {
+        let mut audio_engine = AudioEngine::new(44100);
+        let effects = &mut audio_engine.sequencers[0].effects;
+        effects.slots.swap(0, 2);
+        effects.slots[0].active = true;
+        if let AudioEffect::Delay(delay) = &mut effects.slots[0].effect {
+            delay.steps = 5;
+        }
+        audio_engine.master_effects.slots[3].active = true;
+        let json = serde_json::to_string(&to_song(&audio_engine)).unwrap();
+
+        let mut loaded = AudioEngine::new(44100);
+        apply_song(&parse_song(&json).unwrap(), &mut loaded);
+        let slots = &loaded.sequencers[0].effects.slots;
+        assert!(slots[0].active);
+        assert!(matches!(&slots[0].effect, AudioEffect::Delay(delay) if delay.steps == 5));
+        assert!(slots[1..].iter().all(|slot| !slot.active));
+        assert!(loaded.master_effects.slots[3].active);
+    }
+
+    #[test]
+    fn test_arrangement_round_trip() {
+        let mut audio_engine = AudioEngine::new(44100);
+        let sequencer = &mut audio_engine.sequencers[1];
+        let pattern = sequencer.duplicate_pattern(0).unwrap();
+        assert_eq!(sequencer.set_length(pattern, 32), Some(32));
+        audio_engine.arrangement.sections = vec![
+            Section::new(3),
+            Section {
+                patterns: vec![0, pattern, 0],
+                repeats: 2,
+            },
+        ];
+        audio_engine.arrangement.active = true;
+        let json = serde_json::to_string(&to_song(&audio_engine)).unwrap();
+
+        let mut loaded = AudioEngine::new(44100);
+        apply_song(&parse_song(&json).unwrap(), &mut loaded);
+        assert_eq!(loaded.sequencers[1].patterns().len(), 2);
+        assert_eq!(loaded.sequencers[1].length(1), 32);
+        assert!(loaded.arrangement.active);
+        assert_eq!(
+            loaded.arrangement.sections,
+            audio_engine.arrangement.sections
+        );
+    }
+
+    #[test]
+    fn test_chord_round_trip() {
+        let mut audio_engine = AudioEngine::new(44100);
+        let sequencer = &mut audio_engine.sequencers[0];
+        sequencer.pattern_mut(0).unwrap()[2].chord = Some(ChordType::Minor7);
+        sequencer.arpeggiator.mode = ArpMode::UpDown;
+        sequencer.arpeggiator.rate = 24;
+        let json = serde_json::to_string(&to_song(&audio_engine)).unwrap();
+
+        let mut loaded = AudioEngine::new(44100);
+        apply_song(&parse_song(&json).unwrap(), &mut loaded);
+        let sequencer = &loaded.sequencers[0];
+        assert_eq!(sequencer.patterns()[0][2].chord, Some(ChordType::Minor7));
+        assert_eq!(sequencer.patterns()[0][3].chord, None);
+        assert_eq!(sequencer.arpeggiator.mode, ArpMode::UpDown);
+        assert_eq!(sequencer.arpeggiator.rate, 24);
+    }
+
+    #[test]
+    fn test_sample_paths_are_relative_to_song() {
+        let song_dir = temp_path("song_test");
+        let sample_path = song_dir.join("samples").join("kick.wav");
+        std::fs::create_dir_all(sample_path.parent().unwrap()).unwrap();
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 44100,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&sample_path, spec).unwrap();
+        for i in 0..100 {
+            writer.write_sample((i * 100) as i16).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let sample_path = sample_path.to_string_lossy().into_owned();
+        let relative = to_song_relative(&sample_path, &song_dir);
+        assert_eq!(Path::new(&relative), Path::new("samples").join("kick.wav"));
+        let outside = std::env::temp_dir().join("kick.wav");
+        let outside = outside.to_string_lossy();
+        assert_eq!(to_song_relative(&outside, &song_dir), outside);
+
+        let mut audio_engine = AudioEngine::new(44100);
+        let data = SamplerData {
+            path: Some(sample_path),
+            choke_group: Some(1),
+        };
+        apply_sampler(&data, &mut audio_engine.sequencers[0]);
+        let sample = audio_engine.sequencers[0].sampler.sample.as_ref().unwrap();
+        assert_eq!(sample.len(), 100);
+        std::fs::remove_dir_all(song_dir).unwrap();
+    }
+}
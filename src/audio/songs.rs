@@ -1,5 +1,52 @@
+use super::{midi, step::Step, transport::STEPS_PER_BAR};
 use kopek::utils::{self, Key, Note, Octave};
 
+/// Songs that can be loaded into a track, see `preset`
+pub const PRESETS: [&str; 6] = [
+    "jingle bells",
+    "twinkle star",
+    "billie jean",
+    "billie jean 2",
+    "on my own",
+    "spybreak",
+];
+
+/// Notes of the song at `index` in `PRESETS`
+pub fn preset(index: usize) -> Option<Vec<Note>> {
+    let from_frequencies = |frequencies: &[f32]| frequencies.iter().map(|f| to_note(*f)).collect();
+    let notes = match index {
+        0 => from_frequencies(&JINGLE_BELLS),
+        1 => from_frequencies(&TWINKLE_STAR),
+        2 => from_frequencies(&BILLIE_JEAN),
+        3 => from_frequencies(&BILLIE_JEAN_2),
+        4 => from_frequencies(&ON_MY_OWN),
+        5 => SPYBREAK.to_vec(),
+        _ => return None,
+    };
+    Some(notes)
+}
+
+/// Splits a song into one bar patterns, the last one is filled up with rests
+pub fn to_patterns(notes: &[Note]) -> Vec<Vec<Step>> {
+    notes
+        .chunks(STEPS_PER_BAR as usize)
+        .map(|chunk| {
+            let mut pattern: Vec<Step> = chunk.iter().copied().map(Step::new).collect();
+            pattern.resize(STEPS_PER_BAR as usize, Step::default());
+            pattern
+        })
+        .collect()
+}
+
+/// Nearest note to a frequency, `utils::REST` and other silent frequencies are rests
+fn to_note(frequency: f32) -> Note {
+    if frequency <= 0.0 {
+        return Step::default().note;
+    }
+    let midi_note = 69.0 + 12.0 * (frequency / 440.0).log2();
+    midi::midi_to_note(midi_note.round().clamp(0.0, 127.0) as u8)
+}
+
 pub const JINGLE_BELLS: [f32; 64] = [
     // Verse 1
     utils::E_FREQ,
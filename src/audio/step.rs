@@ -0,0 +1,49 @@
+use super::arpeggiator::ChordType;
+use kopek::utils::{Key, Note, Octave};
+
+/// Fraction of a step the gate stays open
+pub const DEFAULT_GATE: f32 = 0.5;
+
+/// A sequencer step, 303 style. `slide` glides into the next step without retriggering it.
+#[derive(Debug, Clone, Copy)]
+pub struct Step {
+    pub note: Note,
+    pub velocity: f32,
+    pub gate: f32,
+    pub slide: bool,
+    pub probability: f32,
+    /// 0.0..1.0 into the sample where sampler tracks start playing, synth tracks ignore it
+    pub offset: f32,
+    /// Played above `note`, or arpeggiated when the track's arpeggiator is on
+    pub chord: Option<ChordType>,
+}
+
+impl Step {
+    pub fn new(note: Note) -> Self {
+        Self {
+            note,
+            ..Self::default()
+        }
+    }
+
+    pub fn is_rest(&self) -> bool {
+        self.note.key == Key::Rest
+    }
+}
+
+impl Default for Step {
+    fn default() -> Self {
+        Self {
+            note: Note {
+                octave: Octave::Third,
+                key: Key::Rest,
+            },
+            velocity: 1.0,
+            gate: DEFAULT_GATE,
+            slide: false,
+            probability: 1.0,
+            offset: 0.0,
+            chord: None,
+        }
+    }
+}
@@ -0,0 +1,236 @@
+pub const DEFAULT_BPM: f32 = 120.0;
+pub const MIN_BPM: f32 = 20.0;
+pub const MAX_BPM: f32 = 300.0;
+pub const STEPS_PER_BEAT: u32 = 4;
+pub const BEATS_PER_BAR: u32 = 4;
+pub const STEPS_PER_BAR: u32 = STEPS_PER_BEAT * BEATS_PER_BAR;
+/// At full swing every second step starts this far into its slot
+const MAX_SWING_DELAY: f64 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransportState {
+    Playing,
+    Paused,
+    Stopped,
+}
+
+/// Step the sequencers should be playing, with swing applied
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StepPosition {
+    pub step: u64,
+    /// 0.0..1.0 through the current step
+    pub phase: f32,
+}
+
+/// Position in bars and beats for visuals, without swing
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MusicalPosition {
+    pub bar: u64,
+    pub beat: u32,
+    pub step: u32,
+    /// 0.0..1.0 through the current beat
+    pub beat_phase: f32,
+}
+
+impl MusicalPosition {
+    pub fn from_steps(steps: f64) -> Self {
+        let steps = steps.max(0.0);
+        let beats = steps / STEPS_PER_BEAT as f64;
+        let whole_steps = steps as u64;
+
+        Self {
+            bar: whole_steps / STEPS_PER_BAR as u64,
+            beat: (beats as u64 % BEATS_PER_BAR as u64) as u32,
+            step: (whole_steps % STEPS_PER_BEAT as u64) as u32,
+            beat_phase: beats.fract() as f32,
+        }
+    }
+}
+
+/// Shared clock of the sequencers, the position is counted in samples from the last tempo change
+/// so tempo changes never move the playhead and rounding errors don't add up over long runs
+pub struct Transport {
+    state: TransportState,
+    bpm: f32,
+    swing: f32,
+    sample_rate: u32,
+    elapsed_samples: u64,
+    anchor_samples: u64,
+    anchor_steps: f64,
+}
+
+impl Transport {
+    pub fn new(bpm: f32, sample_rate: u32) -> Self {
+        Self {
+            state: TransportState::Playing,
+            bpm: bpm.clamp(MIN_BPM, MAX_BPM),
+            swing: 0.0,
+            sample_rate,
+            elapsed_samples: 0,
+            anchor_samples: 0,
+            anchor_steps: 0.0,
+        }
+    }
+
+    pub fn state(&self) -> TransportState {
+        self.state
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.state == TransportState::Playing
+    }
+
+    pub fn play(&mut self) {
+        self.state = TransportState::Playing;
+    }
+
+    pub fn pause(&mut self) {
+        if self.state == TransportState::Playing {
+            self.state = TransportState::Paused;
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.state = TransportState::Stopped;
+        self.elapsed_samples = 0;
+        self.anchor_samples = 0;
+        self.anchor_steps = 0.0;
+    }
+
+    pub fn bpm(&self) -> f32 {
+        self.bpm
+    }
+
+    pub fn set_bpm(&mut self, bpm: f32) {
+        self.anchor_steps = self.steps();
+        self.anchor_samples = self.elapsed_samples;
+        self.bpm = bpm.clamp(MIN_BPM, MAX_BPM);
+    }
+
+    pub fn swing(&self) -> f32 {
+        self.swing
+    }
+
+    /// 0.0 is straight, 1.0 delays every second step by half a step
+    pub fn set_swing(&mut self, swing: f32) {
+        self.swing = swing.clamp(0.0, 1.0);
+    }
+
+    /// Steps played since the transport was last stopped
+    pub fn steps(&self) -> f64 {
+        self.anchor_steps
+            + (self.elapsed_samples - self.anchor_samples) as f64 * self.steps_per_sample()
+    }
+
+    pub fn steps_per_sample(&self) -> f64 {
+        (self.bpm as f64 * STEPS_PER_BEAT as f64) / (60.0 * self.sample_rate as f64)
+    }
+
+    /// Advances one sample while playing
+    pub fn update(&mut self) {
+        if self.state == TransportState::Playing {
+            self.elapsed_samples += 1;
+        }
+    }
+
+    pub fn step_position(&self) -> StepPosition {
+        let steps = self.steps();
+        let pair = (steps / 2.0).floor();
+        let pair_phase = steps - pair * 2.0;
+        let boundary = 1.0 + self.swing as f64 * MAX_SWING_DELAY;
+        let (step, phase) = if pair_phase < boundary {
+            (pair as u64 * 2, pair_phase / boundary)
+        } else {
+            (
+                pair as u64 * 2 + 1,
+                (pair_phase - boundary) / (2.0 - boundary),
+            )
+        };
+
+        StepPosition {
+            step,
+            phase: phase as f32,
+        }
+    }
+
+    pub fn musical_position(&self) -> MusicalPosition {
+        self.musical_position_at(0)
+    }
+
+    /// Musical position `samples_behind` samples ago at the current tempo, used to line visuals
+    /// up with what is audible rather than what was just rendered
+    pub fn musical_position_at(&self, samples_behind: usize) -> MusicalPosition {
+        MusicalPosition::from_steps(self.steps() - samples_behind as f64 * self.steps_per_sample())
+    }
+}
+
+#[cfg(test)]
+mod transport_tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 1000;
+
+    fn run(transport: &mut Transport, samples: usize) {
+        for _ in 0..samples {
+            transport.update();
+        }
+    }
+
+    #[test]
+    fn test_tempo_change_keeps_position() {
+        let mut transport = Transport::new(120.0, SAMPLE_RATE);
+        run(&mut transport, 500);
+        let before = transport.steps();
+        transport.set_bpm(60.0);
+        assert_eq!(transport.steps(), before);
+
+        // 120 bpm is 8 steps per second, 60 bpm is half of that
+        run(&mut transport, 500);
+        assert!((transport.steps() - 6.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_swing_delays_odd_steps() {
+        let mut transport = Transport::new(120.0, SAMPLE_RATE);
+        transport.set_swing(1.0);
+        // 1.2 steps in, straight this would already be the second step
+        run(&mut transport, 150);
+        assert_eq!(transport.step_position().step, 0);
+        run(&mut transport, 50);
+        assert_eq!(transport.step_position().step, 1);
+        run(&mut transport, 75);
+        assert_eq!(transport.step_position().step, 2);
+    }
+
+    #[test]
+    fn test_pause_and_stop() {
+        let mut transport = Transport::new(120.0, SAMPLE_RATE);
+        run(&mut transport, 2010);
+        assert_eq!(transport.musical_position().bar, 1);
+
+        let steps = transport.steps();
+        transport.pause();
+        run(&mut transport, 100);
+        assert_eq!(transport.steps(), steps);
+
+        transport.stop();
+        transport.play();
+        assert_eq!(transport.musical_position().bar, 0);
+    }
+
+    #[test]
+    fn test_passes_u32_max() {
+        let mut transport = Transport::new(120.0, 48000);
+        // 24 steps short of where a 32-bit sample counter used to wrap
+        transport.elapsed_samples = u32::MAX as u64 - 24 * 6000;
+        let step = transport.step_position().step;
+        let mut previous = step;
+        for _ in 0..48 * 6000 {
+            transport.update();
+            let current = transport.step_position().step;
+            assert!(current == previous || current == previous + 1);
+            previous = current;
+        }
+        assert_eq!(previous, step + 48);
+    }
+}
@@ -0,0 +1,291 @@
+//! Note frequencies of the song: the tuning system, the pitch of A4 and a transpose.
+//!
+//! Notes are MIDI numbers. Every key is one degree of the scale counted from `root`, and the
+//! scale is then scaled so that A4 sounds at `reference`. With 12 divisions, `root` makes no
+//! difference and the default tuning is standard pitch.
+
+use std::{fmt, sync::Arc};
+
+pub const DEFAULT_REFERENCE: f32 = 440.0;
+pub const MIN_REFERENCE: f32 = 400.0;
+pub const MAX_REFERENCE: f32 = 480.0;
+pub const DEFAULT_DIVISIONS: u32 = 12;
+pub const MAX_DIVISIONS: u32 = 72;
+/// C4, just intonation and Scala scales start on it unless the song says otherwise
+pub const DEFAULT_ROOT: u8 = 60;
+/// Highest MIDI note
+pub const MAX_ROOT: u8 = 127;
+pub const MAX_TRANSPOSE: i32 = 24;
+/// A4, the note that sounds at the reference frequency
+const REFERENCE_NOTE: u8 = 69;
+/// Five-limit ratios of the degrees above the root, the last one is the octave
+const JUST_RATIOS: [f64; 12] = [
+    16.0 / 15.0,
+    9.0 / 8.0,
+    6.0 / 5.0,
+    5.0 / 4.0,
+    4.0 / 3.0,
+    45.0 / 32.0,
+    3.0 / 2.0,
+    8.0 / 5.0,
+    5.0 / 3.0,
+    9.0 / 5.0,
+    15.0 / 8.0,
+    2.0,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TuningSystem {
+    /// The octave split into equal steps
+    Equal,
+    Just,
+    /// A scale loaded from a Scala file, standard pitch until one is loaded
+    Scala,
+}
+
+impl TuningSystem {
+    pub fn to_u8(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(TuningSystem::Equal),
+            1 => Some(TuningSystem::Just),
+            2 => Some(TuningSystem::Scala),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ScaleError {
+    Io(std::io::Error),
+    MissingNoteCount,
+    InvalidPitch(String),
+    NoteCount { expected: usize, found: usize },
+}
+
+impl fmt::Display for ScaleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScaleError::Io(e) => write!(f, "{e}"),
+            ScaleError::MissingNoteCount => write!(f, "no note count"),
+            ScaleError::InvalidPitch(pitch) => write!(f, "invalid pitch {pitch}"),
+            ScaleError::NoteCount { expected, found } => {
+                write!(f, "expected {expected} notes, found {found}")
+            }
+        }
+    }
+}
+
+/// Scale of a Scala `.scl` file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scale {
+    /// Where the scale was loaded from
+    pub path: String,
+    pub description: String,
+    /// Ratios of the degrees above the root, the last one is the period the scale repeats at
+    ratios: Vec<f64>,
+}
+
+impl Scale {
+    pub fn load(path: &str) -> Result<Self, ScaleError> {
+        let contents = std::fs::read_to_string(path).map_err(ScaleError::Io)?;
+        let mut scale = Self::parse(&contents)?;
+        scale.path = path.to_string();
+        Ok(scale)
+    }
+
+    /// Lines starting with `!` are comments, then come the description, the note count and a
+    /// pitch per note. Pitches with a period are in cents, the rest are ratios like `3/2` or `2`.
+    pub fn parse(contents: &str) -> Result<Self, ScaleError> {
+        let mut lines = contents.lines().filter(|line| !line.starts_with('!'));
+        let description = lines.next().unwrap_or_default().trim().to_string();
+        let count: usize = lines
+            .next()
+            .and_then(|line| line.split_whitespace().next())
+            .and_then(|count| count.parse().ok())
+            .ok_or(ScaleError::MissingNoteCount)?;
+        let ratios = lines
+            .take(count)
+            .map(|line| {
+                let pitch = line.split_whitespace().next().unwrap_or_default();
+                parse_pitch(pitch).ok_or_else(|| ScaleError::InvalidPitch(pitch.to_string()))
+            })
+            .collect::<Result<Vec<f64>, ScaleError>>()?;
+        if count == 0 || ratios.len() < count {
+            return Err(ScaleError::NoteCount {
+                expected: count,
+                found: ratios.len(),
+            });
+        }
+        Ok(Self {
+            path: String::new(),
+            description,
+            ratios,
+        })
+    }
+
+    pub fn note_count(&self) -> usize {
+        self.ratios.len()
+    }
+}
+
+fn parse_pitch(pitch: &str) -> Option<f64> {
+    let ratio = if pitch.contains('.') {
+        2.0_f64.powf(pitch.parse::<f64>().ok()? / 1200.0)
+    } else if let Some((numerator, denominator)) = pitch.split_once('/') {
+        numerator.parse::<f64>().ok()? / denominator.parse::<f64>().ok()?
+    } else {
+        pitch.parse::<f64>().ok()?
+    };
+    (ratio.is_finite() && ratio > 0.0).then_some(ratio)
+}
+
+/// Ratio of `degree` steps above the root, `ratios` repeat at their last one
+fn periodic_ratio(ratios: &[f64], degree: i32) -> f64 {
+    let length = ratios.len() as i32;
+    let period = ratios[ratios.len() - 1];
+    let step = degree.rem_euclid(length);
+    let ratio = if step == 0 {
+        1.0
+    } else {
+        ratios[step as usize - 1]
+    };
+    period.powi(degree.div_euclid(length)) * ratio
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tuning {
+    pub system: TuningSystem,
+    /// Frequency of A4 in Hz
+    pub reference: f32,
+    /// Steps per octave of equal temperament
+    pub divisions: u32,
+    /// Note the just and Scala scales start on
+    pub root: u8,
+    /// Keys every note is moved by, steps of the scale rather than semitones
+    pub transpose: i32,
+    pub scale: Option<Arc<Scale>>,
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        Self {
+            system: TuningSystem::Equal,
+            reference: DEFAULT_REFERENCE,
+            divisions: DEFAULT_DIVISIONS,
+            root: DEFAULT_ROOT,
+            transpose: 0,
+            scale: None,
+        }
+    }
+}
+
+impl Tuning {
+    pub fn frequency(&self, midi_note: u8) -> f32 {
+        let root = self.root as i32;
+        let note = midi_note as i32 + self.transpose;
+        let ratio = self.ratio(note - root) / self.ratio(REFERENCE_NOTE as i32 - root);
+        (self.reference as f64 * ratio) as f32
+    }
+
+    fn ratio(&self, degree: i32) -> f64 {
+        let equal = |divisions: u32| 2.0_f64.powf(degree as f64 / divisions.max(1) as f64);
+        match (self.system, &self.scale) {
+            (TuningSystem::Equal, _) => equal(self.divisions),
+            (TuningSystem::Just, _) => periodic_ratio(&JUST_RATIOS, degree),
+            (TuningSystem::Scala, Some(scale)) => periodic_ratio(&scale.ratios, degree),
+            (TuningSystem::Scala, None) => equal(DEFAULT_DIVISIONS),
+        }
+    }
+}
+
+/// Standard pitch, what samples are taken to be recorded in
+pub fn standard_frequency(midi_note: u8) -> f32 {
+    DEFAULT_REFERENCE * 2.0_f32.powf((midi_note as f32 - REFERENCE_NOTE as f32) / 12.0)
+}
+
+#[cfg(test)]
+mod tuning_tests {
+    use super::*;
+
+    const C4: u8 = 60;
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-3, "{a} != {b}");
+    }
+
+    #[test]
+    fn test_default_is_standard_pitch() {
+        let tuning = Tuning::default();
+        assert_close(tuning.frequency(REFERENCE_NOTE), 440.0);
+        assert_close(tuning.frequency(C4), 261.6256);
+        assert_close(tuning.frequency(24), standard_frequency(24));
+
+        let tuning = Tuning {
+            reference: 432.0,
+            transpose: 12,
+            ..Tuning::default()
+        };
+        assert_close(tuning.frequency(REFERENCE_NOTE - 12), 432.0);
+    }
+
+    #[test]
+    fn test_equal_divisions() {
+        let tuning = Tuning {
+            divisions: 24,
+            ..Tuning::default()
+        };
+        // Quarter tones, 24 keys to the octave
+        assert_close(tuning.frequency(REFERENCE_NOTE + 24), 880.0);
+        assert_close(
+            tuning.frequency(REFERENCE_NOTE + 2),
+            440.0 * 2.0_f32.powf(1.0 / 12.0),
+        );
+    }
+
+    #[test]
+    fn test_just_intonation() {
+        let tuning = Tuning {
+            system: TuningSystem::Just,
+            ..Tuning::default()
+        };
+        assert_close(tuning.frequency(REFERENCE_NOTE), 440.0);
+        // A is the major sixth above C
+        assert_close(tuning.frequency(C4), 264.0);
+        assert_close(tuning.frequency(C4 + 7), 396.0);
+        assert_close(tuning.frequency(C4 - 7), 176.0);
+    }
+
+    #[test]
+    fn test_scala_scale() {
+        let contents = "! pelog.scl\n!\nnot quite pelog\n 4\n!\n 150.0 cents\n 3/2\n 700.\n2\n";
+        let scale = Scale::parse(contents).unwrap();
+        assert_eq!(scale.description, "not quite pelog");
+        assert_eq!(scale.note_count(), 4);
+
+        let tuning = Tuning {
+            system: TuningSystem::Scala,
+            root: REFERENCE_NOTE,
+            scale: Some(Arc::new(scale)),
+            ..Tuning::default()
+        };
+        assert_close(tuning.frequency(REFERENCE_NOTE + 2), 660.0);
+        assert_close(tuning.frequency(REFERENCE_NOTE + 4), 880.0);
+        assert_close(tuning.frequency(REFERENCE_NOTE - 2), 330.0);
+
+        assert!(matches!(
+            Scale::parse("short\n3\n3/2\n2\n"),
+            Err(ScaleError::NoteCount {
+                expected: 3,
+                found: 2
+            })
+        ));
+        assert!(matches!(
+            Scale::parse("bad\n1\n0/1\n"),
+            Err(ScaleError::InvalidPitch(_))
+        ));
+    }
+}
@@ -0,0 +1,324 @@
+use super::{
+    envelope::Envelope, filter::Filter, mixer, modulated_oscillator::ModulatedOscillator,
+    modulation::ModMatrix,
+};
+
+pub const MAX_POLYPHONY: usize = 8;
+/// Enough for releases to ring out under the next few steps
+pub const DEFAULT_POLYPHONY: usize = 4;
+pub const MAX_UNISON: usize = 8;
+/// Cents the outermost unison copies are detuned by at most
+pub const MAX_DETUNE: f32 = 50.0;
+pub const DEFAULT_DETUNE: f32 = 10.0;
+pub const DEFAULT_SPREAD: f32 = 0.5;
+/// Time constant of the portamento between slid steps
+const SLIDE_TIME: f32 = 0.06;
+/// Time constant of velocity changes, short enough to follow steps but long enough not to click
+const VELOCITY_TIME: f32 = 0.005;
+
+/// Sound settings of a track, every voice follows them
+pub struct VoiceSettings<'a> {
+    pub oscillator: &'a ModulatedOscillator,
+    pub envelope: &'a Envelope,
+    pub filter: &'a Filter,
+    pub filter_envelope: &'a Envelope,
+    pub modulation: &'a ModMatrix,
+    pub wave_volume: f32,
+    /// Noise sample of the track and its level before modulation
+    pub noise: f32,
+    pub noise_volume: f32,
+    pub lfo: f32,
+}
+
+/// One note with its own oscillators, envelopes and filters so its release can ring out while
+/// other notes play
+struct Voice {
+    /// One per unison copy
+    oscillators: Vec<ModulatedOscillator>,
+    envelope: Envelope,
+    filter_envelope: Envelope,
+    filters: [Filter; 2],
+    freq: f32,
+    target_freq: f32,
+    is_gliding: bool,
+    velocity: f32,
+    target_velocity: f32,
+    /// When the voice was started, the oldest one is stolen first
+    started: u64,
+}
+
+impl Voice {
+    fn new(sample_rate: u32) -> Self {
+        Self {
+            oscillators: (0..MAX_UNISON)
+                .map(|_| ModulatedOscillator::new(sample_rate))
+                .collect(),
+            envelope: Envelope::new(0.0, 0.0, 0.0, 0.0),
+            filter_envelope: Envelope::new(0.0, 0.0, 0.0, 0.0),
+            filters: [Filter::new(sample_rate), Filter::new(sample_rate)],
+            freq: 0.0,
+            target_freq: 0.0,
+            is_gliding: false,
+            velocity: 0.0,
+            target_velocity: 0.0,
+            started: 0,
+        }
+    }
+}
+
+/// Spreads the notes of a track over a fixed number of voices, each one playing `unison`
+/// detuned copies of the oscillator panned across the stereo field. Every voice and copy is
+/// made up front so changing the settings never allocates while rendering.
+pub struct VoiceAllocator {
+    pub polyphony: usize,
+    pub unison: usize,
+    /// Cents between the outermost unison copies and the note
+    pub detune: f32,
+    /// 0.0 keeps every copy in the center, 1.0 spreads them from left to right
+    pub spread: f32,
+    voices: Vec<Voice>,
+    /// Voices new notes may take on top of `polyphony`, for chords
+    min_voices: usize,
+    /// Voice of the last note, slides go to it
+    current: usize,
+    started: u64,
+    delta_time: f32,
+    slide_coefficient: f32,
+    velocity_coefficient: f32,
+}
+
+impl VoiceAllocator {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            polyphony: DEFAULT_POLYPHONY,
+            unison: 1,
+            detune: DEFAULT_DETUNE,
+            spread: DEFAULT_SPREAD,
+            voices: (0..MAX_POLYPHONY)
+                .map(|_| Voice::new(sample_rate))
+                .collect(),
+            min_voices: 0,
+            current: 0,
+            started: 0,
+            delta_time: 1.0 / sample_rate as f32,
+            slide_coefficient: one_pole_coefficient(SLIDE_TIME, sample_rate),
+            velocity_coefficient: one_pole_coefficient(VELOCITY_TIME, sample_rate),
+        }
+    }
+
+    /// Lets the next notes use at least `voices` voices whatever the polyphony, so a chord doesn't
+    /// steal its own notes. Set it back to 0 once they are started.
+    pub fn set_min_voices(&mut self, voices: usize) {
+        self.min_voices = voices;
+    }
+
+    /// Starts a note on an idle voice, or on the oldest one when they are all sounding. A
+    /// `glide` moves the current voice to the new note without retriggering it.
+    pub fn note_on(&mut self, frequency: f32, velocity: f32, glide: bool) {
+        self.fit_voices();
+        if !glide {
+            let voices = self.polyphony.max(self.min_voices).min(MAX_POLYPHONY);
+            self.current = self.voices[..voices]
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, voice)| (!voice.envelope.is_idle(), voice.started))
+                .map_or(0, |(i, _)| i);
+        }
+        self.started += 1;
+
+        let voice = &mut self.voices[self.current];
+        voice.target_freq = frequency;
+        voice.target_velocity = velocity.clamp(0.0, 1.0);
+        voice.is_gliding = glide;
+        if !glide {
+            voice.freq = frequency;
+            if voice.envelope.is_idle() {
+                // A stolen voice fades to the new velocity instead of jumping to it
+                voice.velocity = voice.target_velocity;
+            }
+            voice.started = self.started;
+            voice.envelope.gate_on();
+            voice.filter_envelope.gate_on();
+        }
+    }
+
+    /// Releases every held note, the ones already releasing carry on
+    pub fn note_off(&mut self) {
+        for voice in &mut self.voices {
+            voice.is_gliding = false;
+            voice.envelope.gate_off();
+            voice.filter_envelope.gate_off();
+        }
+    }
+
+    /// Frequency of the last note
+    pub fn frequency(&self) -> f32 {
+        self.voices
+            .get(self.current)
+            .map_or(0.0, |voice| voice.freq)
+    }
+
+    pub fn active_voices(&self) -> usize {
+        self.voices.iter().filter(|v| !v.envelope.is_idle()).count()
+    }
+
+    /// Renders every sounding voice
+    pub fn render(&mut self, settings: &VoiceSettings) -> [f32; 2] {
+        self.fit_voices();
+        let unison = self.unison;
+        // Copies are kept at the same overall level
+        let unison_gain = 1.0 / (unison as f32).sqrt();
+        let mut frame = [0.0; 2];
+        for voice in &mut self.voices {
+            copy_envelope(settings.envelope, &mut voice.envelope);
+            copy_envelope(settings.filter_envelope, &mut voice.filter_envelope);
+            if voice.envelope.is_idle() {
+                continue;
+            }
+
+            if voice.is_gliding {
+                voice.freq += (voice.target_freq - voice.freq) * self.slide_coefficient;
+            } else {
+                voice.freq = voice.target_freq;
+            }
+            voice.velocity += (voice.target_velocity - voice.velocity) * self.velocity_coefficient;
+
+            let envelope = voice.envelope.update(self.delta_time);
+            let filter_envelope = voice.filter_envelope.update(self.delta_time);
+            let modulation = settings
+                .modulation
+                .evaluate(settings.lfo, envelope, filter_envelope);
+
+            let mut voice_frame = [0.0; 2];
+            for (i, oscillator) in voice.oscillators.iter_mut().take(unison).enumerate() {
+                // -1.0 for the first copy to 1.0 for the last
+                let position = if unison > 1 {
+                    i as f32 * 2.0 / (unison - 1) as f32 - 1.0
+                } else {
+                    0.0
+                };
+                oscillator.copy_settings(settings.oscillator);
+                oscillator.set_frequency(voice.freq);
+                let cents = position * self.detune.clamp(0.0, MAX_DETUNE);
+                let value = oscillator.run(modulation.pitch + cents / 100.0)
+                    * settings.wave_volume
+                    * unison_gain;
+                let [left, right] = mixer::position(value, position * self.spread + modulation.pan);
+                voice_frame[0] += left;
+                voice_frame[1] += right;
+            }
+            let noise =
+                settings.noise * (settings.noise_volume + modulation.noise_mix).clamp(0.0, 1.0);
+            let [noise_left, noise_right] = mixer::position(noise, modulation.pan);
+            voice_frame[0] += noise_left;
+            voice_frame[1] += noise_right;
+
+            let gain = envelope * voice.velocity * (1.0 + modulation.volume).max(0.0);
+            for (channel, filter) in voice.filters.iter_mut().enumerate() {
+                copy_filter(settings.filter, filter);
+                let value =
+                    filter.process(voice_frame[channel], filter_envelope, modulation.cutoff);
+                frame[channel] += value * gain;
+            }
+        }
+        frame
+    }
+
+    /// Keeps the settings in range, voices beyond the polyphony only play out their releases
+    fn fit_voices(&mut self) {
+        self.polyphony = self.polyphony.clamp(1, MAX_POLYPHONY);
+        self.unison = self.unison.clamp(1, MAX_UNISON);
+    }
+}
+
+fn copy_envelope(from: &Envelope, to: &mut Envelope) {
+    to.attack = from.attack;
+    to.decay = from.decay;
+    to.sustain_level = from.sustain_level;
+    to.release = from.release;
+    to.peak_level = from.peak_level;
+}
+
+fn copy_filter(from: &Filter, to: &mut Filter) {
+    to.filter_type = from.filter_type;
+    to.cutoff = from.cutoff;
+    to.resonance = from.resonance;
+    to.envelope_amount = from.envelope_amount;
+}
+
+/// Per-sample smoothing factor of a one-pole filter with the given time constant
+fn one_pole_coefficient(time: f32, sample_rate: u32) -> f32 {
+    1.0 - (-1.0 / (time * sample_rate as f32)).exp()
+}
+
+#[cfg(test)]
+mod voice_tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 1000;
+
+    struct Track {
+        oscillator: ModulatedOscillator,
+        envelope: Envelope,
+        filter: Filter,
+        filter_envelope: Envelope,
+        modulation: ModMatrix,
+    }
+
+    impl Track {
+        fn new() -> Self {
+            Self {
+                oscillator: ModulatedOscillator::new(SAMPLE_RATE),
+                envelope: Envelope::new(0.0, 0.0, 1.0, 0.5),
+                filter: Filter::new(SAMPLE_RATE),
+                filter_envelope: Envelope::new(0.0, 0.0, 0.0, 0.0),
+                modulation: ModMatrix { routes: Vec::new() },
+            }
+        }
+
+        fn settings(&self) -> VoiceSettings<'_> {
+            VoiceSettings {
+                oscillator: &self.oscillator,
+                envelope: &self.envelope,
+                filter: &self.filter,
+                filter_envelope: &self.filter_envelope,
+                modulation: &self.modulation,
+                wave_volume: 1.0,
+                noise: 0.0,
+                noise_volume: 0.0,
+                lfo: 0.0,
+            }
+        }
+    }
+
+    #[test]
+    fn test_releases_ring_out() {
+        let track = Track::new();
+        let mut voices = VoiceAllocator::new(SAMPLE_RATE);
+        voices.polyphony = 2;
+        voices.note_on(110.0, 1.0, false);
+        voices.render(&track.settings());
+        voices.note_off();
+        voices.note_on(220.0, 1.0, false);
+        voices.render(&track.settings());
+        assert_eq!(voices.active_voices(), 2);
+
+        // A third note takes the voice that was released first
+        voices.note_off();
+        voices.note_on(330.0, 1.0, false);
+        assert_eq!(voices.active_voices(), 2);
+        assert_eq!(voices.frequency(), 330.0);
+    }
+
+    #[test]
+    fn test_glide_keeps_voice() {
+        let track = Track::new();
+        let mut voices = VoiceAllocator::new(SAMPLE_RATE);
+        voices.note_on(110.0, 1.0, false);
+        voices.render(&track.settings());
+        voices.note_on(220.0, 1.0, true);
+        voices.render(&track.settings());
+        assert_eq!(voices.active_voices(), 1);
+        assert!(voices.frequency() > 110.0 && voices.frequency() < 220.0);
+    }
+}
@@ -0,0 +1,224 @@
+use super::{oscillator_type::OscillatorType, sampler::read_mono};
+use kopek::oscillator::WaveType;
+use rustfft::{num_complex::Complex, FftPlanner};
+use std::sync::{Arc, OnceLock};
+
+/// Samples in one cycle, the usual size of wavetable files
+pub const FRAME_LENGTH: usize = 2048;
+pub const MAX_FRAMES: usize = 64;
+/// Copies of every frame with half the harmonics of the one before, 1024 harmonics down to one
+const LEVEL_COUNT: usize = 11;
+
+/// Single-cycle frames, each stored at every level of band-limiting
+pub struct Wavetable {
+    /// Where the frames were loaded from, built-in tables have none
+    pub path: Option<String>,
+    /// `LEVEL_COUNT` levels per frame, `FRAME_LENGTH` samples each
+    levels: Vec<Vec<f32>>,
+    frame_count: usize,
+}
+
+impl Wavetable {
+    /// A file of whole `FRAME_LENGTH` frames is split into them, anything else is taken to be a
+    /// single cycle
+    pub fn load(path: &str) -> Result<Self, hound::Error> {
+        let (data, _) = read_mono(path)?;
+        let mut table = Self::from_cycles(&data);
+        table.path = Some(path.to_string());
+        Ok(table)
+    }
+
+    pub fn from_cycles(data: &[f32]) -> Self {
+        let frames: Vec<Vec<f32>> = if data.len() >= FRAME_LENGTH && data.len() % FRAME_LENGTH == 0
+        {
+            data.chunks(FRAME_LENGTH)
+                .take(MAX_FRAMES)
+                .map(<[f32]>::to_vec)
+                .collect()
+        } else {
+            vec![resample_cycle(data)]
+        };
+
+        let mut planner = FftPlanner::new();
+        let forward = planner.plan_fft_forward(FRAME_LENGTH);
+        let inverse = planner.plan_fft_inverse(FRAME_LENGTH);
+        let mut levels = Vec::with_capacity(frames.len() * LEVEL_COUNT);
+        for frame in &frames {
+            let mut spectrum: Vec<Complex<f32>> = frame
+                .iter()
+                .map(|&value| Complex::new(value, 0.0))
+                .collect();
+            forward.process(&mut spectrum);
+            for level in 0..LEVEL_COUNT {
+                let harmonics = (FRAME_LENGTH / 2) >> level;
+                let mut band_limited: Vec<Complex<f32>> = (0..FRAME_LENGTH)
+                    .map(|bin| {
+                        // DC is dropped, harmonics above the level's limit and their mirror images
+                        // are cleared
+                        let harmonic = bin.min(FRAME_LENGTH - bin);
+                        if harmonic == 0 || harmonic > harmonics {
+                            Complex::new(0.0, 0.0)
+                        } else {
+                            spectrum[bin]
+                        }
+                    })
+                    .collect();
+                inverse.process(&mut band_limited);
+                levels.push(
+                    band_limited
+                        .iter()
+                        .map(|value| value.re / FRAME_LENGTH as f32)
+                        .collect(),
+                );
+            }
+        }
+
+        Self {
+            path: None,
+            levels,
+            frame_count: frames.len(),
+        }
+    }
+
+    /// Sine, triangle, sawtooth and square to morph through when no file is loaded, built once
+    /// and shared by every oscillator
+    pub fn basic() -> Arc<Self> {
+        static BASIC: OnceLock<Arc<Wavetable>> = OnceLock::new();
+        Arc::clone(BASIC.get_or_init(|| Arc::new(Self::build_basic())))
+    }
+
+    fn build_basic() -> Self {
+        let shapes: [fn(f32) -> f32; 4] = [
+            |t| (std::f32::consts::TAU * t).sin(),
+            |t| 1.0 - 4.0 * (t - 0.5).abs(),
+            |t| 2.0 * t - 1.0,
+            |t| if t < 0.5 { 1.0 } else { -1.0 },
+        ];
+        let data: Vec<f32> = shapes
+            .iter()
+            .flat_map(|shape| (0..FRAME_LENGTH).map(|i| shape(i as f32 / FRAME_LENGTH as f32)))
+            .collect();
+        Self::from_cycles(&data)
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frame_count
+    }
+
+    fn sample(&self, frame: usize, level: usize, phase: f32) -> f32 {
+        let table = &self.levels[frame * LEVEL_COUNT + level];
+        let position = phase * FRAME_LENGTH as f32;
+        let index = position as usize % FRAME_LENGTH;
+        let next = (index + 1) % FRAME_LENGTH;
+        let fraction = position.fract();
+        table[index] + (table[next] - table[index]) * fraction
+    }
+}
+
+/// Stretches a cycle of any length to `FRAME_LENGTH`
+fn resample_cycle(data: &[f32]) -> Vec<f32> {
+    if data.is_empty() {
+        return vec![0.0; FRAME_LENGTH];
+    }
+    (0..FRAME_LENGTH)
+        .map(|i| {
+            let position = i as f32 * data.len() as f32 / FRAME_LENGTH as f32;
+            let index = position as usize;
+            let next = (index + 1) % data.len();
+            data[index] + (data[next] - data[index]) * position.fract()
+        })
+        .collect()
+}
+
+/// Plays a wavetable, `position` morphs through its frames. The table is picked by pitch so no
+/// harmonic goes past Nyquist.
+pub struct WavetableOscillator {
+    pub table: Arc<Wavetable>,
+    /// 0.0..=1.0 from the first frame to the last
+    pub position: f32,
+    frequency: f32,
+    sample_rate: f32,
+    phase: f32,
+}
+
+impl WavetableOscillator {
+    /// Level with as many harmonics as fit below Nyquist at the current frequency
+    fn level(&self) -> usize {
+        let max_harmonics = self.sample_rate * 0.5 / self.frequency.max(1.0);
+        let full = (FRAME_LENGTH / 2) as f32;
+        if max_harmonics >= full {
+            return 0;
+        }
+        ((full / max_harmonics).log2().ceil() as usize).min(LEVEL_COUNT - 1)
+    }
+}
+
+impl OscillatorType for WavetableOscillator {
+    fn new(sample_rate: f32) -> Self {
+        Self {
+            table: Wavetable::basic(),
+            position: 0.0,
+            frequency: 440.0,
+            sample_rate,
+            phase: 0.0,
+        }
+    }
+
+    fn frequency(&self) -> f32 {
+        self.frequency
+    }
+
+    fn set_frequency(&mut self, frequency: f32) {
+        self.frequency = frequency;
+    }
+
+    /// The shape comes from the table
+    fn wave_type(&self) -> WaveType {
+        WaveType::Sine
+    }
+
+    fn set_wave_type(&mut self, _wave_type: WaveType) {}
+
+    fn run(&mut self) -> f32 {
+        let level = self.level();
+        let frame_position = self.position.clamp(0.0, 1.0) * (self.table.frame_count() - 1) as f32;
+        let frame = frame_position as usize;
+        let next_frame = (frame + 1).min(self.table.frame_count() - 1);
+        let a = self.table.sample(frame, level, self.phase);
+        let b = self.table.sample(next_frame, level, self.phase);
+        let value = a + (b - a) * frame_position.fract();
+
+        self.phase = (self.phase + self.frequency / self.sample_rate).fract();
+        value
+    }
+}
+
+#[cfg(test)]
+mod wavetable_oscillator_tests {
+    use super::*;
+
+    #[test]
+    fn test_frames_and_levels() {
+        let table = Wavetable::basic();
+        assert_eq!(table.frame_count(), 4);
+        // The top level of the sawtooth is its fundamental alone
+        let saw = &table.levels[2 * LEVEL_COUNT + LEVEL_COUNT - 1];
+        let peak = saw
+            .iter()
+            .fold(0.0_f32, |peak, value| peak.max(value.abs()));
+        let quarter = saw[FRAME_LENGTH / 4];
+        assert!((quarter.abs() - peak).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_high_notes_use_fewer_harmonics() {
+        let mut oscillator = WavetableOscillator::new(44100.0);
+        oscillator.set_frequency(20.0);
+        assert_eq!(oscillator.level(), 0);
+        oscillator.set_frequency(440.0);
+        // 50 harmonics fit below Nyquist, the level with 32 is used
+        assert_eq!(oscillator.level(), 5);
+        oscillator.set_frequency(15000.0);
+        assert_eq!(oscillator.level(), LEVEL_COUNT - 1);
+    }
+}
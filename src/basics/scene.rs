@@ -5,6 +5,7 @@ use super::{
     uniforms::{ColorUniform, EqualizerUniform, LightUniform, ObjectUniform},
 };
 use crate::{
+    audio::analyser::Analysis,
     color_utils::{self, ColorPalette, ToVec4},
     material::{
         diffuse_color_material::{DiffuseColorMaterial, DiffuseColorUniforms},
@@ -237,7 +238,7 @@ impl Scene {
         queue: &Queue,
         delta_time: f32,
         signal: f32,
-        on_beat: bool,
+        analysis: &Analysis,
         wave: Arc<Vec<f32>>,
         color_palette: &ColorPalette<f32, 4>,
     ) {
@@ -261,12 +262,21 @@ impl Scene {
                         normal2: primitive.normal_matrix().y_axis.extend(0.0).to_array(),
                         normal3: primitive.normal_matrix().z_axis.extend(0.0).to_array(),
                     };
+                    let mut track_levels = [0.0; 4];
+                    for (level, track_level) in track_levels.iter_mut().zip(&analysis.track_levels)
+                    {
+                        *level = *track_level;
+                    }
                     let equalizer = EqualizerUniform {
                         color1: color_palette.palette[0].to_vec4(1.0),
                         color2: color_palette.palette[1].to_vec4(1.0),
                         color3: color_palette.palette[2].to_vec4(1.0),
                         signal: signal * 5.0,
-                        _padding: [0.0, 0.0, 0.0],
+                        onset: analysis.onset_envelope,
+                        beat: analysis.beat_envelope,
+                        on_beat: if analysis.on_beat { 1.0 } else { 0.0 },
+                        bands: bytemuck::cast(analysis.bands),
+                        track_levels,
                     };
                     let light = LightUniform {
                         position: self.lights[0].transform.position.extend(0.0).to_array(),
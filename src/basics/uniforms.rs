@@ -48,7 +48,14 @@ pub struct EqualizerUniform {
     pub color2: [f32; 4],
     pub color3: [f32; 4],
     pub signal: f32,
-    pub _padding: [f32; 3],
+    pub onset: f32,
+    pub beat: f32,
+    /// 1.0 on the transport's beat, 0.0 otherwise
+    pub on_beat: f32,
+    /// Analyser bands packed four to a vec4, WGSL pads array elements to 16 bytes
+    pub bands: [[f32; 4]; 4],
+    /// Levels of the first four tracks, the rest are left out
+    pub track_levels: [f32; 4],
 }
 
 impl UniformTrait for EqualizerUniform {
@@ -0,0 +1,105 @@
+use crate::audio::{
+    audio_model::AudioSettings,
+    offline_renderer::{BitDepth, ExportSettings},
+};
+
+pub const USAGE: &str = "usage: fo-rma [--null-audio] [--latency <ms>] [--buffer <frames>] \
+    [--export <file.wav> [--bars <n>] [--sample-rate <hz>] [--format i16|f32]]";
+
+pub struct Args {
+    pub export_path: Option<String>,
+    pub export_settings: ExportSettings,
+    pub audio_settings: AudioSettings,
+}
+
+pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args {
+        export_path: None,
+        export_settings: ExportSettings::default(),
+        audio_settings: AudioSettings::default(),
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--null-audio" => parsed.audio_settings.null_audio = true,
+            "--latency" => parsed.audio_settings.latency_ms = number(&arg, args.next())?,
+            "--buffer" => {
+                parsed.audio_settings.buffer_frames = Some(number(&arg, args.next())?);
+            }
+            "--export" => parsed.export_path = Some(value(&arg, args.next())?),
+            "--bars" => parsed.export_settings.bars = number(&arg, args.next())?,
+            "--sample-rate" => parsed.export_settings.sample_rate = number(&arg, args.next())?,
+            "--format" => {
+                parsed.export_settings.bit_depth = match value(&arg, args.next())?.as_str() {
+                    "i16" => BitDepth::Int16,
+                    "f32" => BitDepth::Float32,
+                    other => return Err(format!("unknown format: {other}")),
+                }
+            }
+            other => return Err(format!("unknown argument: {other}")),
+        }
+    }
+
+    Ok(parsed)
+}
+
+fn value(arg: &str, value: Option<String>) -> Result<String, String> {
+    value.ok_or_else(|| format!("missing value for {arg}"))
+}
+
+fn number(arg: &str, value: Option<String>) -> Result<u32, String> {
+    let value = self::value(arg, value)?;
+    match value.parse::<u32>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("invalid value for {arg}: {value}")),
+    }
+}
+
+#[cfg(test)]
+mod cli_tests {
+    use super::*;
+
+    fn parse_str(args: &str) -> Result<Args, String> {
+        parse(args.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn test_parse() {
+        let args = parse_str("").unwrap();
+        assert!(args.export_path.is_none());
+        assert!(!args.audio_settings.null_audio);
+
+        let args = parse_str(
+            "--null-audio --latency 30 --buffer 256 --export out.wav --bars 8 \
+             --sample-rate 48000 --format f32",
+        )
+        .unwrap();
+        assert!(args.audio_settings.null_audio);
+        assert_eq!(args.audio_settings.latency_ms, 30);
+        assert_eq!(args.audio_settings.buffer_frames, Some(256));
+        assert_eq!(args.export_path.as_deref(), Some("out.wav"));
+        assert_eq!(args.export_settings.bars, 8);
+        assert_eq!(args.export_settings.sample_rate, 48000);
+        assert_eq!(args.export_settings.bit_depth, BitDepth::Float32);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            parse_str("--verbose").err().unwrap(),
+            "unknown argument: --verbose"
+        );
+        assert_eq!(
+            parse_str("--bars").err().unwrap(),
+            "missing value for --bars"
+        );
+        assert_eq!(
+            parse_str("--bars 0").err().unwrap(),
+            "invalid value for --bars: 0"
+        );
+        assert_eq!(
+            parse_str("--format i24").err().unwrap(),
+            "unknown format: i24"
+        );
+    }
+}
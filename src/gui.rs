@@ -1,5 +1,5 @@
 use crate::app::UiEvent;
-use crate::audio::sequencer::Sequencer;
+use crate::audio::audio_model::AudioModel;
 use crate::shader_utils::Effect;
 use egui::epaint::Shadow;
 use egui::ViewportId;
@@ -14,12 +14,19 @@ use wgpu::{Device, Queue};
 use winit::dpi::PhysicalSize;
 use winit::window::Window;
 
+pub mod gui_arrangement;
+pub mod gui_effects;
 pub mod gui_envelope;
+pub mod gui_mixer;
 pub mod gui_oscillator;
 pub mod gui_post_process;
 pub mod gui_sequencer;
+pub mod gui_tuning;
 pub mod top_bar;
 
+use gui_oscillator::FilePaths;
+use gui_sequencer::PatternTools;
+
 pub struct Gui {
     ctx: Context,
     state: State,
@@ -33,9 +40,16 @@ pub struct Gui {
 pub struct Settings {
     pub show_sequencers: bool,
     pub show_oscillator_inspector: bool,
+    pub show_effects: bool,
+    pub show_mixer: bool,
+    pub show_arrangement: bool,
     pub show_vfx: bool,
+    pub show_tuning: bool,
     pub selected: usize,
+    pub selected_pattern: usize,
     pub selected_color: usize,
+    pub pattern_tools: PatternTools,
+    pub file_paths: FilePaths,
 }
 
 impl Gui {
@@ -74,9 +88,16 @@ impl Gui {
             settings: Settings {
                 show_sequencers: false,
                 show_oscillator_inspector: false,
+                show_effects: false,
+                show_mixer: false,
+                show_arrangement: false,
                 show_vfx: true,
+                show_tuning: false,
                 selected: 0,
+                selected_pattern: 0,
                 selected_color: 0,
+                pattern_tools: PatternTools::default(),
+                file_paths: FilePaths::default(),
             },
         }
     }
@@ -100,19 +121,88 @@ impl Gui {
         render_target: &wgpu::TextureView,
         device: &Device,
         queue: &Queue,
-        sequencers: &mut Vec<Sequencer>,
+        audio_model: &mut AudioModel,
         fps: f32,
         ui_events: &mut Vec<UiEvent>,
         effect_to_active: &mut FastIndexMap<Effect, bool>,
     ) {
         let raw_input = self.state.take_egui_input(window);
+        // Widgets only change values on clicks, drags, scrolls and typing, hovering the interface
+        // doesn't make the audio thread's copy of the song out of date
+        let is_edited = self.ctx.input(|input| input.pointer.any_down())
+            || raw_input
+                .events
+                .iter()
+                .any(|event| !matches!(event, egui::Event::PointerMoved(_)));
+        if is_edited {
+            audio_model.mark_dirty();
+        }
+        let input_status = audio_model.input_status();
+        let output_status = audio_model.output_status();
+        let (audio_engine, snapshot) = audio_model.engine_and_snapshot();
+        // Tracks can be removed while one of them is selected
+        self.settings.selected = self
+            .settings
+            .selected
+            .min(audio_engine.sequencers.len().saturating_sub(1));
         let output = self.ctx.run(raw_input, |egui_ctx| {
-            top_bar::draw(egui_ctx, &mut self.settings, ui_events, fps);
-            if self.settings.show_oscillator_inspector {
+            top_bar::draw(
+                egui_ctx,
+                &mut self.settings,
+                ui_events,
+                fps,
+                &output_status,
+                &mut audio_engine.transport,
+                snapshot,
+            );
+            if self.settings.show_oscillator_inspector && !audio_engine.sequencers.is_empty() {
                 gui_oscillator::draw(
                     egui_ctx,
-                    &mut sequencers[self.settings.selected],
+                    &mut audio_engine.sequencers[self.settings.selected],
+                    snapshot.track(self.settings.selected),
+                    self.settings.selected,
+                    &mut self.settings.file_paths,
                     &mut self.settings.show_oscillator_inspector,
+                    ui_events,
+                );
+            }
+            if self.settings.show_effects {
+                gui_effects::draw(
+                    egui_ctx,
+                    audio_engine
+                        .sequencers
+                        .get_mut(self.settings.selected)
+                        .map(|s| &mut s.effects),
+                    self.settings.selected,
+                    &mut audio_engine.master_effects,
+                    &mut self.settings.show_effects,
+                );
+            }
+            if self.settings.show_mixer {
+                gui_mixer::draw(
+                    egui_ctx,
+                    audio_engine,
+                    snapshot,
+                    input_status,
+                    &mut self.settings.show_mixer,
+                    ui_events,
+                );
+            }
+            if self.settings.show_arrangement {
+                gui_arrangement::draw(
+                    egui_ctx,
+                    audio_engine,
+                    snapshot,
+                    &mut self.settings.show_arrangement,
+                );
+            }
+            if self.settings.show_tuning {
+                gui_tuning::draw(
+                    egui_ctx,
+                    &mut audio_engine.tuning,
+                    &mut self.settings.file_paths.scale,
+                    &mut self.settings.show_tuning,
+                    ui_events,
                 );
             }
             if self.settings.show_vfx {
@@ -127,9 +217,10 @@ impl Gui {
             if self.settings.show_sequencers {
                 gui_sequencer::draw(
                     egui_ctx,
-                    sequencers,
-                    &mut self.settings.selected,
-                    &mut self.settings.show_sequencers,
+                    &mut audio_engine.sequencers,
+                    snapshot,
+                    &mut self.settings,
+                    ui_events,
                 );
             }
         });
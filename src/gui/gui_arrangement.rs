@@ -0,0 +1,82 @@
+use crate::audio::{
+    arrangement::{pattern_name, Section, MAX_REPEATS},
+    audio_engine::{section_bars, AudioEngine},
+    audio_thread::Snapshot,
+};
+use egui::{Color32, RichText};
+
+pub fn draw(
+    ctx: &egui::Context,
+    audio_engine: &mut AudioEngine,
+    snapshot: &Snapshot,
+    is_open: &mut bool,
+) {
+    egui::Window::new("arrangement")
+        .open(is_open)
+        .show(ctx, |ui| {
+            ctx.request_repaint();
+            ui.checkbox(&mut audio_engine.arrangement.active, "follow arrangement")
+                .on_hover_text("Patterns switch on bar boundaries as the sections play");
+
+            let current_section = snapshot.current_section;
+            let track_count = audio_engine.sequencers.len();
+            let mut move_up = None;
+            let mut remove = None;
+            egui::Grid::new("sections").striped(true).show(ui, |ui| {
+                ui.label("");
+                for track in 0..track_count {
+                    ui.label(format!("{}", track + 1));
+                }
+                ui.label("repeats");
+                ui.label("bars");
+                ui.end_row();
+
+                let sequencers = &audio_engine.sequencers;
+                let sections = &mut audio_engine.arrangement.sections;
+                for (index, section) in sections.iter_mut().enumerate() {
+                    let mut label = RichText::new(format!("{}", index + 1));
+                    if current_section == Some(index) {
+                        label = label.color(Color32::GREEN);
+                    }
+                    ui.label(label);
+                    for (track, sequencer) in sequencers.iter().enumerate() {
+                        let pattern_count = sequencer.patterns().len();
+                        let pattern = &mut section.patterns[track];
+                        egui::ComboBox::from_id_source(("section_pattern", index, track))
+                            .width(30.0)
+                            .selected_text(pattern_name(*pattern).to_string())
+                            .show_ui(ui, |ui| {
+                                for i in 0..pattern_count {
+                                    ui.selectable_value(pattern, i, pattern_name(i).to_string());
+                                }
+                            });
+                    }
+                    ui.add(egui::DragValue::new(&mut section.repeats).clamp_range(1..=MAX_REPEATS));
+                    ui.label(format!("{}", section_bars(section, sequencers)));
+                    if ui.button("⏶").clicked() && index > 0 {
+                        move_up = Some(index);
+                    }
+                    if ui.button("x").clicked() {
+                        remove = Some(index);
+                    }
+                    ui.end_row();
+                }
+            });
+
+            let sections = &mut audio_engine.arrangement.sections;
+            if let Some(index) = move_up {
+                sections.swap(index - 1, index);
+            }
+            if let Some(index) = remove {
+                sections.remove(index);
+            }
+            if ui.button("add section").clicked() {
+                // New sections start with whatever the last one plays
+                let section = sections
+                    .last()
+                    .cloned()
+                    .unwrap_or_else(|| Section::new(track_count));
+                sections.push(section);
+            }
+        });
+}
@@ -0,0 +1,85 @@
+use crate::audio::effects::{delay, AudioEffect, EffectChain};
+
+pub fn draw(
+    ctx: &egui::Context,
+    track_effects: Option<&mut EffectChain>,
+    selected: usize,
+    master_effects: &mut EffectChain,
+    is_open: &mut bool,
+) {
+    egui::Window::new("effects").open(is_open).show(ctx, |ui| {
+        ctx.request_repaint();
+
+        if let Some(track_effects) = track_effects {
+            ui.label(format!("track {}", selected));
+            draw_chain(ui, "track", track_effects);
+            ui.separator();
+        }
+        ui.label("master");
+        draw_chain(ui, "master", master_effects);
+    });
+}
+
+fn draw_chain(ui: &mut egui::Ui, id: &str, chain: &mut EffectChain) {
+    let mut swap_pair: Option<(usize, usize)> = None;
+    let length = chain.slots.len();
+    for (index, slot) in chain.slots.iter_mut().enumerate() {
+        ui.horizontal(|ui| {
+            if ui.button("⏶").clicked() && index > 0 {
+                swap_pair = Some((index, index - 1));
+            }
+            if ui.button("⏷").clicked() && index < length - 1 {
+                swap_pair = Some((index, index + 1));
+            }
+            ui.checkbox(&mut slot.active, slot.effect.name());
+        });
+        if slot.active {
+            ui.push_id((id, index), |ui| {
+                ui.indent("parameters", |ui| draw_parameters(ui, &mut slot.effect));
+            });
+        }
+    }
+    if let Some(sp) = swap_pair {
+        chain.slots.swap(sp.0, sp.1);
+    }
+}
+
+fn draw_parameters(ui: &mut egui::Ui, effect: &mut AudioEffect) {
+    match effect {
+        AudioEffect::Distortion(e) => {
+            slider(ui, "drive: ", &mut e.drive, 1.0..=20.0);
+            slider(ui, "mix: ", &mut e.mix, 0.0..=1.0);
+        }
+        AudioEffect::Bitcrusher(e) => {
+            ui.horizontal(|ui| {
+                ui.label("bits: ");
+                ui.add(egui::Slider::new(&mut e.bits, 1..=16));
+            });
+            ui.horizontal(|ui| {
+                ui.label("downsample: ");
+                ui.add(egui::Slider::new(&mut e.downsample, 1..=32));
+            });
+            slider(ui, "mix: ", &mut e.mix, 0.0..=1.0);
+        }
+        AudioEffect::Delay(e) => {
+            ui.horizontal(|ui| {
+                ui.label("steps: ");
+                ui.add(egui::Slider::new(&mut e.steps, 1..=delay::MAX_STEPS));
+            });
+            slider(ui, "feedback: ", &mut e.feedback, 0.0..=0.95);
+            slider(ui, "mix: ", &mut e.mix, 0.0..=1.0);
+        }
+        AudioEffect::Reverb(e) => {
+            slider(ui, "room size: ", &mut e.room_size, 0.0..=1.0);
+            slider(ui, "damping: ", &mut e.damping, 0.0..=1.0);
+            slider(ui, "mix: ", &mut e.mix, 0.0..=1.0);
+        }
+    }
+}
+
+fn slider(ui: &mut egui::Ui, label: &str, value: &mut f32, range: std::ops::RangeInclusive<f32>) {
+    ui.horizontal(|ui| {
+        ui.label(label);
+        ui.add(egui::Slider::new(value, range));
+    });
+}
@@ -0,0 +1,71 @@
+use crate::{
+    app::UiEvent,
+    audio::{
+        audio_engine::AudioEngine, audio_model::InputStatus, audio_thread::Snapshot,
+        mixer::ChannelStrip,
+    },
+};
+
+pub fn draw(
+    ctx: &egui::Context,
+    audio_engine: &mut AudioEngine,
+    snapshot: &Snapshot,
+    input_status: InputStatus,
+    is_open: &mut bool,
+    ui_events: &mut Vec<UiEvent>,
+) {
+    egui::Window::new("mixer").open(is_open).show(ctx, |ui| {
+        ctx.request_repaint();
+
+        ui.horizontal(|ui| {
+            for (index, sequencer) in audio_engine.sequencers.iter_mut().enumerate() {
+                ui.vertical(|ui| {
+                    ui.label(format!("{}", index));
+                    draw_strip(ui, &mut sequencer.strip, 0.0..=1.0);
+                });
+                ui.separator();
+            }
+            ui.vertical(|ui| {
+                ui.label("input");
+                draw_strip(ui, &mut audio_engine.input.strip, 0.0..=2.0);
+                ui.add(egui::ProgressBar::new(snapshot.input_level.min(1.0)).desired_width(60.0));
+                if ui.selectable_label(input_status.is_open, "on").clicked() {
+                    ui_events.push(UiEvent::ToggleInput);
+                }
+                if ui
+                    .selectable_label(input_status.is_analysed, "visuals")
+                    .on_hover_text("visuals follow the input instead of the mix")
+                    .clicked()
+                {
+                    ui_events.push(UiEvent::AnalyseInput(!input_status.is_analysed));
+                }
+                if ui
+                    .selectable_label(input_status.is_recording, "rec")
+                    .on_hover_text("records the input to input.wav")
+                    .clicked()
+                {
+                    ui_events.push(UiEvent::ToggleRecording);
+                }
+            });
+            ui.separator();
+            ui.vertical(|ui| {
+                ui.label("master");
+                ui.add(egui::Slider::new(&mut audio_engine.master_gain, 0.0..=2.0).vertical());
+            });
+        });
+    });
+}
+
+fn draw_strip(ui: &mut egui::Ui, strip: &mut ChannelStrip, gain: std::ops::RangeInclusive<f32>) {
+    ui.add(egui::Slider::new(&mut strip.gain, gain).vertical());
+    ui.add(
+        egui::DragValue::new(&mut strip.pan)
+            .clamp_range(-1.0..=1.0)
+            .speed(0.01)
+            .prefix("pan: "),
+    );
+    ui.horizontal(|ui| {
+        ui.toggle_value(&mut strip.mute, "M");
+        ui.toggle_value(&mut strip.solo, "S");
+    });
+}
@@ -1,11 +1,46 @@
-use crate::audio::sequencer::Sequencer;
+use crate::{
+    app::UiEvent,
+    audio::{
+        audio_thread::TrackSnapshot,
+        envelope::Envelope,
+        filter::{FilterType, MAX_CUTOFF, MAX_ENVELOPE_AMOUNT, MIN_CUTOFF},
+        fm_oscillator::{MAX_INDEX, MAX_RATIO, MIN_RATIO},
+        modulated_oscillator::ModulatedOscillator,
+        modulated_oscillator::OscillatorMode,
+        modulation::{ModDestination, ModMatrix, ModRoute, ModSource, MAX_ROUTES},
+        sampler::MAX_CHOKE_GROUP,
+        sequencer::{Sequencer, VoiceType},
+        voice::{VoiceAllocator, MAX_DETUNE, MAX_POLYPHONY, MAX_UNISON},
+    },
+};
 use kopek::{noise_generator::NoiseType, oscillator::WaveType};
 
-pub fn draw(ctx: &egui::Context, sequencer: &mut Sequencer, is_open: &mut bool) {
+pub fn draw(
+    ctx: &egui::Context,
+    sequencer: &mut Sequencer,
+    playing: TrackSnapshot,
+    track: usize,
+    paths: &mut FilePaths,
+    is_open: &mut bool,
+    ui_events: &mut Vec<UiEvent>,
+) {
     egui::Window::new("oscillator")
         .open(is_open)
         .show(ctx, |ui| {
             ctx.request_repaint();
+            egui::ComboBox::from_label("voice")
+                .selected_text(format!("{:?}", sequencer.voice_type))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut sequencer.voice_type, VoiceType::Synth, "synth");
+                    ui.selectable_value(&mut sequencer.voice_type, VoiceType::Sampler, "sampler");
+                });
+            match sequencer.voice_type {
+                VoiceType::Synth => draw_voices(ui, &mut sequencer.voices, playing.active_voices),
+                VoiceType::Sampler => {
+                    draw_sampler(ui, sequencer, track, &mut paths.sample, ui_events)
+                }
+            }
+            ui.separator();
             // ui.label(format!("Running: {}", sequencer.is_running));
             let mut wave_volume = sequencer.volume();
             ui.horizontal(|ui| {
@@ -16,12 +51,27 @@ pub fn draw(ctx: &egui::Context, sequencer: &mut Sequencer, is_open: &mut bool)
             // volume
             // vco freq
             {
-                let mut vco_frequency = sequencer.frequency();
-                ui.horizontal(|ui| {
-                    ui.label("vco: ");
-                    ui.add(egui::widgets::Slider::new(&mut vco_frequency, 0.0..=800.0));
-                });
-                sequencer.set_frequency(vco_frequency);
+                let oscillator = &mut sequencer.modulated_oscillator;
+                egui::ComboBox::from_label("oscillator")
+                    .selected_text(format!("{:?}", oscillator.mode))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut oscillator.mode, OscillatorMode::Vco, "vco");
+                        ui.selectable_value(&mut oscillator.mode, OscillatorMode::Fm, "fm");
+                        ui.selectable_value(
+                            &mut oscillator.mode,
+                            OscillatorMode::Wavetable,
+                            "wavetable",
+                        );
+                    });
+                match oscillator.mode {
+                    OscillatorMode::Vco => {}
+                    OscillatorMode::Fm => draw_fm(ui, oscillator),
+                    OscillatorMode::Wavetable => {
+                        draw_wavetable(ui, oscillator, track, &mut paths.wavetable, ui_events)
+                    }
+                }
+
+                ui.label(format!("vco: {:.1} Hz", playing.frequency));
                 let mut selected_wave: WaveType = sequencer.vco_wave_type();
                 egui::ComboBox::from_label("vco wave")
                     .selected_text(format!("{:?}", selected_wave))
@@ -42,12 +92,25 @@ pub fn draw(ctx: &egui::Context, sequencer: &mut Sequencer, is_open: &mut bool)
             }
             // lfo freq
             {
-                let mut lfo_frequency = sequencer.lfo_frequency();
+                let mut is_synced = sequencer.modulated_oscillator.lfo_sync.is_some();
                 ui.horizontal(|ui| {
                     ui.label("lfo: ");
-                    ui.add(egui::widgets::Slider::new(&mut lfo_frequency, 0.0..=20.0));
+                    if let Some(steps) = &mut sequencer.modulated_oscillator.lfo_sync {
+                        ui.add(
+                            egui::DragValue::new(steps)
+                                .clamp_range(1..=64)
+                                .suffix(" steps"),
+                        );
+                    } else {
+                        let mut lfo_frequency = sequencer.lfo_frequency();
+                        ui.add(egui::widgets::Slider::new(&mut lfo_frequency, 0.0..=20.0));
+                        sequencer.set_lfo_frequency(lfo_frequency);
+                    }
+                    ui.checkbox(&mut is_synced, "sync");
                 });
-                sequencer.set_lfo_frequency(lfo_frequency);
+                if is_synced != sequencer.modulated_oscillator.lfo_sync.is_some() {
+                    sequencer.modulated_oscillator.lfo_sync = is_synced.then_some(4);
+                }
                 let mut selected_wave: WaveType = sequencer.lfo_wave_type();
                 egui::ComboBox::from_label("lfo wave")
                     .selected_text(format!("{:?}", selected_wave))
@@ -89,30 +152,245 @@ pub fn draw(ctx: &egui::Context, sequencer: &mut Sequencer, is_open: &mut bool)
 
             ui.separator();
 
-            ui.horizontal(|ui| {
-                ui.label("attack: ");
-                ui.add(egui::Slider::new(&mut sequencer.envelope.attack, 0.0..=0.5));
-            });
-            ui.horizontal(|ui| {
-                ui.label("decay: ");
-                ui.add(egui::Slider::new(&mut sequencer.envelope.decay, 0.0..=0.5));
-            });
-            ui.horizontal(|ui| {
-                ui.label("sustain: ");
-                ui.add(egui::Slider::new(
-                    &mut sequencer.envelope.sustain,
-                    0.0..=0.5,
-                ));
-            });
-            ui.horizontal(|ui| {
-                ui.label("release: ");
-                ui.add(egui::Slider::new(
-                    &mut sequencer.envelope.release,
-                    0.0..=0.5,
-                ));
-            });
+            draw_envelope(ui, &mut sequencer.envelope);
+
+            ui.separator();
+
+            // filter
+            {
+                let filter = &mut sequencer.filter;
+                egui::ComboBox::from_label("filter")
+                    .selected_text(format!("{:?}", filter.filter_type))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(
+                            &mut filter.filter_type,
+                            FilterType::LowPass,
+                            "low pass",
+                        );
+                        ui.selectable_value(
+                            &mut filter.filter_type,
+                            FilterType::HighPass,
+                            "high pass",
+                        );
+                        ui.selectable_value(
+                            &mut filter.filter_type,
+                            FilterType::BandPass,
+                            "band pass",
+                        );
+                    });
+                ui.horizontal(|ui| {
+                    ui.label("cutoff: ");
+                    ui.add(
+                        egui::Slider::new(&mut filter.cutoff, MIN_CUTOFF..=MAX_CUTOFF)
+                            .logarithmic(true),
+                    );
+                });
+                ui.horizontal(|ui| {
+                    ui.label("resonance: ");
+                    ui.add(egui::Slider::new(&mut filter.resonance, 0.0..=1.0));
+                });
+                ui.horizontal(|ui| {
+                    ui.label("env amount: ");
+                    ui.add(egui::Slider::new(
+                        &mut filter.envelope_amount,
+                        -MAX_ENVELOPE_AMOUNT..=MAX_ENVELOPE_AMOUNT,
+                    ));
+                });
+            }
+            ui.label("filter envelope");
+            draw_envelope(ui, &mut sequencer.filter_envelope);
+
+            ui.separator();
+
+            ui.label("modulation");
+            draw_modulation(ui, &mut sequencer.modulation);
             if ui.button("play").clicked() {
                 println!("try envelope");
             }
         });
 }
+
+/// Files typed into the oscillator window, loaded through `UiEvent`s
+pub struct FilePaths {
+    pub sample: String,
+    pub wavetable: String,
+    pub scale: String,
+}
+
+impl Default for FilePaths {
+    fn default() -> Self {
+        Self {
+            sample: String::from("kick.wav"),
+            wavetable: String::from("wavetable.wav"),
+            scale: String::from("scale.scl"),
+        }
+    }
+}
+
+fn draw_voices(ui: &mut egui::Ui, voices: &mut VoiceAllocator, active_voices: usize) {
+    ui.horizontal(|ui| {
+        ui.label("polyphony: ");
+        ui.add(egui::DragValue::new(&mut voices.polyphony).clamp_range(1..=MAX_POLYPHONY));
+        ui.label(format!("{active_voices} playing"));
+    });
+    ui.horizontal(|ui| {
+        ui.label("unison: ");
+        ui.add(egui::DragValue::new(&mut voices.unison).clamp_range(1..=MAX_UNISON))
+            .on_hover_text("Detuned copies of every voice");
+    });
+    if voices.unison > 1 {
+        ui.horizontal(|ui| {
+            ui.label("detune: ");
+            ui.add(egui::Slider::new(&mut voices.detune, 0.0..=MAX_DETUNE).suffix(" ct"));
+        });
+        ui.horizontal(|ui| {
+            ui.label("spread: ");
+            ui.add(egui::Slider::new(&mut voices.spread, 0.0..=1.0));
+        });
+    }
+}
+
+fn draw_fm(ui: &mut egui::Ui, oscillator: &mut ModulatedOscillator) {
+    let fm = &mut oscillator.fm;
+    ui.horizontal(|ui| {
+        ui.label("ratio: ");
+        ui.add(egui::Slider::new(&mut fm.ratio, MIN_RATIO..=MAX_RATIO).logarithmic(true));
+    });
+    ui.horizontal(|ui| {
+        ui.label("index: ");
+        ui.add(egui::Slider::new(&mut fm.index, 0.0..=MAX_INDEX));
+    });
+    ui.horizontal(|ui| {
+        ui.label("feedback: ");
+        ui.add(egui::Slider::new(&mut fm.feedback, 0.0..=1.0));
+    });
+}
+
+fn draw_wavetable(
+    ui: &mut egui::Ui,
+    oscillator: &mut ModulatedOscillator,
+    track: usize,
+    wavetable_path: &mut String,
+    ui_events: &mut Vec<UiEvent>,
+) {
+    let wavetable = &mut oscillator.wavetable;
+    let name = wavetable.table.path.as_deref().unwrap_or("built-in");
+    ui.label(format!("{name} ({} frames)", wavetable.table.frame_count()));
+    ui.horizontal(|ui| {
+        ui.label("file: ");
+        ui.text_edit_singleline(wavetable_path);
+        if ui.button("load").clicked() {
+            ui_events.push(UiEvent::LoadWavetable {
+                path: wavetable_path.clone(),
+                track,
+            });
+        }
+    });
+    ui.horizontal(|ui| {
+        ui.label("position: ");
+        ui.add(egui::Slider::new(&mut wavetable.position, 0.0..=1.0));
+    });
+}
+
+/// Step notes transpose the sample from C4, step offsets move its start
+fn draw_sampler(
+    ui: &mut egui::Ui,
+    sequencer: &mut Sequencer,
+    track: usize,
+    sample_path: &mut String,
+    ui_events: &mut Vec<UiEvent>,
+) {
+    let sampler = &mut sequencer.sampler;
+    match &sampler.sample {
+        Some(sample) => ui.label(format!("{} ({:.2}s)", sample.path, sample.seconds())),
+        None => ui.label("no sample"),
+    };
+    ui.horizontal(|ui| {
+        ui.label("file: ");
+        ui.text_edit_singleline(sample_path);
+        if ui.button("load").clicked() {
+            ui_events.push(UiEvent::LoadSample {
+                path: sample_path.clone(),
+                track,
+            });
+        }
+    });
+    let mut choke_group = sampler.choke_group.unwrap_or(0);
+    ui.horizontal(|ui| {
+        ui.label("choke group: ");
+        ui.add(egui::DragValue::new(&mut choke_group).clamp_range(0..=MAX_CHOKE_GROUP))
+            .on_hover_text("0 is none, samplers in the same group cut each other off");
+    });
+    sampler.choke_group = (choke_group > 0).then_some(choke_group);
+}
+
+fn draw_envelope(ui: &mut egui::Ui, envelope: &mut Envelope) {
+    ui.horizontal(|ui| {
+        ui.label("peak: ");
+        ui.add(egui::Slider::new(&mut envelope.peak_level, 0.0..=1.0));
+    });
+    ui.horizontal(|ui| {
+        ui.label("attack: ");
+        ui.add(egui::Slider::new(&mut envelope.attack, 0.0..=0.5));
+    });
+    ui.horizontal(|ui| {
+        ui.label("decay: ");
+        ui.add(egui::Slider::new(&mut envelope.decay, 0.0..=0.5));
+    });
+    ui.horizontal(|ui| {
+        ui.label("sustain: ");
+        ui.add(egui::Slider::new(&mut envelope.sustain_level, 0.0..=1.0));
+    });
+    ui.horizontal(|ui| {
+        ui.label("release: ");
+        ui.add(egui::Slider::new(&mut envelope.release, 0.0..=0.5));
+    });
+}
+
+fn draw_modulation(ui: &mut egui::Ui, modulation: &mut ModMatrix) {
+    let mut removed = None;
+    for (index, route) in modulation.routes.iter_mut().enumerate() {
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_source(("mod source", index))
+                .selected_text(format!("{:?}", route.source))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut route.source, ModSource::Lfo, "lfo");
+                    ui.selectable_value(&mut route.source, ModSource::Envelope, "envelope");
+                    ui.selectable_value(
+                        &mut route.source,
+                        ModSource::FilterEnvelope,
+                        "filter envelope",
+                    );
+                });
+            ui.label("→");
+            egui::ComboBox::from_id_source(("mod destination", index))
+                .selected_text(format!("{:?}", route.destination))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut route.destination, ModDestination::Pitch, "pitch");
+                    ui.selectable_value(&mut route.destination, ModDestination::Volume, "volume");
+                    ui.selectable_value(
+                        &mut route.destination,
+                        ModDestination::NoiseMix,
+                        "noise mix",
+                    );
+                    ui.selectable_value(&mut route.destination, ModDestination::Cutoff, "cutoff");
+                    ui.selectable_value(&mut route.destination, ModDestination::Pan, "pan");
+                });
+            let max_depth = route.destination.max_depth();
+            ui.add(egui::Slider::new(&mut route.depth, -max_depth..=max_depth));
+            if ui.button("x").clicked() {
+                removed = Some(index);
+            }
+        });
+    }
+    if let Some(index) = removed {
+        modulation.routes.remove(index);
+    }
+    if modulation.routes.len() < MAX_ROUTES && ui.button("+").clicked() {
+        modulation.routes.push(ModRoute {
+            source: ModSource::Lfo,
+            destination: ModDestination::Pitch,
+            depth: 0.0,
+        });
+    }
+}
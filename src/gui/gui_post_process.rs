@@ -18,6 +18,7 @@ pub fn draw(
         .show(ctx, |ui| {
             ctx.request_repaint();
 
+            ui.weak("the master bus follows the same effects");
             let mut swap_pair: Option<(usize, usize)> = None;
             let length = effect_to_active.len();
             for (index, (effect, active)) in effect_to_active.iter_mut().enumerate() {
@@ -1,13 +1,34 @@
-use crate::audio::sequencer::Sequencer;
-use egui::Color32;
-use kopek::utils;
+use super::Settings;
+use crate::{
+    app::UiEvent,
+    audio::{
+        arpeggiator::{ArpMode, Arpeggiator, ChordType, RATES},
+        arrangement::{self, pattern_name},
+        audio_thread::{Snapshot, TrackSnapshot},
+        midi::MidiSource,
+        pattern_generator::{self, RandomWalk, Scale, MAX_LEAP, MAX_OCTAVES},
+        sequencer::{self, Sequencer},
+        songs,
+        step::Step,
+    },
+};
+use egui::{Color32, RichText};
+use kopek::utils::{self, Key};
 
 pub fn draw(
     ctx: &egui::Context,
     sequencers: &mut Vec<Sequencer>,
-    selected: &mut usize,
-    is_open: &mut bool,
+    snapshot: &Snapshot,
+    settings: &mut Settings,
+    ui_events: &mut Vec<UiEvent>,
 ) {
+    let Settings {
+        selected,
+        selected_pattern,
+        show_sequencers: is_open,
+        pattern_tools: tools,
+        ..
+    } = settings;
     egui::Window::new("sequencers")
         .open(is_open)
         .show(ctx, |ui| {
@@ -24,31 +45,388 @@ pub fn draw(
                         *selected = i;
                     }
                 }
+                if ui.button("+").clicked() {
+                    ui_events.push(UiEvent::AddTrack);
+                }
+                if ui.button("-").clicked() && sequencers.len() > 1 {
+                    ui_events.push(UiEvent::RemoveTrack(*selected));
+                }
             });
-            ui.colored_label(Color32::RED, format!("Sequencer {}", (*selected + 1)));
-            let selected = *selected;
-            let count = sequencers[selected].sequence.len();
+            if sequencers.is_empty() {
+                return;
+            }
+            let sequencer = &mut sequencers[*selected];
+            // Patterns can be removed while one of them is selected
+            *selected_pattern = (*selected_pattern).min(sequencer.patterns().len() - 1);
             ui.horizontal(|ui| {
-                for i in 0..count {
+                ui.colored_label(Color32::RED, format!("Sequencer {}", (*selected + 1)));
+                let mut length = sequencer.length(*selected_pattern);
+                ui.label("steps: ");
+                ui.add(egui::DragValue::new(&mut length).clamp_range(1..=sequencer::MAX_LENGTH));
+                if length != sequencer.length(*selected_pattern) {
+                    sequencer.set_length(*selected_pattern, length);
+                }
+            });
+            draw_patterns(
+                ui,
+                sequencer,
+                snapshot.track(*selected),
+                *selected,
+                selected_pattern,
+                ui_events,
+            );
+            draw_arpeggiator(ui, &mut sequencer.arpeggiator);
+            ui.collapsing("midi import", |ui| {
+                draw_midi_import(
+                    ui,
+                    &mut tools.midi_import,
+                    *selected,
+                    *selected_pattern,
+                    ui_events,
+                )
+            });
+            let Some(sequence) = sequencer.pattern_mut(*selected_pattern) else {
+                return;
+            };
+            ui.collapsing("generate", |ui| {
+                draw_generator(ui, &mut tools.generator, sequence)
+            });
+            ui.horizontal_wrapped(|ui| {
+                for step in sequence.iter_mut() {
                     ui.vertical(|ui| {
-                        ui.menu_button(sequencers[selected].sequence[i].octave.to_string(), |ui| {
+                        ui.menu_button(step.note.octave.to_string(), |ui| {
                             for (label, value) in utils::OCTAVES {
                                 if ui.button(label).clicked() {
-                                    sequencers[selected].sequence[i].octave = value;
+                                    step.note.octave = value;
                                     ui.close_menu();
                                 }
                             }
                         });
-                        ui.menu_button(sequencers[selected].sequence[i].key.to_string(), |ui| {
+                        ui.menu_button(step.note.key.to_string(), |ui| {
                             for (label, value) in utils::KEYS {
                                 if ui.button(label).clicked() {
-                                    sequencers[selected].sequence[i].key = value;
+                                    step.note.key = value;
                                     ui.close_menu();
                                 }
                             }
                         });
+                        ui.menu_button(step.chord.map_or("-", ChordType::name), |ui| {
+                            if ui.button("none").clicked() {
+                                step.chord = None;
+                                ui.close_menu();
+                            }
+                            for chord in ChordType::ALL {
+                                if ui.button(chord.name()).clicked() {
+                                    step.chord = Some(chord);
+                                    ui.close_menu();
+                                }
+                            }
+                        })
+                        .response
+                        .on_hover_text("chord");
+                        ui.add(
+                            egui::DragValue::new(&mut step.velocity)
+                                .speed(0.01)
+                                .clamp_range(0.0..=1.0),
+                        )
+                        .on_hover_text("velocity");
+                        ui.add(
+                            egui::DragValue::new(&mut step.gate)
+                                .speed(0.01)
+                                .clamp_range(0.05..=1.0),
+                        )
+                        .on_hover_text("gate");
+                        ui.add(
+                            egui::DragValue::new(&mut step.probability)
+                                .speed(0.01)
+                                .clamp_range(0.0..=1.0),
+                        )
+                        .on_hover_text("probability");
+                        ui.add(
+                            egui::DragValue::new(&mut step.offset)
+                                .speed(0.01)
+                                .clamp_range(0.0..=1.0),
+                        )
+                        .on_hover_text("sample offset");
+                        ui.toggle_value(&mut step.slide, "slide");
                     });
                 }
             });
         });
 }
+
+/// Chord steps are arpeggiated unless the mode is off
+fn draw_arpeggiator(ui: &mut egui::Ui, arpeggiator: &mut Arpeggiator) {
+    ui.horizontal(|ui| {
+        egui::ComboBox::from_label("arp")
+            .selected_text(format!("{:?}", arpeggiator.mode))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut arpeggiator.mode, ArpMode::Off, "off");
+                ui.selectable_value(&mut arpeggiator.mode, ArpMode::Up, "up");
+                ui.selectable_value(&mut arpeggiator.mode, ArpMode::Down, "down");
+                ui.selectable_value(&mut arpeggiator.mode, ArpMode::UpDown, "up-down");
+                ui.selectable_value(&mut arpeggiator.mode, ArpMode::Random, "random");
+            });
+        let rate_label = RATES
+            .iter()
+            .find(|(rate, _)| *rate == arpeggiator.rate)
+            .map_or("?", |(_, label)| label);
+        egui::ComboBox::from_label("rate")
+            .selected_text(rate_label)
+            .show_ui(ui, |ui| {
+                for (rate, label) in RATES {
+                    ui.selectable_value(&mut arpeggiator.rate, rate, label);
+                }
+            });
+    });
+}
+
+/// Pattern tabs, the playing pattern is green and a queued one yellow
+fn draw_patterns(
+    ui: &mut egui::Ui,
+    sequencer: &mut Sequencer,
+    playing: TrackSnapshot,
+    track: usize,
+    selected_pattern: &mut usize,
+    ui_events: &mut Vec<UiEvent>,
+) {
+    ui.horizontal(|ui| {
+        ui.label("patterns: ");
+        for i in 0..sequencer.patterns().len() {
+            let mut text = RichText::new(pattern_name(i).to_string());
+            if i == playing.pattern_index {
+                text = text.color(Color32::GREEN);
+            } else if Some(i) == playing.queued_pattern {
+                text = text.color(Color32::YELLOW);
+            }
+            if ui.selectable_label(i == *selected_pattern, text).clicked() {
+                *selected_pattern = i;
+            }
+        }
+        if ui
+            .button("+")
+            .on_hover_text("Adds a copy of the selected pattern")
+            .clicked()
+        {
+            if let Some(pattern) = sequencer.duplicate_pattern(*selected_pattern) {
+                *selected_pattern = pattern;
+            }
+        }
+        if ui.button("-").clicked() && sequencer.patterns().len() > 1 {
+            ui_events.push(UiEvent::RemovePattern {
+                track,
+                pattern: *selected_pattern,
+            });
+        }
+        if ui
+            .button("play")
+            .on_hover_text("Switches to the selected pattern on the next bar")
+            .clicked()
+        {
+            ui_events.push(UiEvent::QueuePattern {
+                track,
+                pattern: *selected_pattern,
+            });
+        }
+        ui.menu_button("presets", |ui| {
+            for (preset, name) in songs::PRESETS.iter().enumerate() {
+                if ui
+                    .button(*name)
+                    .on_hover_text("Replaces the patterns of this sequencer")
+                    .clicked()
+                {
+                    ui_events.push(UiEvent::LoadPreset { preset, track });
+                    ui.close_menu();
+                }
+            }
+        });
+    });
+    if sequencer.patterns().len() >= arrangement::MAX_PATTERNS {
+        ui.label("all patterns are in use");
+    }
+}
+
+/// Settings of the tools that fill the selected pattern
+#[derive(Default)]
+pub struct PatternTools {
+    pub midi_import: MidiImportSettings,
+    pub generator: GeneratorSettings,
+}
+
+pub struct MidiImportSettings {
+    pub path: String,
+    pub source: MidiSource,
+    /// Outcome of the last import, shown until the next one
+    pub report: Option<ImportReport>,
+}
+
+pub struct ImportReport {
+    pub summary: String,
+    /// Tempo of the file, the transport keeps its own until it's applied
+    pub bpm: Option<f32>,
+    pub issues: Vec<String>,
+}
+
+impl Default for MidiImportSettings {
+    fn default() -> Self {
+        Self {
+            path: String::from("song.mid"),
+            source: MidiSource::Channel(0),
+            report: None,
+        }
+    }
+}
+
+fn draw_midi_import(
+    ui: &mut egui::Ui,
+    midi_import: &mut MidiImportSettings,
+    selected: usize,
+    selected_pattern: usize,
+    ui_events: &mut Vec<UiEvent>,
+) {
+    ui.horizontal(|ui| {
+        ui.label("file: ");
+        ui.text_edit_singleline(&mut midi_import.path);
+    });
+    ui.horizontal(|ui| {
+        let is_track = matches!(midi_import.source, MidiSource::Track(_));
+        if ui.radio(is_track, "track").clicked() && !is_track {
+            midi_import.source = MidiSource::Track(0);
+        }
+        if ui.radio(!is_track, "channel").clicked() && is_track {
+            midi_import.source = MidiSource::Channel(0);
+        }
+        match &mut midi_import.source {
+            MidiSource::Track(track) => {
+                ui.add(egui::DragValue::new(track).clamp_range(0..=255));
+            }
+            MidiSource::Channel(channel) => {
+                ui.add(egui::DragValue::new(channel).clamp_range(0..=15));
+            }
+        }
+    });
+    if ui
+        .button("import")
+        .on_hover_text("Replaces the steps of the selected pattern")
+        .clicked()
+    {
+        ui_events.push(UiEvent::ImportMidi {
+            path: midi_import.path.clone(),
+            source: midi_import.source,
+            track: selected,
+            pattern: selected_pattern,
+        });
+    }
+    let Some(report) = &midi_import.report else {
+        return;
+    };
+    ui.label(&report.summary);
+    if let Some(bpm) = report.bpm {
+        ui.horizontal(|ui| {
+            ui.label(format!("the file's tempo is {bpm} bpm"));
+            if ui.button("use it").clicked() {
+                ui_events.push(UiEvent::SetBpm(bpm));
+            }
+        });
+    }
+    if !report.issues.is_empty() {
+        ui.collapsing(format!("{} issues", report.issues.len()), |ui| {
+            egui::ScrollArea::vertical()
+                .max_height(100.0)
+                .show(ui, |ui| {
+                    for issue in &report.issues {
+                        ui.label(issue);
+                    }
+                });
+        });
+    }
+}
+
+/// Kept between uses so a seed that gave a good pattern can be generated again
+pub struct GeneratorSettings {
+    pub hits: usize,
+    pub steps: usize,
+    pub rotation: usize,
+    pub walk: RandomWalk,
+}
+
+impl Default for GeneratorSettings {
+    fn default() -> Self {
+        Self {
+            hits: 5,
+            steps: 16,
+            rotation: 0,
+            walk: RandomWalk::new(0),
+        }
+    }
+}
+
+fn draw_generator(ui: &mut egui::Ui, generator: &mut GeneratorSettings, pattern: &mut Vec<Step>) {
+    ui.horizontal(|ui| {
+        ui.label("hits: ");
+        ui.add(egui::DragValue::new(&mut generator.hits).clamp_range(0..=generator.steps));
+        ui.label("steps: ");
+        ui.add(egui::DragValue::new(&mut generator.steps).clamp_range(1..=sequencer::MAX_LENGTH));
+        ui.label("rotation: ");
+        ui.add(egui::DragValue::new(&mut generator.rotation).clamp_range(0..=generator.steps - 1));
+        if ui
+            .button("rhythm")
+            .on_hover_text("Euclidean rhythm, steps on hits keep their notes")
+            .clicked()
+        {
+            let rhythm =
+                pattern_generator::euclidean(generator.hits, generator.steps, generator.rotation);
+            pattern_generator::apply_rhythm(pattern, &rhythm);
+        }
+    });
+
+    let walk = &mut generator.walk;
+    ui.horizontal(|ui| {
+        ui.label("root: ");
+        ui.menu_button(walk.root.octave.to_string(), |ui| {
+            for (label, value) in utils::OCTAVES {
+                if ui.button(label).clicked() {
+                    walk.root.octave = value;
+                    ui.close_menu();
+                }
+            }
+        });
+        ui.menu_button(walk.root.key.to_string(), |ui| {
+            // A rest has no pitch to start from
+            for (label, value) in utils::KEYS.into_iter().filter(|(_, key)| *key != Key::Rest) {
+                if ui.button(label).clicked() {
+                    walk.root.key = value;
+                    ui.close_menu();
+                }
+            }
+        });
+        egui::ComboBox::from_label("scale")
+            .selected_text(walk.scale.name())
+            .show_ui(ui, |ui| {
+                for scale in Scale::ALL {
+                    ui.selectable_value(&mut walk.scale, scale, scale.name());
+                }
+            });
+    });
+    ui.horizontal(|ui| {
+        ui.label("octaves: ");
+        ui.add(egui::DragValue::new(&mut walk.octaves).clamp_range(1..=MAX_OCTAVES));
+        ui.label("leap: ");
+        ui.add(egui::DragValue::new(&mut walk.max_leap).clamp_range(1..=MAX_LEAP))
+            .on_hover_text("Largest move between notes, in scale degrees");
+    });
+    ui.horizontal(|ui| {
+        ui.label("seed: ");
+        ui.add(egui::DragValue::new(&mut walk.seed));
+        if ui.button("new seed").clicked() {
+            walk.seed = rand::random();
+        }
+        if ui
+            .button("melody")
+            .on_hover_text("Random walk over the steps that play")
+            .clicked()
+        {
+            pattern_generator::apply_melody(pattern, walk);
+        }
+    });
+}
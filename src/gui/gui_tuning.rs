@@ -0,0 +1,76 @@
+use crate::{
+    app::UiEvent,
+    audio::tuning::{
+        Tuning, TuningSystem, MAX_DIVISIONS, MAX_REFERENCE, MAX_ROOT, MAX_TRANSPOSE, MIN_REFERENCE,
+    },
+};
+
+pub fn draw(
+    ctx: &egui::Context,
+    tuning: &mut Tuning,
+    scale_path: &mut String,
+    is_open: &mut bool,
+    ui_events: &mut Vec<UiEvent>,
+) {
+    egui::Window::new("tuning").open(is_open).show(ctx, |ui| {
+        egui::ComboBox::from_label("system")
+            .selected_text(format!("{:?}", tuning.system))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut tuning.system, TuningSystem::Equal, "equal");
+                ui.selectable_value(&mut tuning.system, TuningSystem::Just, "just");
+                ui.selectable_value(&mut tuning.system, TuningSystem::Scala, "scala");
+            });
+        ui.add(
+            egui::DragValue::new(&mut tuning.reference)
+                .clamp_range(MIN_REFERENCE..=MAX_REFERENCE)
+                .speed(0.1)
+                .prefix("A4: ")
+                .suffix(" Hz"),
+        );
+        ui.add(
+            egui::DragValue::new(&mut tuning.transpose)
+                .clamp_range(-MAX_TRANSPOSE..=MAX_TRANSPOSE)
+                .prefix("transpose: "),
+        );
+        match tuning.system {
+            TuningSystem::Equal => {
+                ui.add(
+                    egui::DragValue::new(&mut tuning.divisions)
+                        .clamp_range(1..=MAX_DIVISIONS)
+                        .prefix("divisions: "),
+                );
+            }
+            TuningSystem::Just => draw_root(ui, tuning),
+            TuningSystem::Scala => {
+                draw_root(ui, tuning);
+                match &tuning.scale {
+                    Some(scale) => ui.label(format!(
+                        "{} ({} notes)",
+                        scale.description,
+                        scale.note_count()
+                    )),
+                    None => ui.label("no scale, standard pitch"),
+                };
+                ui.horizontal(|ui| {
+                    ui.label("file: ");
+                    ui.text_edit_singleline(scale_path);
+                    if ui.button("load").clicked() {
+                        ui_events.push(UiEvent::LoadScale {
+                            path: scale_path.clone(),
+                        });
+                    }
+                });
+            }
+        }
+    });
+}
+
+fn draw_root(ui: &mut egui::Ui, tuning: &mut Tuning) {
+    ui.add(
+        egui::DragValue::new(&mut tuning.root)
+            .clamp_range(0..=MAX_ROOT)
+            .prefix("root: ")
+            .suffix(" (midi)"),
+    )
+    .on_hover_text("the note the scale starts on, 60 is C4");
+}
@@ -1,9 +1,25 @@
 use super::Settings;
-use crate::app::UiEvent;
+use crate::{
+    app::UiEvent,
+    audio::{
+        audio_backend::{MAX_LATENCY_MS, MIN_LATENCY_MS},
+        audio_model::OutputStatus,
+        audio_thread::Snapshot,
+        transport::{Transport, TransportState, MAX_BPM, MIN_BPM},
+    },
+};
 use egui::{Color32, RichText};
 use egui_winit::egui::{self, Context};
 
-pub fn draw(ctx: &Context, settings: &mut Settings, ui_events: &mut Vec<UiEvent>, fps: f32) {
+pub fn draw(
+    ctx: &Context,
+    settings: &mut Settings,
+    ui_events: &mut Vec<UiEvent>,
+    fps: f32,
+    output_status: &OutputStatus,
+    transport: &mut Transport,
+    snapshot: &Snapshot,
+) {
     egui::TopBottomPanel::top("menubar_container").show(ctx, |ui| {
         egui::menu::bar(ui, |ui| {
             ui.label(
@@ -12,15 +28,32 @@ pub fn draw(ctx: &Context, settings: &mut Settings, ui_events: &mut Vec<UiEvent>
                     .color(Color32::YELLOW),
             );
             ui.label(RichText::new(format!("FPS: {0:.2}", fps)).color(Color32::GREEN));
+            draw_output_status(ui, ui_events, output_status);
             ui.menu_button("file", |ui| {
                 if ui.button("oscillator").clicked() {
                     settings.show_oscillator_inspector = true;
                     ui.close_menu();
                 }
+                if ui.button("effects").clicked() {
+                    settings.show_effects = true;
+                    ui.close_menu();
+                }
+                if ui.button("mixer").clicked() {
+                    settings.show_mixer = true;
+                    ui.close_menu();
+                }
                 if ui.button("sequencers").clicked() {
                     settings.show_sequencers = true;
                     ui.close_menu();
                 }
+                if ui.button("arrangement").clicked() {
+                    settings.show_arrangement = true;
+                    ui.close_menu();
+                }
+                if ui.button("tuning").clicked() {
+                    settings.show_tuning = true;
+                    ui.close_menu();
+                }
                 if ui.button("VFX").clicked() {
                     settings.show_vfx = true;
                     ui.close_menu();
@@ -39,7 +72,125 @@ pub fn draw(ctx: &Context, settings: &mut Settings, ui_events: &mut Vec<UiEvent>
                     ui_events.push(UiEvent::ClearSong);
                     ui.close_menu();
                 }
-            })
+                if ui.button("export wav").clicked() {
+                    ui_events.push(UiEvent::ExportSong);
+                    ui.close_menu();
+                }
+                if ui.button("export midi").clicked() {
+                    ui_events.push(UiEvent::ExportMidi);
+                    ui.close_menu();
+                }
+            });
+            ui.separator();
+            draw_transport(ui, ui_events, transport, snapshot);
         });
     });
 }
+
+/// Latency and underruns, the menu has the rest of the report and the latency target
+fn draw_output_status(ui: &mut egui::Ui, ui_events: &mut Vec<UiEvent>, status: &OutputStatus) {
+    let report = &status.report;
+    let color = if report.recent_underruns > 0 {
+        Color32::RED
+    } else {
+        Color32::GREEN
+    };
+    let title = RichText::new(format!(
+        "audio: {:.1} ms, {} underruns",
+        report.latency.as_secs_f64() * 1000.0,
+        report.underruns
+    ))
+    .color(color);
+    ui.menu_button(title, |ui| {
+        let ms = |duration: std::time::Duration| duration.as_secs_f64() * 1000.0;
+        ui.label(format!(
+            "buffered: {} frames, lowest {}",
+            report.buffered_frames, report.min_buffered_frames
+        ));
+        ui.label(format!("device buffer: {} frames", report.callback_frames));
+        ui.label(format!(
+            "device latency: {:.1} ms",
+            ms(report.device_latency)
+        ));
+        ui.label(format!(
+            "callback: {:.3} ms, slowest {:.3} ms ({:.0}%)",
+            ms(report.callback_time),
+            ms(report.max_callback_time),
+            report.load * 100.0
+        ));
+        ui.label(format!(
+            "underruns: {}, {} frames of silence",
+            report.underruns, report.missing_frames
+        ));
+        let mut latency = status.latency_target;
+        ui.add(
+            egui::DragValue::new(&mut latency)
+                .clamp_range(MIN_LATENCY_MS..=MAX_LATENCY_MS)
+                .prefix("target: ")
+                .suffix(" ms"),
+        );
+        if latency != status.latency_target {
+            ui_events.push(UiEvent::SetLatency(latency));
+        }
+    });
+}
+
+/// Tempo and swing are edited here, state and position come from the audio thread
+fn draw_transport(
+    ui: &mut egui::Ui,
+    ui_events: &mut Vec<UiEvent>,
+    transport: &mut Transport,
+    snapshot: &Snapshot,
+) {
+    let state = snapshot.transport_state;
+    if ui
+        .selectable_label(state == TransportState::Playing, "play")
+        .clicked()
+    {
+        ui_events.push(UiEvent::Play);
+    }
+    if ui
+        .selectable_label(state == TransportState::Paused, "pause")
+        .clicked()
+    {
+        ui_events.push(UiEvent::Pause);
+    }
+    if ui
+        .selectable_label(state == TransportState::Stopped, "stop")
+        .clicked()
+    {
+        ui_events.push(UiEvent::Stop);
+    }
+
+    let mut bpm = transport.bpm();
+    ui.add(
+        egui::DragValue::new(&mut bpm)
+            .clamp_range(MIN_BPM..=MAX_BPM)
+            .speed(0.5)
+            .prefix("bpm: "),
+    );
+    if bpm != transport.bpm() {
+        transport.set_bpm(bpm);
+    }
+    let mut swing = transport.swing();
+    ui.add(
+        egui::DragValue::new(&mut swing)
+            .clamp_range(0.0..=1.0)
+            .speed(0.01)
+            .prefix("swing: "),
+    );
+    if swing != transport.swing() {
+        transport.set_swing(swing);
+    }
+
+    let position = snapshot.position;
+    ui.label(
+        RichText::new(format!(
+            "{}.{}.{}",
+            position.bar + 1,
+            position.beat + 1,
+            position.step + 1
+        ))
+        .monospace(),
+    );
+}
@@ -1,6 +1,7 @@
 mod app;
 mod audio;
 mod basics;
+mod cli;
 mod color_utils;
 mod gui;
 mod material;
@@ -12,6 +13,27 @@ mod rendering_utils;
 mod save_image;
 mod shader_utils;
 
+use audio::offline_renderer;
+
 fn main() {
-    pollster::block_on(app::start());
+    let args = match cli::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}\n{}", cli::USAGE);
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(path) = &args.export_path {
+        match offline_renderer::export_song(path, &args.export_settings) {
+            Ok(()) => println!("Exported {} bars to {path}", args.export_settings.bars),
+            Err(e) => {
+                eprintln!("Error exporting song: {e}");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    pollster::block_on(app::start(&args.audio_settings));
 }
@@ -1,6 +1,6 @@
 use crate::{
     app::{Settings, UiEvent},
-    audio::sequencer::Sequencer,
+    audio::audio_model::AudioModel,
     basics::scene::Scene,
     color_utils::{self},
     gui::Gui,
@@ -88,7 +88,7 @@ impl<'a> Renderer<'a> {
         &mut self,
         window: &Window,
         scene: &Scene,
-        sequencers: &mut Vec<Sequencer>,
+        audio_model: &mut AudioModel,
         fps: f32,
         settings: &mut Settings,
         ui_events: &mut Vec<UiEvent>,
@@ -134,7 +134,7 @@ impl<'a> Renderer<'a> {
                 &self.render_texture_material.post_process_texture_view,
                 &self.device,
                 &self.queue,
-                sequencers,
+                audio_model,
                 fps,
                 ui_events,
                 &mut settings.effect_to_active,
@@ -10,6 +10,13 @@ struct Material {
     color2: vec4<f32>,
     color3: vec4<f32>,
     signal: f32,
+    onset: f32,
+    beat: f32,
+    on_beat: f32,
+    // 16 analyser bands, lowest first
+    bands: array<vec4<f32>, 4>,
+    // Levels of the first four tracks
+    track_levels: vec4<f32>,
 };
 @group(1) @binding(0) var<uniform> material: Material;
 
@@ -61,13 +68,26 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let middle = srgb_to_linear(in.color2.rgb);
     let top = srgb_to_linear(in.color3.rgb);
 
-    var base = vec3(0.1, 0.1, 0.1);
+    // The background pulses with detected beats
+    var base = vec3(0.1, 0.1, 0.1) * (1.0 + 2.0 * material.beat);
     var color = mix(middle, bottom, step(uv.y, 0.5));
     color = mix(top, color, step(uv.y, 0.75));
 
+    // One bar per band across the width, with a gap between bars
+    let band_count = 16.0;
+    let band = min(u32(uv.x * band_count), 15u);
+    let level = material.bands[band / 4u][band % 4u];
+    let gap = step(0.1, fract(uv.x * band_count));
+
     let step_size = 0.125;
-    let quantized_signal = floor(in.signal / step_size + 0.5) * step_size;
-    color = mix(base, color, step(uv.y, quantized_signal));
+    let quantized_level = floor(level / step_size + 0.5) * step_size;
+    color = mix(base, color * (1.0 + 0.5 * material.onset), step(uv.y, quantized_level) * gap);
+
+    // A strip of track meters along the top, brighter on the transport's beat
+    let track = min(u32(uv.x * 4.0), 3u);
+    let track_x = fract(uv.x * 4.0);
+    let meter = step(0.95, uv.y) * step(track_x, material.track_levels[track]) * step(0.02, track_x);
+    color = mix(color, top * (1.0 + 0.5 * material.on_beat), meter);
 
     return vec4<f32>(color, 1.0);
 }
\ No newline at end of file